@@ -0,0 +1,283 @@
+/*
+In-memory cache of instance API responses, shared between workers
+
+Responses are stored as raw bodies keyed on the request URL, each with the TTL of its endpoint.
+Concurrent misses for the same URL are coalesced, only the first one reaches the instance.
+*/
+
+use std::{collections::{BTreeSet, HashMap}, fmt, future::Future, sync::Mutex, time::{Duration, Instant}};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use actix_web::web::Bytes;
+use futures_channel::oneshot;
+use crate::error::LiteError;
+
+type Waiters = Vec<oneshot::Sender<Result<Bytes, LiteError>>>;
+
+pub struct ApiCache {
+    entries: Mutex<Entries>,
+    pending: Mutex<HashMap<String, Waiters>>,
+    max_size: usize, // Bytes, 0 disables caching
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    by_expiry: BTreeSet<(Instant, String)>,    // Eviction order
+    size: usize
+}
+
+struct Entry {
+    body: Bytes,
+    expires: Instant
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) {
+        if let Some(old) = self.map.remove(key) {
+            self.size -= old.body.len();
+            self.by_expiry.remove(&(old.expires, key.to_string()));
+        }
+    }
+}
+
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub coalesced: u64,
+    pub entries: usize,
+    pub size: usize
+}
+
+// Removes the in-flight marker even if the request is dropped, waiters then fetch on their own
+struct PendingGuard<'a> {
+    cache: &'a ApiCache,
+    key: &'a str,
+    done: bool
+}
+
+impl PendingGuard<'_> {
+    // Takes the waiters of a finished request, a marker inserted by a later miss is left alone
+    fn finish(mut self) -> Waiters {
+        self.done = true;
+        self.cache.pending.lock().unwrap().remove(self.key).unwrap_or_default()
+    }
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.cache.pending.lock().unwrap().remove(self.key);
+        }
+    }
+}
+
+impl ApiCache {
+    pub fn new(max_size: usize) -> Self {
+        ApiCache {
+            entries: Mutex::new(Entries::default()),
+            pending: Mutex::new(HashMap::new()),
+            max_size,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0)
+        }
+    }
+
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, ttl: Duration, fetch: F) -> Result<Bytes, LiteError>
+        where F: FnOnce() -> Fut, Fut: Future<Output = Result<Bytes, LiteError>>
+    {
+        if self.max_size == 0 {
+            return fetch().await;
+        }
+        if let Some(body) = self.get(key) {
+            self.hits.fetch_add(1, Relaxed);
+            return Ok(body);
+        }
+
+        let waiter = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get_mut(key) {
+                Some(waiters) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    Some(rx)
+                }
+                None => {
+                    pending.insert(key.to_string(), Vec::new());
+                    None
+                }
+            }
+        };
+        if let Some(rx) = waiter {
+            if let Ok(res) = rx.await {
+                self.coalesced.fetch_add(1, Relaxed);
+                return res;
+            }
+            // The first request was dropped before finishing
+            self.misses.fetch_add(1, Relaxed);
+            return fetch().await;
+        }
+
+        self.misses.fetch_add(1, Relaxed);
+        let guard = PendingGuard { cache: self, key, done: false };
+        let res = fetch().await;
+        if let Ok(ref body) = res {
+            self.insert(key, body.clone(), ttl);
+        }
+
+        let waiters = guard.finish();
+        for waiter in waiters {
+            let _ = waiter.send(res.clone());
+        }
+        res
+    }
+
+    // Drops an entry that is known to be outdated
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Relaxed),
+            misses: self.misses.load(Relaxed),
+            coalesced: self.coalesced.load(Relaxed),
+            entries: entries.map.len(),
+            size: entries.size
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Bytes> {
+        let entries = self.entries.lock().unwrap();
+        entries.map.get(key)
+            .filter(|e| e.expires > Instant::now())
+            .map(|e| e.body.clone())
+    }
+
+    fn insert(&self, key: &str, body: Bytes, ttl: Duration) {
+        if ttl.is_zero() || body.len() > self.max_size {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(key);
+
+        // Make room by dropping the entries closest to expiring, expired ones come first
+        while entries.size + body.len() > self.max_size {
+            match entries.by_expiry.pop_first() {
+                Some((_, oldest)) => {
+                    if let Some(e) = entries.map.remove(&oldest) {
+                        entries.size -= e.body.len();
+                    }
+                },
+                None => break
+            }
+        }
+
+        let expires = Instant::now() + ttl;
+        entries.size += body.len();
+        entries.by_expiry.insert((expires, key.to_string()));
+        entries.map.insert(key.to_string(), Entry { body, expires });
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} hits, {} misses, {} coalesced, {} entries, {} bytes",
+            self.hits, self.misses, self.coalesced, self.entries, self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use actix_web::rt::System;
+    use futures_util::{future::{join3, pending}, poll};
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn body(s: &'static str) -> Result<Bytes, LiteError> {
+        Ok(Bytes::from_static(s.as_bytes()))
+    }
+
+    fn run<F: Future<Output = ()> + 'static>(test: F) {
+        System::new("test").block_on(test)
+    }
+
+    #[test]
+    fn concurrent_misses_are_coalesced() { run(async {
+        let cache = ApiCache::new(1024);
+        let fetches = Cell::new(0);
+        let (tx, rx) = oneshot::channel::<()>();
+        let fetch = || async {
+            fetches.set(fetches.get() + 1);
+            body("post")
+        };
+
+        let (first, second, _) = join3(
+            cache.get_or_fetch("/post", TTL, || async {
+                let _ = rx.await;
+                fetch().await
+            }),
+            cache.get_or_fetch("/post", TTL, fetch),
+            async { tx.send(()).unwrap() }
+        ).await;
+        assert_eq!(first.unwrap(), "post");
+        assert_eq!(second.unwrap(), "post");
+        assert_eq!(fetches.get(), 1);
+        let stats = cache.stats();
+        assert_eq!((stats.misses, stats.coalesced, stats.entries), (1, 1, 1));
+        assert!(cache.pending.lock().unwrap().is_empty());
+    })}
+
+    #[test]
+    fn dropped_fetch_leaves_waiters_to_fetch() { run(async {
+        let cache = ApiCache::new(1024);
+        let mut first = Box::pin(cache.get_or_fetch("/post", TTL, pending));
+        let mut second = Box::pin(cache.get_or_fetch("/post", TTL, || async { body("post") }));
+        assert!(poll!(first.as_mut()).is_pending());
+        assert!(poll!(second.as_mut()).is_pending());
+        drop(first);
+        assert_eq!(second.await.unwrap(), "post");
+    })}
+
+    #[test]
+    fn expired_entries_are_fetched_again() { run(async {
+        let cache = ApiCache::new(1024);
+        let fetches = Cell::new(0);
+        let fetch = || async {
+            fetches.set(fetches.get() + 1);
+            body("list")
+        };
+        cache.get_or_fetch("/fresh", TTL, fetch).await.unwrap();
+        cache.get_or_fetch("/fresh", TTL, fetch).await.unwrap();
+        assert_eq!(fetches.get(), 1);
+        cache.get_or_fetch("/stale", Duration::from_secs(0), fetch).await.unwrap();
+        cache.get_or_fetch("/stale", Duration::from_secs(0), fetch).await.unwrap();
+        assert_eq!(fetches.get(), 3);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.entries), (1, 1));
+    })}
+
+    #[test]
+    fn eviction_drops_entries_closest_to_expiring() {
+        let cache = ApiCache::new(10);
+        cache.insert("/short", Bytes::from_static(b"1234"), Duration::from_secs(10));
+        cache.insert("/long", Bytes::from_static(b"1234"), Duration::from_secs(100));
+        cache.insert("/new", Bytes::from_static(b"1234"), TTL);
+        assert!(cache.get("/short").is_none());
+        assert!(cache.get("/long").is_some());
+        assert!(cache.get("/new").is_some());
+        assert_eq!(cache.stats().size, 8);
+
+        // Larger than the whole cache
+        cache.insert("/huge", Bytes::from_static(b"12345678901"), TTL);
+        assert!(cache.get("/huge").is_none());
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.entries.lock().unwrap().by_expiry.len(), 2);
+    }
+}
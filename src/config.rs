@@ -0,0 +1,319 @@
+/*
+Runtime configuration
+
+Values are resolved in this order, later sources overriding earlier ones:
+1. Built-in defaults
+2. TOML file (--config, $LEMMY_LITE_CONFIG or ./lemmy-lite.toml if present)
+3. Environment variables, LEMMY_LITE_<KEY> (ex: LEMMY_LITE_WORKERS=4)
+4. Command-line flags, --<key> <value> (ex: --workers 4)
+
+Keys use underscores in TOML and env variables, dashes or underscores on the command line.
+*/
+
+use std::{env, fmt, fs, net::ToSocketAddrs, sync::OnceLock};
+use serde::{Deserialize, Deserializer, de};
+use crate::lemmy_api::ApiVersion;
+
+const DEFAULT_CONFIG_FILE: &str = "lemmy-lite.toml";
+const ENV_PREFIX: &str = "LEMMY_LITE_";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub const HELP: &str = "lemmy-lite - A static, JSless, touch-friendly Lemmy frontend
+
+Usage: lemmy-lite [OPTIONS]
+
+Options:
+  -c, --config <FILE>          TOML configuration file [default: ./lemmy-lite.toml]
+  -l, --listen <ADDR>          Address to listen on, may be repeated or comma separated [default: 127.0.0.1:1131]
+  -w, --workers <N>            Number of HTTP worker threads [default: number of CPUs]
+      --upstream-scheme <S>    Scheme used to reach instances, http or https [default: https]
+      --api-version <V>        Lemmy API version spoken by instances, auto, v1 or v3 [default: auto]
+      --upstream-timeout <S>   Seconds to wait for an instance to respond [default: 10]
+      --blocked-instances <I>  Comma separated instances that cannot be browsed
+      --max-body-size <B>      Maximum size in bytes of an instance response [default: 8388608]
+      --cache-size <B>         Memory in bytes used to cache instance responses, 0 disables [default: 33554432]
+      --secure-cookies <BOOL>  Only send login cookies over HTTPS [default: true]
+      --public-url <URL>       Scheme and host lemmy-lite is reached at, used in feed and text links [default: from the request]
+      --stylesheet <URL>       Stylesheet URL [default: /s.css]
+      --link-img <URL>         Link preview image URL [default: /l.svg]
+      --media-img <URL>        Media preview image URL [default: /m.svg]
+      --text-img <URL>         Text preview image URL [default: /t.svg]
+      --gemini-listen <ADDR>   Address to serve Gemini on, disabled by default
+      --gemini-cert <FILE>     PEM certificate chain of the Gemini listener
+      --gemini-key <FILE>      PEM private key of the Gemini listener
+  -h, --help                   Print this message
+
+Every option can also be set with an environment variable, ex: LEMMY_LITE_UPSTREAM_TIMEOUT=5";
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: Vec<String>,
+    pub workers: Option<usize>,
+    pub upstream_scheme: String,
+    #[serde(deserialize_with = "api_version")]
+    pub api_version: Option<ApiVersion>,   // None detects the version of each instance
+    pub upstream_timeout: u64,  // Seconds
+    pub max_body_size: usize,   // Bytes
+    pub cache_size: usize,      // Bytes, 0 disables the response cache
+    pub secure_cookies: bool,
+    pub public_url: Option<String>,     // None trusts the Host and X-Forwarded-* headers of each request
+    pub blocked_instances: Vec<String>,
+    pub assets: Assets,
+    pub gemini: Gemini
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Assets {
+    pub stylesheet: String,
+    pub link_img: String,
+    pub media_img: String,
+    pub text_img: String
+}
+
+// The Gemini listener only runs when listen is set, it then needs a certificate and key
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gemini {
+    pub listen: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec!["127.0.0.1:1131".to_string()],
+            workers: None,
+            upstream_scheme: "https".to_string(),
+            api_version: None,
+            upstream_timeout: 10,
+            max_body_size: 8388608, // 8MB limit
+            cache_size: 33554432,   // 32MB
+            secure_cookies: true,
+            public_url: None,
+            blocked_instances: Vec::new(),
+            assets: Assets::default(),
+            gemini: Gemini::default()
+        }
+    }
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Assets {
+            stylesheet: "/s.css".to_string(),
+            link_img: "/l.svg".to_string(),
+            media_img: "/m.svg".to_string(),
+            text_img: "/t.svg".to_string()
+        }
+    }
+}
+
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub enum Command {
+    Run(Box<Config>),
+    Help
+}
+
+impl Config {
+    // Resolve the configuration from the config file, environment and command line
+    pub fn load() -> Result<Command, ConfigError> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let mut overrides = Vec::new();
+        let mut config_file = env::var(format!("{}CONFIG", ENV_PREFIX)).ok();
+
+        let mut iter = args.into_iter();
+        while let Some(arg) = iter.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg, None)
+            };
+            let key = match flag.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-c" => "config".to_string(),
+                "-l" => "listen".to_string(),
+                "-w" => "workers".to_string(),
+                f if f.starts_with("--") => f[2..].replace('-', "_"),
+                f => return Err(ConfigError(format!("unexpected argument '{}', see --help", f)))
+            };
+            let value = match inline_value.or_else(|| iter.next()) {
+                Some(v) => v,
+                None => return Err(ConfigError(format!("missing value for '{}'", flag)))
+            };
+            if key == "config" {
+                config_file = Some(value);
+            } else {
+                overrides.push((key, value, flag));
+            }
+        }
+
+        let mut config = match config_file {
+            Some(path) => Config::from_file(&path)?,
+            None if fs::metadata(DEFAULT_CONFIG_FILE).is_ok() => Config::from_file(DEFAULT_CONFIG_FILE)?,
+            None => Config::default()
+        };
+
+        // Other programs' variables may not be UTF-8, which env::vars panics on
+        for (key, value) in env::vars_os() {
+            let (key, value) = match (key.into_string(), value.into_string()) {
+                (Ok(key), Ok(value)) => (key, value),
+                _ => continue
+            };
+            if let Some(k) = key.strip_prefix(ENV_PREFIX) {
+                if k != "CONFIG" {
+                    config.set(&k.to_lowercase(), &value)
+                        .map_err(|e| ConfigError(format!("{}: {}", key, e)))?;
+                }
+            }
+        }
+
+        // Repeated --listen flags accumulate rather than replace each other
+        let mut cli_listen: Option<Vec<String>> = None;
+        for (key, value, flag) in overrides {
+            if key == "listen" {
+                cli_listen.get_or_insert_with(Vec::new).extend(split_list(&value));
+            } else {
+                config.set(&key, &value).map_err(|e| ConfigError(format!("{}: {}", flag, e)))?;
+            }
+        }
+        if let Some(listen) = cli_listen {
+            config.listen = listen;
+        }
+
+        config.validate()?;
+        Ok(Command::Run(Box::new(config)))
+    }
+
+    fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("unable to read config file '{}': {}", path, e)))?;
+        toml::from_str(&contents)
+            .map_err(|e| ConfigError(format!("invalid config file '{}': {}", path, e)))
+    }
+
+    // Apply a single override from the environment or command line
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "listen" => self.listen = split_list(value),
+            "workers" => self.workers = Some(parse_number(value)?),
+            "upstream_scheme" => self.upstream_scheme = value.to_string(),
+            "api_version" => self.api_version = parse_api_version(value)?,
+            "upstream_timeout" => self.upstream_timeout = parse_number(value)?,
+            "max_body_size" => self.max_body_size = parse_number(value)?,
+            "cache_size" => self.cache_size = parse_number(value)?,
+            "secure_cookies" => self.secure_cookies = parse_bool(value)?,
+            "public_url" => self.public_url = Some(value.to_string()),
+            "blocked_instances" => self.blocked_instances = split_list(value),
+            "stylesheet" => self.assets.stylesheet = value.to_string(),
+            "link_img" => self.assets.link_img = value.to_string(),
+            "media_img" => self.assets.media_img = value.to_string(),
+            "text_img" => self.assets.text_img = value.to_string(),
+            "gemini_listen" => self.gemini.listen = Some(value.to_string()),
+            "gemini_cert" => self.gemini.cert = Some(value.to_string()),
+            "gemini_key" => self.gemini.key = Some(value.to_string()),
+            _ => return Err(format!("unknown option '{}'", key))
+        }
+        Ok(())
+    }
+
+    pub fn is_blocked(&self, instance: &str) -> bool {
+        self.blocked_instances.iter().any(|b| b.eq_ignore_ascii_case(instance))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.listen.is_empty() {
+            return Err(ConfigError("listen: at least one address is required".to_string()));
+        }
+        for addr in &self.listen {
+            if addr.to_socket_addrs().is_err() {
+                return Err(ConfigError(format!("listen: '{}' is not a valid address, expected host:port", addr)));
+            }
+        }
+        if self.workers == Some(0) {
+            return Err(ConfigError("workers: must be greater than 0".to_string()));
+        }
+        if self.upstream_scheme != "https" && self.upstream_scheme != "http" {
+            return Err(ConfigError(format!("upstream_scheme: '{}' is not supported, expected http or https", self.upstream_scheme)));
+        }
+        if self.upstream_timeout == 0 {
+            return Err(ConfigError("upstream_timeout: must be greater than 0".to_string()));
+        }
+        if self.max_body_size == 0 {
+            return Err(ConfigError("max_body_size: must be greater than 0".to_string()));
+        }
+        if let Some(ref url) = self.public_url {
+            if !(url.starts_with("https://") || url.starts_with("http://")) || url.ends_with('/') {
+                return Err(ConfigError(format!("public_url: '{}' is not valid, expected a scheme and host, ex: https://lite.example.com", url)));
+            }
+        }
+        for (name, url) in &[
+            ("stylesheet", &self.assets.stylesheet),
+            ("link_img", &self.assets.link_img),
+            ("media_img", &self.assets.media_img),
+            ("text_img", &self.assets.text_img)
+        ] {
+            if url.is_empty() {
+                return Err(ConfigError(format!("assets.{}: cannot be empty", name)));
+            }
+        }
+        if let Some(ref addr) = self.gemini.listen {
+            if addr.to_socket_addrs().is_err() {
+                return Err(ConfigError(format!("gemini.listen: '{}' is not a valid address, expected host:port", addr)));
+            }
+            if self.gemini.cert.is_none() || self.gemini.key.is_none() {
+                return Err(ConfigError("gemini: cert and key are required to listen".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Set the global configuration, must be called once before the server starts
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("Configuration initialized twice");
+    }
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
+}
+
+fn parse_api_version(value: &str) -> Result<Option<ApiVersion>, String> {
+    match value {
+        "auto" => Ok(None),
+        "v1" => Ok(Some(ApiVersion::V1)),
+        "v3" => Ok(Some(ApiVersion::V3)),
+        _ => Err(format!("'{}' is not a supported API version, expected auto, v1 or v3", value))
+    }
+}
+
+fn api_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ApiVersion>, D::Error> {
+    parse_api_version(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.trim() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(format!("'{}' is not a valid boolean, expected true or false", value))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("'{}' is not a valid number", value))
+}
@@ -0,0 +1,156 @@
+// Errors shown to the user as a regular page, keeping the navbar whenever the instance is known
+
+use std::fmt;
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, client::{SendRequestError, ConnectError}};
+use crate::templates::error_page;
+
+#[derive(Debug, Clone)]
+pub enum LiteError {
+    UpstreamUnreachable { instance: String },
+    UpstreamTimeout { instance: String },
+    UpstreamError { instance: String, message: String },
+    BadJson { instance: String, detail: String },
+    NotFound { instance: Option<String>, detail: String },
+    InvalidPath { instance: Option<String>, detail: String },
+    InstanceBlocked { instance: String },
+    UnsupportedInstance { instance: String, software: String, version: String },
+    LoginRequired { instance: String },
+    Internal { instance: String, detail: String }
+}
+
+impl LiteError {
+    pub fn instance(&self) -> Option<&str> {
+        match self {
+            LiteError::UpstreamUnreachable { instance } |
+            LiteError::UpstreamTimeout { instance } |
+            LiteError::UpstreamError { instance, .. } |
+            LiteError::BadJson { instance, .. } |
+            LiteError::LoginRequired { instance } |
+            LiteError::Internal { instance, .. } => Some(instance),
+            LiteError::NotFound { instance, .. } |
+            LiteError::InvalidPath { instance, .. } => instance.as_deref(),
+            // Navigating a blocked or unsupported instance leads to more errors
+            LiteError::InstanceBlocked { .. } |
+            LiteError::UnsupportedInstance { .. } => None
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            LiteError::UpstreamUnreachable { .. } => "Instance unreachable",
+            LiteError::UpstreamTimeout { .. } => "Instance took too long to respond",
+            LiteError::UpstreamError { .. } => "Instance returned an error",
+            LiteError::BadJson { .. } => "Instance sent an unexpected response",
+            LiteError::NotFound { .. } => "Not found",
+            LiteError::InvalidPath { .. } => "Invalid path",
+            LiteError::InstanceBlocked { .. } => "Instance blocked",
+            LiteError::UnsupportedInstance { .. } => "Unsupported instance",
+            LiteError::LoginRequired { .. } => "Login required",
+            LiteError::Internal { .. } => "Internal error"
+        }
+    }
+
+    // Message for errors caused by what the user submitted, shown on the form instead of an error page
+    pub fn form_message(&self) -> Option<String> {
+        match self {
+            LiteError::UpstreamError { message, .. } => Some(describe_upstream(message)),
+            LiteError::NotFound { detail, .. } => Some(detail.clone()),
+            _ => None
+        }
+    }
+
+    // Build from a failed request to an instance
+    pub fn from_send(instance: &str, e: SendRequestError) -> Self {
+        match e {
+            SendRequestError::Timeout | SendRequestError::Connect(ConnectError::Timeout) =>
+                LiteError::UpstreamTimeout { instance: instance.to_string() },
+            _ => LiteError::UpstreamUnreachable { instance: instance.to_string() }
+        }
+    }
+}
+
+// Lemmy reports errors as snake_case codes, common ones get a proper sentence
+fn describe_upstream(code: &str) -> String {
+    match code {
+        "incorrect_login" | "password_incorrect" => "Incorrect username or password".to_string(),
+        "email_not_verified" => "Verify your email before logging in".to_string(),
+        "captcha_incorrect" => "The captcha answer is incorrect".to_string(),
+        "passwords_dont_match" => "The passwords don't match".to_string(),
+        "user_already_exists" => "This username is taken".to_string(),
+        "email_already_exists" => "This email is already registered".to_string(),
+        "email_required" => "An email is required".to_string(),
+        "registration_application_answer_required" => "Answer the question to apply".to_string(),
+        "invalid_url" => "The URL is not valid".to_string(),
+        "invalid_post_title" => "The title cannot be empty or contain line breaks".to_string(),
+        "post_title_too_long" => "The title is too long".to_string(),
+        "invalid_body_field" => "The body is too long".to_string(),
+        "invalid_display_name" => "The display name is not valid".to_string(),
+        "invalid_matrix_id" => "The Matrix user is not valid, it looks like @user:example.com".to_string(),
+        "bio_length_overflow" => "The bio is too long".to_string(),
+        "locked" => "This post is locked".to_string(),
+        "site_ban" | "banned_from_community" | "person_is_banned_from_community" => "You are banned".to_string(),
+        "no_post_edit_allowed" | "no_comment_edit_allowed" => "You are not allowed to edit this".to_string(),
+        "report_reason_required" => "A reason is required".to_string(),
+        "report_too_long" => "The reason is too long".to_string(),
+        "rate_limit_error" => "Too many actions, wait a moment and try again".to_string(),
+        "not_logged_in" => "Your session expired, log in again".to_string(),
+        _ => {
+            let text = code.replace('_', " ");
+            let mut chars = text.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+        }
+    }
+}
+
+impl fmt::Display for LiteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiteError::UpstreamUnreachable { instance } =>
+                write!(f, "{} could not be reached, it may be down or the name may be misspelled.", instance),
+            LiteError::UpstreamTimeout { instance } =>
+                write!(f, "{} did not respond in time, try again later.", instance),
+            LiteError::UpstreamError { instance, message } =>
+                write!(f, "{} responded with: {}", instance, message),
+            LiteError::BadJson { instance, detail } =>
+                write!(f, "{} responded with something other than the expected Lemmy API response ({}).", instance, detail),
+            LiteError::NotFound { detail, .. } |
+            LiteError::InvalidPath { detail, .. } => f.write_str(detail),
+            LiteError::InstanceBlocked { instance } =>
+                write!(f, "{} has been blocked by the administrator of this lemmy-lite.", instance),
+            LiteError::UnsupportedInstance { instance, software, version } => {
+                write!(f, "{} is running {}", instance, software)?;
+                if !version.is_empty() {
+                    write!(f, " {}", version)?;
+                }
+                f.write_str(", which is not a supported Lemmy version. \
+                    lemmy-lite supports Lemmy 0.9 and older, and Lemmy 0.17 and newer.")
+            }
+            LiteError::LoginRequired { instance } =>
+                write!(f, "You need to log in to {} first.", instance),
+            LiteError::Internal { detail, .. } =>
+                write!(f, "lemmy-lite failed to build this page ({}).", detail)
+        }
+    }
+}
+
+impl ResponseError for LiteError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LiteError::UpstreamUnreachable { .. } |
+            LiteError::UpstreamError { .. } |
+            LiteError::BadJson { .. } |
+            LiteError::UnsupportedInstance { .. } => StatusCode::BAD_GATEWAY,
+            LiteError::UpstreamTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            LiteError::NotFound { .. } => StatusCode::NOT_FOUND,
+            LiteError::InvalidPath { .. } => StatusCode::BAD_REQUEST,
+            LiteError::InstanceBlocked { .. } => StatusCode::FORBIDDEN,
+            LiteError::LoginRequired { .. } => StatusCode::UNAUTHORIZED,
+            LiteError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).content_type("text/html; charset=utf-8")
+            .body(error_page(self).into_string())
+    }
+}
@@ -0,0 +1,286 @@
+/*
+RSS and Atom feeds
+
+A feed is requested by adding .rss or .atom to the last path segment of a page, ex: /lemmy.ml/c/rust.atom
+Feeds are built from the same API responses as the pages, markdown is rendered to HTML then escaped into the XML.
+*/
+
+use std::{cmp::Reverse, collections::HashMap};
+use chrono::naive::NaiveDateTime;
+use maud::html;
+use crate::lemmy_api::{PostView, CommentView};
+use crate::templates::{mdstr_to_html, is_safe_url, rfc3339};
+
+#[derive(Clone, Copy)]
+pub enum FeedFormat {
+    Rss,
+    Atom
+}
+
+impl FeedFormat {
+    // Splits the feed extension off a path segment
+    pub fn split(segment: &str) -> (&str, Option<FeedFormat>) {
+        if let Some(rest) = segment.strip_suffix(".rss") {
+            (rest, Some(FeedFormat::Rss))
+        } else if let Some(rest) = segment.strip_suffix(".atom") {
+            (rest, Some(FeedFormat::Atom))
+        } else {
+            (segment, None)
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8"
+        }
+    }
+}
+
+struct Entry {
+    title: String,
+    link: String,
+    author: String,
+    published: NaiveDateTime,
+    content: String     // HTML
+}
+
+// base is the scheme and host lemmy-lite is reached through, feed readers need absolute links
+pub struct Feed<'a> {
+    base: &'a str,
+    instance: &'a str,
+    title: String,
+    link: String,
+    self_link: String,
+    entries: Vec<Entry>
+}
+
+impl<'a> Feed<'a> {
+    // path is the page the feed mirrors, self_path the feed itself with its query
+    pub fn new(base: &'a str, instance: &'a str, title: String, path: &str, self_path: &str) -> Self {
+        Feed {
+            base,
+            instance,
+            title,
+            link: format!("{}{}", base, path),
+            self_link: format!("{}{}", base, self_path),
+            entries: Vec::new()
+        }
+    }
+
+    pub fn push_posts(&mut self, posts: &[PostView]) {
+        for post in posts {
+            let content = html! {
+                @if let Some(url) = post.url.as_ref().filter(|u| is_safe_url(u)) {
+                    p { a href=(url) {(url)} }
+                }
+                @if let Some(ref body) = post.body {
+                    (mdstr_to_html(body))
+                }
+            };
+            self.entries.push(Entry {
+                title: post.name.clone(),
+                link: format!("{}/{}/post/{}", self.base, self.instance, post.id),
+                author: post.creator_name.clone(),
+                published: post.published,
+                content: content.into_string()
+            });
+        }
+    }
+
+    // Posts and comments are listed apart in the sort of the page, they can only be mixed when sorted by date
+    pub fn push_comments(&mut self, comments: &[CommentView], sort: Option<&str>) {
+        for comment in comments {
+            self.entries.push(Entry {
+                title: format!("Comment by {} in /c/{}", comment.creator_name, comment.community_name),
+                link: format!("{}/{}/post/{}/comment/{}", self.base, self.instance, comment.post_id, comment.id),
+                author: comment.creator_name.clone(),
+                published: comment.published,
+                content: mdstr_to_html(&comment.content).into_string()
+            });
+        }
+        match sort {
+            Some("New") => self.entries.sort_by_key(|e| Reverse(e.published)),
+            Some("Old") => self.entries.sort_by_key(|e| e.published),
+            _ => ()
+        }
+    }
+
+    // Comments of a post newest first, each quoting the comments it replies to
+    pub fn push_thread(&mut self, post: &PostView, comments: &[CommentView]) {
+        let by_id: HashMap<i32, &CommentView> = comments.iter().map(|c| (c.id, c)).collect();
+        let mut comments: Vec<&CommentView> = comments.iter().collect();
+        comments.sort_by_key(|c| Reverse(c.published));
+        for comment in comments {
+            let mut parents = Vec::new();
+            let mut parent_id = comment.parent_id;
+            while let Some(parent) = parent_id.and_then(|id| by_id.get(&id)) {
+                // A chain longer than the thread means the instance returned a loop
+                if parents.len() >= by_id.len() {
+                    break;
+                }
+                parents.push(*parent);
+                parent_id = parent.parent_id;
+            }
+            let content = html! {
+                @for parent in parents.iter().rev() {
+                    blockquote {
+                        p { b {(parent.creator_name)} " wrote:" }
+                        (mdstr_to_html(&parent.content))
+                    }
+                }
+                (mdstr_to_html(&comment.content))
+            };
+            self.entries.push(Entry {
+                title: format!("Comment by {} on {}", comment.creator_name, post.name),
+                link: format!("{}/{}/post/{}/comment/{}", self.base, self.instance, post.id, comment.id),
+                author: comment.creator_name.clone(),
+                published: comment.published,
+                content: content.into_string()
+            });
+        }
+    }
+
+    pub fn render(&self, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Rss => self.rss(),
+            FeedFormat::Atom => self.atom()
+        }
+    }
+
+    fn updated(&self) -> Option<NaiveDateTime> {
+        self.entries.iter().map(|e| e.published).max()
+    }
+
+    fn rss(&self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/"><channel>"#);
+        xml.push_str(&format!("<title>{}</title><link>{}</link><description>{}</description>",
+            escape(&self.title), escape(&self.link), escape(&self.title)));
+        xml.push_str(&format!(r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#, escape(&self.self_link)));
+        if let Some(updated) = self.updated() {
+            xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>", rfc822(updated)));
+        }
+        for entry in &self.entries {
+            xml.push_str(&format!(
+                r#"<item><title>{}</title><link>{}</link><guid isPermaLink="true">{}</guid><dc:creator>{}</dc:creator><pubDate>{}</pubDate><description>{}</description></item>"#,
+                escape(&entry.title), escape(&entry.link), escape(&entry.link), escape(&entry.author),
+                rfc822(entry.published), escape(&entry.content)));
+        }
+        xml.push_str("</channel></rss>");
+        xml
+    }
+
+    fn atom(&self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        xml.push_str(&format!(r#"<title>{}</title><id>{}</id><link href="{}"/><link rel="self" href="{}"/>"#,
+            escape(&self.title), escape(&self.self_link), escape(&self.link), escape(&self.self_link)));
+        // Atom requires an updated date even for an empty feed
+        xml.push_str(&format!("<updated>{}</updated>", rfc3339(self.updated().unwrap_or_default())));
+        for entry in &self.entries {
+            xml.push_str(&format!(
+                r#"<entry><title>{}</title><id>{}</id><link href="{}"/><author><name>{}</name></author><published>{}</published><updated>{}</updated><content type="html">{}</content></entry>"#,
+                escape(&entry.title), escape(&entry.link), escape(&entry.link), escape(&entry.author),
+                rfc3339(entry.published), rfc3339(entry.published), escape(&entry.content)));
+        }
+        xml.push_str("</feed>");
+        xml
+    }
+}
+
+// Escapes text and attribute values, dropping control characters XML doesn't allow
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+// API timestamps are UTC
+fn rfc822(time: NaiveDateTime) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S +0000").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, published: &str, content: &str) -> Entry {
+        Entry {
+            title: title.to_string(),
+            link: "https://lite.example.com/lemmy.ml/post/1".to_string(),
+            author: "alice".to_string(),
+            published: NaiveDateTime::parse_from_str(published, "%Y-%m-%d %H:%M").unwrap(),
+            content: content.to_string()
+        }
+    }
+
+    #[test]
+    fn escapes_markup_and_quotes() {
+        assert_eq!(escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;");
+    }
+
+    #[test]
+    fn drops_control_characters_xml_forbids() {
+        assert_eq!(escape("a\u{0}b\u{1b}c\u{7f}d\te\nf\r"), "abcd\te\nf\r");
+    }
+
+    #[test]
+    fn content_cannot_break_out_of_its_element() {
+        let mut feed = Feed::new("https://lite.example.com", "lemmy.ml", "lemmy.ml".to_string(), "/lemmy.ml", "/lemmy.ml.rss");
+        feed.entries.push(entry("]]></title><x/>", "2023-06-01 12:00", "</description><script>alert(1)</script>"));
+        let rss = feed.render(FeedFormat::Rss);
+        assert!(!rss.contains("<script>") && !rss.contains("<x/>"));
+        assert!(rss.contains("<description>&lt;/description&gt;&lt;script&gt;"));
+        let atom = feed.render(FeedFormat::Atom);
+        assert!(!atom.contains("<script>") && !atom.contains("<x/>"));
+    }
+
+    fn post(name: &str, published: &str) -> PostView {
+        PostView {
+            id: 1, name: name.to_string(), url: None, body: None, creator_id: 7, creator_name: "alice".to_string(),
+            community_id: 2, community_name: "rust".to_string(), removed: false, locked: false, deleted: false, nsfw: false,
+            stickied: false, published: NaiveDateTime::parse_from_str(published, "%Y-%m-%d %H:%M").unwrap(),
+            banned_from_community: false, number_of_comments: 0, score: 1, upvotes: 1, downvotes: 0, my_vote: None, saved: None
+        }
+    }
+
+    fn comment(content: &str, published: &str) -> CommentView {
+        CommentView {
+            id: 10, creator_id: 7, creator_name: "alice".to_string(), post_id: 1, parent_id: None, community_id: 2,
+            community_name: "rust".to_string(), content: content.to_string(), removed: false, deleted: false,
+            published: NaiveDateTime::parse_from_str(published, "%Y-%m-%d %H:%M").unwrap(), banned_from_community: false,
+            score: 1, upvotes: 1, downvotes: 0, my_vote: None, saved: None
+        }
+    }
+
+    fn user_feed(sort: Option<&str>) -> Vec<String> {
+        let mut feed = Feed::new("https://lite.example.com", "lemmy.ml", "alice".to_string(), "/lemmy.ml/u/alice", "/lemmy.ml/u/alice.atom");
+        feed.push_posts(&[post("new post", "2023-06-03 12:00"), post("old post", "2023-06-01 12:00")]);
+        feed.push_comments(&[comment("new comment", "2023-06-04 12:00"), comment("comment", "2023-06-02 12:00")], sort);
+        feed.entries.iter().map(|e| if e.title.starts_with("Comment") { e.content.clone() } else { e.title.clone() }).collect()
+    }
+
+    #[test]
+    fn comments_are_mixed_with_posts_by_date() {
+        assert_eq!(user_feed(Some("New")), ["<p>new comment</p>\n", "new post", "<p>comment</p>\n", "old post"]);
+    }
+
+    #[test]
+    fn other_sorts_keep_the_order_of_the_instance() {
+        assert_eq!(user_feed(Some("TopAll")), ["new post", "old post", "<p>new comment</p>\n", "<p>comment</p>\n"]);
+        assert_eq!(user_feed(None), user_feed(Some("Hot")));
+    }
+}
@@ -0,0 +1,703 @@
+/*
+Gemini frontend
+
+An optional TLS listener serving the same pages as the HTTP routes as gemtext, ex: gemini://host/lemmy.ml/c/rust
+Pages are fetched through lemmy_api like the HTML ones, anonymously since Gemini has no cookies.
+Markdown links are pulled out of the text as => lines after the block they appear in,
+nested comments are indented with a prefix per level.
+*/
+
+use std::{fs::File, io::{self, BufReader}, time::Duration};
+use actix_server::Server;
+use actix_service::{fn_service, pipeline_factory};
+use actix_tls::rustls::{Acceptor, ServerConfig, TlsStream};
+use actix_web::{web, client::Client, rt::{net::TcpStream, time::timeout}};
+use chrono::{naive::NaiveDateTime, offset::Utc};
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use pulldown_cmark::{Parser, Event, Tag};
+use rustls::{NoClientAuth, internal::pemfile};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use url::{Url, form_urlencoded};
+use crate::config;
+use crate::cache::ApiCache;
+use crate::error::LiteError;
+use crate::routes::{connect, parse_id, unknown_page};
+use crate::lemmy_api::{Api, Instances, PagingParams, SearchParams, PostView, CommentView, CommunityView, UserView, ModlogTarget,
+    get_post_list, get_post, get_community, get_community_list, get_user, search, get_modlog};
+use crate::templates::{self, is_safe_url, markdown_link, pager_urls, paging_pairs, query_string, simple_duration};
+
+const MAX_REQUEST: u64 = 1026;  // 1024 bytes of URL and CRLF
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const INDENT: &str = "│ ";
+// Characters escaped when a prompt answer becomes a path segment
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>').add(b'?').add(b'`');
+const SORTS: &[(&str, &str)] = &[("Hot", "Hot"), ("Active", "Active"), ("New", "New"), ("TopDay", "Top day"),
+    ("TopWeek", "Top week"), ("TopMonth", "Top month"), ("TopYear", "Top year"), ("TopAll", "Top all time")];
+
+enum Response {
+    Success(String),    // Gemtext
+    Input(&'static str),
+    Redirect(String),
+    Failure(u8, String)
+}
+
+impl Response {
+    fn from_error(e: &LiteError) -> Self {
+        let status = match e {
+            LiteError::NotFound { .. } => 51,
+            LiteError::InvalidPath { .. } => 59,
+            LiteError::UpstreamUnreachable { .. } |
+            LiteError::UpstreamTimeout { .. } |
+            LiteError::UpstreamError { .. } |
+            LiteError::BadJson { .. } => 43,
+            LiteError::Internal { .. } => 40,
+            LiteError::InstanceBlocked { .. } => 53,
+            LiteError::UnsupportedInstance { .. } |
+            LiteError::LoginRequired { .. } => 50
+        };
+        Response::Failure(status, e.to_string())
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let (status, meta, body) = match self {
+            Response::Success(body) => (20, "text/gemini; charset=utf-8".to_string(), Some(body)),
+            Response::Input(prompt) => (10, prompt.to_string(), None),
+            Response::Redirect(url) => (30, url, None),
+            Response::Failure(status, message) => (status, message, None)
+        };
+        let mut res = format!("{} {}\r\n", status, single_line(&meta)).into_bytes();
+        if let Some(body) = body {
+            res.extend(body.into_bytes());
+        }
+        res
+    }
+}
+
+// Certificate chain and private key, PKCS8 or RSA, of the listener
+pub fn tls_config(cert: &str, key: &str) -> Result<ServerConfig, String> {
+    let certs = File::open(cert).ok()
+        .and_then(|f| pemfile::certs(&mut BufReader::new(f)).ok())
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| format!("unable to read a certificate from '{}'", cert))?;
+    let read_keys = |read: fn(&mut dyn io::BufRead) -> Result<Vec<rustls::PrivateKey>, ()>| File::open(key).ok()
+        .and_then(|f| read(&mut BufReader::new(f)).ok())
+        .and_then(|mut k| k.pop());
+    let key_der = read_keys(pemfile::pkcs8_private_keys)
+        .or_else(|| read_keys(pemfile::rsa_private_keys))
+        .ok_or_else(|| format!("unable to read a private key from '{}'", key))?;
+
+    let mut tls = ServerConfig::new(NoClientAuth::new());
+    tls.set_single_cert(certs, key_der).map_err(|e| format!("invalid certificate or key: {}", e))?;
+    Ok(tls)
+}
+
+// Each worker gets its own client, like the HTTP workers, the cache and instances are shared
+pub fn server(addr: &str, tls: ServerConfig, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> io::Result<Server> {
+    let config = config::get();
+    let mut builder = Server::build();
+    if let Some(workers) = config.workers {
+        builder = builder.workers(workers);
+    }
+    Ok(builder.bind("gemini", addr, move || {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.upstream_timeout))
+            .finish();
+        let instances = instances.clone();
+        let cache = cache.clone();
+        pipeline_factory(Acceptor::new(tls.clone()))
+            .and_then(fn_service(move |stream| serve(stream, client.clone(), instances.clone(), cache.clone())))
+    })?.run())
+}
+
+async fn serve(mut stream: TlsStream<TcpStream>, client: Client, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> io::Result<()> {
+    let mut line = Vec::new();
+    let mut reader = AsyncBufReader::new((&mut stream).take(MAX_REQUEST));
+    match timeout(REQUEST_TIMEOUT, reader.read_until(b'\n', &mut line)).await {
+        Ok(res) => res?,
+        Err(_) => return Ok(())
+    };
+    drop(reader);
+    let res = match parse_request(&line) {
+        Ok(url) => route(&client, &cache, &instances, &url).await.unwrap_or_else(|e| Response::from_error(&e)),
+        Err(res) => res
+    };
+    stream.write_all(&res.into_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn parse_request(line: &[u8]) -> Result<Url, Response> {
+    let bad_request = || Response::Failure(59, "Bad request".to_string());
+    let line = line.strip_suffix(b"\r\n").ok_or_else(bad_request)?;
+    let url = std::str::from_utf8(line).ok()
+        .and_then(|l| Url::parse(l).ok())
+        .ok_or_else(bad_request)?;
+    if url.scheme() != "gemini" {
+        return Err(Response::Failure(53, "Only gemini:// URLs are served".to_string()));
+    }
+    Ok(url)
+}
+
+// Same pages as lvl1 to lvl5, except the ones that need a session
+async fn route(client: &Client, cache: &ApiCache, instances: &Instances, url: &Url) -> Result<Response, LiteError> {
+    let segments: Vec<String> = url.path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).map(decode).collect())
+        .unwrap_or_default();
+    let query = url.query();
+    let inst = match segments.first() {
+        Some(inst) => inst,
+        None => return Ok(match query {
+            Some(q) => Response::Redirect(format!("/{}", utf8_percent_encode(decode(q).trim(), SEGMENT))),
+            None => Response::Input("Instance to browse, ex: lemmy.ml")
+        })
+    };
+    let path: Vec<&str> = segments.iter().skip(1).map(String::as_str).collect();
+    let api = &connect(client, cache, instances, inst, None).await?;
+    let now = &Utc::now().naive_utc();
+
+    let mut out = Gemtext::default();
+    match path.as_slice() {
+        [] => {
+            let paging_params = &params::<PagingParams>(inst, query)?;
+            let post_list = get_post_list(api, None, None, Some(paging_params)).await?;
+            out.heading(1, inst);
+            post_list_body(&mut out, inst, &post_list.posts, &format!("/{}", inst), paging_params, now);
+            out.link(&format!("/{}/communities", inst), "Communities");
+            out.link(&format!("/{}/search", inst), "Search");
+            out.link(&format!("/{}/modlog", inst), "Modlog");
+        },
+        ["c", name] => {
+            let paging_params = &params::<PagingParams>(inst, query)?;
+            let post_list = get_post_list(api, None, Some(&name.to_string()), Some(paging_params)).await?;
+            out.heading(1, &format!("/c/{}", name));
+            out.link(&format!("/{}/c/{}/info", inst, name), "More info");
+            out.blank();
+            post_list_body(&mut out, inst, &post_list.posts, &format!("/{}/c/{}", inst, name), paging_params, now);
+            out.link(&format!("/{}/c/{}/modlog", inst, name), "Modlog");
+        },
+        ["communities"] => {
+            let mut paging_params = params::<PagingParams>(inst, query)?;
+            paging_params.s.get_or_insert_with(|| "TopAll".to_string());
+            let communities = get_community_list(api, Some(&paging_params)).await?;
+            out.heading(1, &format!("Communities on {}", inst));
+            for community in &communities.communities {
+                community_entry(&mut out, inst, community);
+            }
+            out.blank();
+            pager(&mut out, &format!("/{}/communities", inst), &paging_pairs(&paging_params), paging_params.p);
+        },
+        ["search"] => {
+            let q = match query {
+                Some(q) => q,
+                None => return Ok(Response::Input("Search"))
+            };
+            // Answers to the input prompt arrive as the raw query, the pager links use parameters
+            let search_params = match web::Query::<SearchParams>::from_query(q) {
+                Ok(p) if p.q.as_ref().is_some_and(|q| !q.is_empty()) => p.into_inner(),
+                _ => {
+                    let q = decode(q);
+                    if q.trim().is_empty() {
+                        return Ok(Response::Input("Search"));
+                    }
+                    return Ok(Response::Redirect(format!("/{}/search?q={}",
+                        inst, form_urlencoded::byte_serialize(q.as_bytes()).collect::<String>())));
+                }
+            };
+            let search_res = search(api, &search_params).await?;
+            out.heading(1, &format!("Search for {}", search_params.q.as_deref().unwrap_or_default()));
+            if !search_res.posts.is_empty() {
+                out.heading(2, "Posts");
+                for post in &search_res.posts {
+                    post_entry(&mut out, inst, post, now);
+                }
+            }
+            if !search_res.comments.is_empty() {
+                out.heading(2, "Comments");
+                for comment in &search_res.comments {
+                    comment_entry(&mut out, inst, comment, 0, true, now);
+                }
+            }
+            if !search_res.communities.is_empty() {
+                out.heading(2, "Communities");
+                for community in &search_res.communities {
+                    community_entry(&mut out, inst, community);
+                }
+                out.blank();
+            }
+            if !search_res.users.is_empty() {
+                out.heading(2, "Users");
+                for user in &search_res.users {
+                    user_entry(&mut out, inst, user);
+                }
+                out.blank();
+            }
+            let mut pairs = paging_pairs(&search_params.to_paging_params());
+            pairs.push(("q", search_params.q.clone().unwrap_or_default()));
+            if let Some(ref t) = search_params.t {
+                pairs.push(("t", t.clone()));
+            }
+            pager(&mut out, &format!("/{}/search", inst), &pairs, search_params.p);
+            out.link(&format!("/{}/search", inst), "New search");
+        },
+        ["modlog"] => {
+            let paging_params = &params::<PagingParams>(inst, query)?;
+            modlog_body(&mut out, api, inst, None, paging_params, now).await?;
+        },
+        ["c", name, "modlog"] => {
+            let paging_params = &params::<PagingParams>(inst, query)?;
+            modlog_body(&mut out, api, inst, Some(name), paging_params, now).await?;
+        },
+        ["c", name, "info"] => {
+            let community_detail = get_community(api, name).await?;
+            let community = &community_detail.community;
+            out.heading(1, &community.title);
+            out.line(&format!("/c/{} • {} subscribers • {} posts • {} comments", community.name,
+                community.number_of_subscribers, community.number_of_posts, community.number_of_comments));
+            out.link(&format!("/{}/c/{}", inst, community.name), "Posts");
+            out.blank();
+            if let Some(ref description) = community.description {
+                out.markdown(inst, description, "");
+            }
+            out.heading(2, "Moderators");
+            for moderator in &community_detail.moderators {
+                out.link(&format!("/{}/u/{}", inst, moderator.user_name), &moderator.user_name);
+            }
+        },
+        ["u", name] => {
+            let paging_params = &params::<PagingParams>(inst, query)?;
+            let user_detail = get_user(api, name, false, Some(paging_params)).await?;
+            let user = &user_detail.user;
+            out.heading(1, user.display_name.as_ref().filter(|n| !n.is_empty()).unwrap_or(&user.name));
+            out.line(&format!("{} • {} post points • {} comment points • joined {} ago",
+                user.name, user.post_score, user.comment_score, simple_duration(now, user.published)));
+            out.blank();
+            if let Some(ref bio) = user.bio {
+                out.markdown(inst, bio, "");
+            }
+            if !user_detail.posts.is_empty() {
+                out.heading(2, "Posts");
+                for post in &user_detail.posts {
+                    post_entry(&mut out, inst, post, now);
+                }
+            }
+            if !user_detail.comments.is_empty() {
+                out.heading(2, "Comments");
+                for comment in &user_detail.comments {
+                    comment_entry(&mut out, inst, comment, 0, true, now);
+                }
+            }
+            pager(&mut out, &format!("/{}/u/{}", inst, name), &paging_pairs(paging_params), paging_params.p);
+        },
+        ["post", id] => {
+            let post_detail = get_post(api, id).await?;
+            let post = &post_detail.post;
+            post_header(&mut out, inst, post, now);
+            if let Some(ref body) = post.body {
+                out.markdown(inst, body, "");
+            }
+            out.heading(2, &format!("{} comments", post.number_of_comments));
+            comment_tree(&mut out, inst, &post_detail.comments, None, 0, now);
+        },
+        ["post", id, "comment", comment_id] => {
+            let post_detail = get_post(api, id).await?;
+            let comment_id = parse_id(inst, comment_id, "comment")?;
+            let comment = post_detail.comments.iter().find(|c| c.id == comment_id).ok_or_else(|| LiteError::NotFound {
+                instance: Some(inst.clone()),
+                detail: format!("Comment {} doesn't belong to post {}", comment_id, id)
+            })?;
+            out.heading(1, &post_detail.post.name);
+            out.link(&format!("/{}/post/{}", inst, id), "All comments");
+            if let Some(parent_id) = comment.parent_id {
+                out.link(&format!("/{}/post/{}/comment/{}", inst, id, parent_id), "Parent comment");
+            }
+            out.blank();
+            comment_entry(&mut out, inst, comment, 0, false, now);
+            comment_tree(&mut out, inst, &post_detail.comments, Some(comment.id), 1, now);
+        },
+        _ => return Err(unknown_page(inst))
+    }
+    Ok(Response::Success(out.0))
+}
+
+fn post_list_body(out: &mut Gemtext, inst: &str, posts: &[PostView], path: &str, paging_params: &PagingParams, now: &NaiveDateTime) {
+    for post in posts {
+        post_entry(out, inst, post, now);
+    }
+    pager(out, path, &paging_pairs(paging_params), paging_params.p);
+    out.heading(2, "Sort");
+    for (sort, label) in SORTS {
+        let mut pairs = paging_pairs(paging_params);
+        pairs.retain(|(k, _)| *k != "s");
+        pairs.push(("s", sort.to_string()));
+        out.link(&format!("{}?{}", path, query_string(&pairs)), label);
+    }
+    out.blank();
+}
+
+async fn modlog_body(out: &mut Gemtext, api: &Api<'_>, inst: &str, community: Option<&str>,
+    paging_params: &PagingParams, now: &NaiveDateTime) -> Result<(), LiteError> {
+    // The modlog is filtered by community id, not name
+    let community_id = match community {
+        Some(name) => Some(get_community(api, name).await?.community.id),
+        None => None
+    };
+    let modlog = get_modlog(api, community_id, Some(paging_params)).await?;
+    let path = match community {
+        Some(name) => {
+            out.heading(1, &format!("Modlog of /c/{}", name));
+            format!("/{}/c/{}/modlog", inst, name)
+        },
+        None => {
+            out.heading(1, &format!("Modlog of {}", inst));
+            format!("/{}/modlog", inst)
+        }
+    };
+    for entry in &modlog.entries {
+        let mut line = format!("{} ago • ", simple_duration(now, entry.when));
+        if let Some(ref name) = entry.moderator_name {
+            line.push_str(&format!("{} • ", name));
+        }
+        line.push_str(entry.action.label());
+        if let Some(ref name) = entry.community_name {
+            line.push_str(&format!(" in /c/{}", name));
+        }
+        out.line(&line);
+        match entry.target {
+            ModlogTarget::Post { id, ref name } => out.link(&format!("/{}/post/{}", inst, id), name),
+            ModlogTarget::Comment { id, post_id, ref content } => out.link(&format!("/{}/post/{}/comment/{}", inst, post_id, id), content),
+            ModlogTarget::User { ref name } => out.link(&format!("/{}/u/{}", inst, name), name)
+        }
+        if let Some(ref reason) = entry.reason {
+            out.line(&format!("Reason: {}", reason));
+        }
+        out.blank();
+    }
+    pager(out, &path, &paging_pairs(paging_params), paging_params.p);
+    Ok(())
+}
+
+fn post_header(out: &mut Gemtext, inst: &str, post: &PostView, now: &NaiveDateTime) {
+    out.heading(1, &post.name);
+    out.line(&format!("{} points • {} ago{}", post.score, simple_duration(now, post.published),
+        badges(post.deleted, post.removed, post.locked)));
+    out.link(&format!("/{}/u/{}", inst, post.creator_name), &format!("by {}", post.creator_name));
+    out.link(&format!("/{}/c/{}", inst, post.community_name), &format!("to /c/{}", post.community_name));
+    if let Some(url) = post.url.as_ref().filter(|u| is_safe_url(u)) {
+        out.link(url, url);
+    }
+    out.blank();
+}
+
+fn post_entry(out: &mut Gemtext, inst: &str, post: &PostView, now: &NaiveDateTime) {
+    let title = if post.stickied { format!("📌 {}", post.name) } else { post.name.clone() };
+    out.link(&format!("/{}/post/{}", inst, post.id), &title);
+    out.line(&format!("{} points • {} comments • by {} to /c/{} • {} ago{}", post.score, post.number_of_comments,
+        post.creator_name, post.community_name, simple_duration(now, post.published),
+        badges(post.deleted, post.removed, post.locked)));
+    if let Some(url) = post.url.as_ref().filter(|u| is_safe_url(u)) {
+        out.link(url, url);
+    }
+    out.blank();
+}
+
+fn community_entry(out: &mut Gemtext, inst: &str, community: &CommunityView) {
+    out.link(&format!("/{}/c/{}", inst, community.name), &format!("{} • /c/{} • {} subscribers",
+        community.title, community.name, community.number_of_subscribers));
+}
+
+fn user_entry(out: &mut Gemtext, inst: &str, user: &UserView) {
+    out.link(&format!("/{}/u/{}", inst, user.name), &format!("{} • {} post points • {} comment points",
+        user.name, user.post_score, user.comment_score));
+}
+
+// The header links to the comment's own page, the content is indented with its depth
+fn comment_entry(out: &mut Gemtext, inst: &str, comment: &CommentView, depth: usize, community: bool, now: &NaiveDateTime) {
+    let indent = INDENT.repeat(depth);
+    let mut header = format!("{}{}", indent, comment.creator_name);
+    if community {
+        header.push_str(&format!(" in /c/{}", comment.community_name));
+    }
+    header.push_str(&format!(" • {} points • {} ago{}", comment.score, simple_duration(now, comment.published),
+        badges(comment.deleted, comment.removed, false)));
+    out.link(&format!("/{}/post/{}/comment/{}", inst, comment.post_id, comment.id), &header);
+    out.markdown(inst, &comment.content, &indent);
+}
+
+fn comment_tree(out: &mut Gemtext, inst: &str, comments: &[CommentView], parent_id: Option<i32>, depth: i32, now: &NaiveDateTime) {
+    let trees = templates::comment_tree(comments, parent_id, depth, &mut |comment, depth, children: Vec<String>| {
+        let mut entry = Gemtext::default();
+        comment_entry(&mut entry, inst, comment, depth as usize, false, now);
+        entry.0 + &children.concat()
+    });
+    out.0.push_str(&trees.concat());
+}
+
+// Prev and Next links, keeping the other parameters of the page
+fn pager(out: &mut Gemtext, path: &str, pairs: &[(&str, String)], page: Option<i32>) {
+    let (prev, next) = pager_urls(path, pairs, page);
+    if let Some(prev) = prev {
+        out.link(&prev, "Previous page");
+    }
+    out.link(&next, "Next page");
+}
+
+fn params<T: DeserializeOwned>(inst: &str, query: Option<&str>) -> Result<T, LiteError> {
+    web::Query::<T>::from_query(query.unwrap_or_default())
+        .map(web::Query::into_inner)
+        .map_err(|e| LiteError::InvalidPath {
+            instance: Some(inst.to_string()),
+            detail: e.to_string()
+        })
+}
+
+fn badges(deleted: bool, removed: bool, locked: bool) -> String {
+    let mut badges = String::new();
+    for (set, name) in &[(deleted, "deleted"), (removed, "removed"), (locked, "locked")] {
+        if *set {
+            badges.push_str(" • ");
+            badges.push_str(name);
+        }
+    }
+    badges
+}
+
+fn decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+fn single_line(text: &str) -> String {
+    text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+// Gemtext document, every line type is decided by how the line starts
+#[derive(Default)]
+struct Gemtext(String);
+
+impl Gemtext {
+    fn push(&mut self, line: &str) {
+        self.0.push_str(line);
+        self.0.push('\n');
+    }
+
+    fn blank(&mut self) {
+        self.0.push('\n');
+    }
+
+    // Text that could be read as another line type is shifted by a space
+    fn line(&mut self, text: &str) {
+        let text = single_line(text);
+        if ["=>", "```", "#", "*", ">"].iter().any(|p| text.starts_with(p)) {
+            self.push(&format!(" {}", text));
+        } else {
+            self.push(&text);
+        }
+    }
+
+    fn heading(&mut self, level: usize, text: &str) {
+        self.push(&format!("{} {}", "#".repeat(level.clamp(1, 3)), single_line(text)));
+    }
+
+    fn link(&mut self, url: &str, label: &str) {
+        let url: String = url.chars().filter(|c| !c.is_control()).collect::<String>().replace(' ', "%20");
+        self.push(&format!("=> {} {}", url, single_line(label)));
+    }
+
+    // Markdown to gemtext, links follow the block they are in since gemtext has no inline links
+    fn markdown(&mut self, inst: &str, text: &str, indent: &str) {
+        let mut md = Markdown {
+            out: self,
+            indent,
+            line: String::new(),
+            prefix: "",
+            quote: 0,
+            lists: 0,
+            links: Vec::new()
+        };
+        let mut link_starts: Vec<(usize, Option<String>)> = Vec::new();
+        let mut code = false;
+        for event in Parser::new(text) {
+            match event {
+                Event::Start(Tag::Paragraph) => md.flush(),
+                Event::End(Tag::Paragraph) => {
+                    md.end_block();
+                    if md.lists == 0 {
+                        md.out.blank();
+                    }
+                },
+                Event::Start(Tag::Heading(level)) => {
+                    md.flush();
+                    md.prefix = ["# ", "## ", "### "][(level as usize).clamp(1, 3) - 1];
+                },
+                Event::End(Tag::Heading(_)) => md.end_block(),
+                Event::Start(Tag::BlockQuote) => {
+                    md.flush();
+                    md.quote += 1;
+                },
+                Event::End(Tag::BlockQuote) => {
+                    md.end_block();
+                    md.quote -= 1;
+                },
+                Event::Start(Tag::List(_)) => {
+                    md.flush();
+                    md.lists += 1;
+                },
+                Event::End(Tag::List(_)) => {
+                    md.end_block();
+                    md.lists -= 1;
+                    if md.lists == 0 {
+                        md.out.blank();
+                    }
+                },
+                Event::Start(Tag::Item) => {
+                    md.flush();
+                    md.prefix = "* ";
+                },
+                Event::End(Tag::Item) => md.end_block(),
+                Event::Start(Tag::CodeBlock(_)) => {
+                    md.flush();
+                    md.out.push("```");
+                    code = true;
+                },
+                Event::End(Tag::CodeBlock(_)) => {
+                    md.out.push("```");
+                    code = false;
+                    md.end_block();
+                    md.out.blank();
+                },
+                Event::Start(Tag::Link(linktype, url, _)) | Event::Start(Tag::Image(linktype, url, _)) => {
+                    link_starts.push((md.line.len(), markdown_link(inst, linktype, &url)));
+                },
+                Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                    if let Some((start, Some(url))) = link_starts.pop() {
+                        let label = md.line.get(start..).map(str::trim).filter(|l| !l.is_empty()).unwrap_or(&url).to_string();
+                        md.links.push((url, label));
+                    }
+                },
+                Event::Text(text) if code => {
+                    for line in text.lines() {
+                        let line = format!("{}{}", indent, single_line(line));
+                        md.out.push(&if line.starts_with("```") { format!(" {}", line) } else { line });
+                    }
+                },
+                Event::Text(text) | Event::Code(text) | Event::Html(text) => md.line.push_str(&text),
+                Event::SoftBreak => md.line.push(' '),
+                Event::HardBreak => md.flush(),
+                Event::Rule => {
+                    md.flush();
+                    md.line.push_str("──────────");
+                    md.end_block();
+                },
+                _ => ()
+            }
+        }
+        md.end_block();
+    }
+}
+
+struct Markdown<'a> {
+    out: &'a mut Gemtext,
+    indent: &'a str,
+    line: String,
+    prefix: &'static str,   // Heading or list item marker of the current block
+    quote: usize,
+    lists: usize,
+    links: Vec<(String, String)>
+}
+
+impl Markdown<'_> {
+    // Writes the pending text as a line, line types only apply without indentation
+    fn flush(&mut self) {
+        let text = self.line.trim().to_string();
+        self.line.clear();
+        if text.is_empty() {
+            return;
+        }
+        let quote = if self.quote > 0 { "> " } else { "" };
+        if self.indent.is_empty() && quote.is_empty() && self.prefix.is_empty() {
+            self.out.line(&text);
+        } else {
+            self.out.push(&format!("{}{}{}{}", self.indent, quote, self.prefix, single_line(&text)));
+        }
+        self.prefix = "";
+    }
+
+    fn end_block(&mut self) {
+        self.flush();
+        for (url, label) in self.links.drain(..) {
+            self.out.link(&url, &label);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gemtext(markdown: &str) -> String {
+        let mut out = Gemtext::default();
+        out.markdown("lemmy.ml", markdown, "");
+        out.0
+    }
+
+    #[test]
+    fn request_needs_crlf() {
+        assert!(parse_request(b"gemini://host/lemmy.ml\r\n").is_ok());
+        assert!(matches!(parse_request(b"gemini://host/lemmy.ml\n"), Err(Response::Failure(59, _))));
+        assert!(matches!(parse_request(b"gemini://host/lemmy.ml"), Err(Response::Failure(59, _))));
+        assert!(matches!(parse_request(b"\r\n"), Err(Response::Failure(59, _))));
+    }
+
+    #[test]
+    fn request_longer_than_1024_bytes_is_cut_before_its_crlf() {
+        let url = format!("gemini://host/{}", "a".repeat(1024 - 14));
+        assert_eq!(url.len(), 1024);
+        assert!(parse_request(format!("{}\r\n", url).as_bytes()).is_ok());
+        let line = format!("{}a\r\n", url);
+        assert!(matches!(parse_request(&line.as_bytes()[..MAX_REQUEST as usize]), Err(Response::Failure(59, _))));
+    }
+
+    #[test]
+    fn request_must_be_a_gemini_url() {
+        assert!(matches!(parse_request(b"https://host/lemmy.ml\r\n"), Err(Response::Failure(53, _))));
+        assert!(matches!(parse_request(b"/lemmy.ml\r\n"), Err(Response::Failure(59, _))));
+        assert!(matches!(parse_request(b"gemini://host/\xff\r\n"), Err(Response::Failure(59, _))));
+    }
+
+    #[test]
+    fn links_follow_their_block() {
+        assert_eq!(gemtext("See [the docs](https://example.com/docs) and <https://rust-lang.org>.\n\nNext"),
+            "See the docs and https://rust-lang.org.\n\
+            => https://example.com/docs the docs\n\
+            => https://rust-lang.org https://rust-lang.org\n\
+            \n\
+            Next\n\
+            \n");
+    }
+
+    #[test]
+    fn relative_links_are_resolved() {
+        assert_eq!(gemtext("[rust](/c/rust) [bob](/u/bob) ![cat](/pictrs/image/cat.png)"),
+            "rust bob cat\n\
+            => /lemmy.ml/c/rust rust\n\
+            => /lemmy.ml/u/bob bob\n\
+            => https://lemmy.ml/pictrs/image/cat.png cat\n\
+            \n");
+    }
+
+    #[test]
+    fn unsafe_links_are_dropped() {
+        assert_eq!(gemtext("[click](javascript:alert(1))"), "click\n\n");
+    }
+
+    #[test]
+    fn text_never_starts_another_line_type() {
+        assert_eq!(gemtext("\\# not a heading\n\n\\* not an item\n\n=> not a link\n\n\\> not a quote"),
+            " # not a heading\n\n * not an item\n\n => not a link\n\n > not a quote\n\n");
+        assert_eq!(gemtext("    ```\n    code"), "```\n ```\ncode\n```\n\n");
+        assert_eq!(gemtext("    clear\u{1b}[2J\u{9b}2J"), "```\nclear [2J 2J\n```\n\n");
+        let mut out = Gemtext::default();
+        out.link("/lemmy.ml/post/1", "two\nlines");
+        out.heading(1, "# title\r\n=> x");
+        assert_eq!(out.0, "=> /lemmy.ml/post/1 two lines\n# # title  => x\n");
+    }
+}
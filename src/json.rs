@@ -0,0 +1,540 @@
+/*
+JSON pages
+
+?fmt=json returns the data of a page in lemmy-lite's own schema, the same whichever API version the instance speaks.
+Every response has the schema version and the instance, then the fields of the page, see the JSON section of the README.
+Fields are only added within a version, renaming or removing one bumps VERSION.
+*/
+
+use chrono::naive::NaiveDateTime;
+use serde::Serialize;
+use actix_web::{HttpResponse, ResponseError, dev::ServiceResponse, http::{StatusCode, header}};
+use crate::error::LiteError;
+use crate::lemmy_api::{PostView, PostDetail, CommentView, CommunityView, CommunityList, CommunityDetail, CommunityModeratorView,
+    UserView, UserDetail, SearchResponse, Modlog, ModlogEntry, ModlogTarget, ModAction, Subscription};
+use crate::templates::{comment_tree, rfc3339, simple_duration};
+
+pub const VERSION: u32 = 1;
+
+pub fn wants_json(query: &str) -> bool {
+    query.split('&').any(|p| p == "fmt=json")
+}
+
+#[derive(Serialize)]
+struct Page<'a, T: Serialize> {
+    version: u32,
+    instance: &'a str,
+    #[serde(flatten)]
+    data: T
+}
+
+pub fn page<T: Serialize>(instance: &str, data: T) -> Result<HttpResponse, LiteError> {
+    let body = serde_json::to_string(&Page { version: VERSION, instance, data })
+        .map_err(|e| LiteError::Internal { instance: instance.to_string(), detail: e.to_string() })?;
+    Ok(HttpResponse::Ok().content_type("application/json").body(body))
+}
+
+#[derive(Serialize)]
+struct Error<'a> {
+    version: u32,
+    instance: Option<&'a str>,
+    error: ErrorDetail<'a>
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    status: u16,
+    title: &'a str,
+    message: String
+}
+
+// Scripts get errors as JSON too, a page without a JSON form is one of them
+pub fn error_response<B>(res: ServiceResponse<B>) -> ServiceResponse<B> {
+    let headers = res.response().headers();
+    let json = headers.get(header::CONTENT_TYPE).is_some_and(|t| t == "application/json");
+    let instance = res.request().path().split('/').nth(1).filter(|i| !i.is_empty()).map(str::to_string);
+    // There is no login form to redirect a script to
+    let login_required = instance.clone()
+        .filter(|_| headers.get(header::LOCATION).is_some_and(|l| l.as_bytes().ends_with(b"/login")))
+        .map(|instance| LiteError::LoginRequired { instance });
+    let (status, title, message, instance) = match res.response().error().and_then(|e| e.as_error::<LiteError>()).or(login_required.as_ref()) {
+        Some(e) => (e.status_code(), e.title(), e.to_string(), e.instance().map(str::to_string)),
+        None if json => return res,
+        None => (StatusCode::NOT_ACCEPTABLE, "No JSON form", "This page is only available as HTML.".to_string(), instance)
+    };
+    let body = serde_json::to_string(&Error {
+        version: VERSION,
+        instance: instance.as_deref(),
+        error: ErrorDetail { status: status.as_u16(), title, message }
+    }).unwrap_or_default();
+    res.into_response(HttpResponse::build(status).content_type("application/json").body(body).into_body())
+}
+
+#[derive(Serialize)]
+pub struct Post<'a> {
+    id: i32,
+    title: &'a str,
+    url: Option<&'a str>,
+    body: Option<&'a str>,      // Markdown
+    creator: &'a str,
+    community: &'a str,
+    score: i32,
+    upvotes: i32,
+    downvotes: i32,
+    comments: i32,
+    published: String,
+    age: String,
+    pinned: bool,
+    locked: bool,
+    nsfw: bool,
+    deleted: bool,
+    removed: bool,
+    my_vote: Option<i32>,       // Null when logged out
+    saved: Option<bool>
+}
+
+impl<'a> Post<'a> {
+    pub fn new(post: &'a PostView, now: &NaiveDateTime) -> Self {
+        Post {
+            id: post.id,
+            title: &post.name,
+            url: post.url.as_deref(),
+            body: post.body.as_deref(),
+            creator: &post.creator_name,
+            community: &post.community_name,
+            score: post.score,
+            upvotes: post.upvotes,
+            downvotes: post.downvotes,
+            comments: post.number_of_comments,
+            published: rfc3339(post.published),
+            age: simple_duration(now, post.published),
+            pinned: post.stickied,
+            locked: post.locked,
+            nsfw: post.nsfw,
+            deleted: post.deleted,
+            removed: post.removed,
+            my_vote: post.my_vote,
+            saved: post.saved
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Comment<'a> {
+    id: i32,
+    post_id: i32,
+    parent_id: Option<i32>,
+    creator: &'a str,
+    community: &'a str,
+    content: &'a str,           // Markdown
+    score: i32,
+    upvotes: i32,
+    downvotes: i32,
+    published: String,
+    age: String,
+    deleted: bool,
+    removed: bool,
+    my_vote: Option<i32>,
+    saved: Option<bool>
+}
+
+impl<'a> Comment<'a> {
+    pub fn new(comment: &'a CommentView, now: &NaiveDateTime) -> Self {
+        Comment {
+            id: comment.id,
+            post_id: comment.post_id,
+            parent_id: comment.parent_id,
+            creator: &comment.creator_name,
+            community: &comment.community_name,
+            content: &comment.content,
+            score: comment.score,
+            upvotes: comment.upvotes,
+            downvotes: comment.downvotes,
+            published: rfc3339(comment.published),
+            age: simple_duration(now, comment.published),
+            deleted: comment.deleted,
+            removed: comment.removed,
+            my_vote: comment.my_vote,
+            saved: comment.saved
+        }
+    }
+}
+
+// A comment with its replies, nested and highlighted like on the HTML page
+#[derive(Serialize)]
+pub struct Thread<'a> {
+    #[serde(flatten)]
+    comment: Comment<'a>,
+    depth: i32,
+    by_post_creator: bool,
+    highlighted: bool,
+    replies: Vec<Thread<'a>>
+}
+
+fn threads<'a>(comments: &'a [CommentView], post_creator_id: i32, parent_id: Option<i32>, highlight_id: Option<i32>,
+    now: &NaiveDateTime) -> Vec<Thread<'a>> {
+    comment_tree(comments, parent_id, 0, &mut |comment, depth, replies| Thread {
+        comment: Comment::new(comment, now),
+        depth,
+        by_post_creator: comment.creator_id == post_creator_id,
+        highlighted: Some(comment.id) == highlight_id,
+        replies
+    })
+}
+
+#[derive(Serialize)]
+pub struct Community<'a> {
+    id: i32,
+    name: &'a str,
+    title: &'a str,
+    description: Option<&'a str>,   // Markdown
+    subscribers: i32,
+    posts: i32,
+    comments: i32,
+    published: String,
+    nsfw: bool,
+    deleted: bool,
+    removed: bool,
+    subscribed: Option<bool>,           // Null when logged out
+    subscription_pending: Option<bool>  // Waiting for a remote instance to accept the follow
+}
+
+impl<'a> Community<'a> {
+    pub fn new(community: &'a CommunityView) -> Self {
+        Community {
+            id: community.id,
+            name: &community.name,
+            title: &community.title,
+            description: community.description.as_deref(),
+            subscribers: community.number_of_subscribers,
+            posts: community.number_of_posts,
+            comments: community.number_of_comments,
+            published: rfc3339(community.published),
+            nsfw: community.nsfw,
+            deleted: community.deleted,
+            removed: community.removed,
+            subscribed: community.subscribed.map(|s| s == Subscription::Subscribed),
+            subscription_pending: community.subscribed.map(|s| s == Subscription::Pending)
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct User<'a> {
+    id: i32,
+    name: &'a str,
+    display_name: Option<&'a str>,
+    avatar: Option<&'a str>,
+    bio: Option<&'a str>,           // Markdown
+    posts: i32,
+    post_score: i32,
+    comments: i32,
+    comment_score: i32,
+    published: String,
+    age: String,
+    admin: bool,
+    banned: bool
+}
+
+impl<'a> User<'a> {
+    pub fn new(user: &'a UserView, now: &NaiveDateTime) -> Self {
+        User {
+            id: user.id,
+            name: &user.name,
+            display_name: user.display_name.as_deref(),
+            avatar: user.avatar.as_deref(),
+            bio: user.bio.as_deref(),
+            posts: user.number_of_posts,
+            post_score: user.post_score,
+            comments: user.number_of_comments,
+            comment_score: user.comment_score,
+            published: rfc3339(user.published),
+            age: simple_duration(now, user.published),
+            admin: user.admin,
+            banned: user.banned
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Moderator<'a> {
+    id: i32,
+    name: &'a str
+}
+
+fn moderators(moderators: &[CommunityModeratorView]) -> Vec<Moderator<'_>> {
+    moderators.iter().map(|m| Moderator { id: m.user_id, name: &m.user_name }).collect()
+}
+
+#[derive(Serialize)]
+pub struct PostListPage<'a> {
+    community: Option<&'a str>,     // Null on the front page
+    page: i32,
+    posts: Vec<Post<'a>>
+}
+
+pub fn post_list_page<'a>(community: Option<&'a str>, posts: &'a [PostView], page: Option<i32>, now: &NaiveDateTime) -> PostListPage<'a> {
+    PostListPage {
+        community,
+        page: page.unwrap_or(1),
+        posts: posts.iter().map(|p| Post::new(p, now)).collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct PostPage<'a> {
+    post: Post<'a>,
+    moderators: Vec<Moderator<'a>>,
+    comments: Vec<Thread<'a>>
+}
+
+pub fn post_page<'a>(post_detail: &'a PostDetail, now: &NaiveDateTime) -> PostPage<'a> {
+    PostPage {
+        post: Post::new(&post_detail.post, now),
+        moderators: moderators(&post_detail.moderators),
+        comments: threads(&post_detail.comments, post_detail.post.creator_id, None, None, now)
+    }
+}
+
+// comments is the comment's context, its parent and direct replies, highlighted as on its HTML page
+pub fn comment_page<'a>(post_detail: &'a PostDetail, comments: &'a [CommentView], root_id: Option<i32>, comment_id: i32,
+    now: &NaiveDateTime) -> PostPage<'a> {
+    PostPage {
+        post: Post::new(&post_detail.post, now),
+        moderators: moderators(&post_detail.moderators),
+        comments: threads(comments, post_detail.post.creator_id, root_id, Some(comment_id), now)
+    }
+}
+
+#[derive(Serialize)]
+pub struct UserPage<'a> {
+    user: User<'a>,
+    moderates: Vec<&'a str>,
+    saved: bool,                    // Posts and comments are the ones saved by the user
+    page: i32,
+    posts: Vec<Post<'a>>,
+    comments: Vec<Comment<'a>>
+}
+
+pub fn user_page<'a>(user: &'a UserDetail, saved: bool, page: Option<i32>, now: &NaiveDateTime) -> UserPage<'a> {
+    UserPage {
+        user: User::new(&user.user, now),
+        moderates: user.moderates.iter().map(|m| m.community_name.as_str()).collect(),
+        saved,
+        page: page.unwrap_or(1),
+        posts: user.posts.iter().map(|p| Post::new(p, now)).collect(),
+        comments: user.comments.iter().map(|c| Comment::new(c, now)).collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct CommunitiesPage<'a> {
+    page: i32,
+    communities: Vec<Community<'a>>
+}
+
+pub fn communities_page(community_list: &CommunityList, page: Option<i32>) -> CommunitiesPage<'_> {
+    CommunitiesPage {
+        page: page.unwrap_or(1),
+        communities: community_list.communities.iter().map(Community::new).collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct CommunityPage<'a> {
+    community: Community<'a>,
+    moderators: Vec<Moderator<'a>>
+}
+
+pub fn community_page(community_detail: &CommunityDetail) -> CommunityPage<'_> {
+    CommunityPage {
+        community: Community::new(&community_detail.community),
+        moderators: moderators(&community_detail.moderators)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SearchPage<'a> {
+    query: &'a str,
+    page: i32,
+    communities: Vec<Community<'a>>,
+    users: Vec<User<'a>>,
+    posts: Vec<Post<'a>>,
+    comments: Vec<Comment<'a>>
+}
+
+// An empty query has empty results, as on the HTML page
+pub fn search_page<'a>(query: &'a str, search_res: Option<&'a SearchResponse>, page: Option<i32>, now: &NaiveDateTime) -> SearchPage<'a> {
+    SearchPage {
+        query,
+        page: page.unwrap_or(1),
+        communities: search_res.map_or(Vec::new(), |r| r.communities.iter().map(Community::new).collect()),
+        users: search_res.map_or(Vec::new(), |r| r.users.iter().map(|u| User::new(u, now)).collect()),
+        posts: search_res.map_or(Vec::new(), |r| r.posts.iter().map(|p| Post::new(p, now)).collect()),
+        comments: search_res.map_or(Vec::new(), |r| r.comments.iter().map(|c| Comment::new(c, now)).collect())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Target<'a> {
+    Post { id: i32, title: &'a str },
+    Comment { id: i32, post_id: i32, content: &'a str },
+    User { name: &'a str }
+}
+
+#[derive(Serialize)]
+struct Action<'a> {
+    action: &'static str,           // snake_case kind, ex: remove_post
+    moderator: Option<&'a str>,     // Null when the instance hides moderators
+    target: Target<'a>,
+    community: Option<&'a str>,     // Null for site bans
+    reason: Option<&'a str>,
+    published: String,
+    age: String
+}
+
+impl<'a> Action<'a> {
+    fn new(entry: &'a ModlogEntry, now: &NaiveDateTime) -> Self {
+        Action {
+            action: action_name(entry.action),
+            moderator: entry.moderator_name.as_deref(),
+            target: match entry.target {
+                ModlogTarget::Post { id, ref name } => Target::Post { id, title: name },
+                ModlogTarget::Comment { id, post_id, ref content } => Target::Comment { id, post_id, content },
+                ModlogTarget::User { ref name } => Target::User { name }
+            },
+            community: entry.community_name.as_deref(),
+            reason: entry.reason.as_deref(),
+            published: rfc3339(entry.when),
+            age: simple_duration(now, entry.when)
+        }
+    }
+}
+
+// Part of the schema, unlike the labels of the HTML modlog which can be reworded
+fn action_name(action: ModAction) -> &'static str {
+    match action {
+        ModAction::RemovePost => "remove_post",
+        ModAction::RestorePost => "restore_post",
+        ModAction::LockPost => "lock_post",
+        ModAction::UnlockPost => "unlock_post",
+        ModAction::StickyPost => "sticky_post",
+        ModAction::UnstickyPost => "unsticky_post",
+        ModAction::RemoveComment => "remove_comment",
+        ModAction::RestoreComment => "restore_comment",
+        ModAction::Ban => "ban_from_community",
+        ModAction::Unban => "unban_from_community",
+        ModAction::SiteBan => "ban_from_site",
+        ModAction::SiteUnban => "unban_from_site"
+    }
+}
+
+#[derive(Serialize)]
+pub struct ModlogPage<'a> {
+    community: Option<&'a str>,     // Null for the whole instance
+    page: i32,
+    actions: Vec<Action<'a>>
+}
+
+pub fn modlog_page<'a>(modlog: &'a Modlog, community: Option<&'a str>, page: Option<i32>, now: &NaiveDateTime) -> ModlogPage<'a> {
+    ModlogPage {
+        community,
+        page: page.unwrap_or(1),
+        actions: modlog.entries.iter().map(|e| Action::new(e, now)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn post() -> PostView {
+        PostView {
+            id: 1, name: "Hello".to_string(), url: None, body: Some("**hi**".to_string()),
+            creator_id: 7, creator_name: "alice".to_string(), community_id: 2, community_name: "rust".to_string(),
+            removed: false, locked: true, deleted: false, nsfw: false, stickied: true, published: time("2023-06-01 12:00"),
+            banned_from_community: false, number_of_comments: 2, score: 5, upvotes: 6, downvotes: 1, my_vote: None, saved: None
+        }
+    }
+
+    fn comment(id: i32, parent_id: Option<i32>, creator_id: i32) -> CommentView {
+        CommentView {
+            id, creator_id, creator_name: "bob".to_string(), post_id: 1, parent_id, community_id: 2,
+            community_name: "rust".to_string(), content: "text".to_string(), removed: false, deleted: false,
+            published: time("2023-06-01 13:00"), banned_from_community: false, score: 1, upvotes: 1, downvotes: 0,
+            my_vote: Some(1), saved: Some(false)
+        }
+    }
+
+    fn to_json<T: Serialize>(data: T) -> Value {
+        serde_json::to_value(Page { version: VERSION, instance: "lemmy.ml", data }).unwrap()
+    }
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys: Vec<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn post_page_has_the_documented_fields() {
+        let post_detail = PostDetail {
+            post: post(),
+            comments: vec![comment(10, None, 7), comment(11, Some(10), 8)],
+            moderators: vec![CommunityModeratorView { community_name: "rust".to_string(), user_id: 3, user_name: "mod".to_string() }]
+        };
+        let page = to_json(post_page(&post_detail, &time("2023-06-01 14:00")));
+        assert_eq!(keys(&page), ["comments", "instance", "moderators", "post", "version"]);
+        assert_eq!(page["version"], 1);
+        assert_eq!(keys(&page["post"]), ["age", "body", "comments", "community", "creator", "deleted", "downvotes", "id", "locked",
+            "my_vote", "nsfw", "pinned", "published", "removed", "saved", "score", "title", "upvotes", "url"]);
+        assert_eq!(page["post"]["published"], "2023-06-01T12:00:00Z");
+        assert_eq!(page["post"]["pinned"], true);
+        assert_eq!(page["post"]["my_vote"], Value::Null);
+        assert_eq!(page["moderators"], json!([{"id": 3, "name": "mod"}]));
+    }
+
+    #[test]
+    fn comments_nest_their_replies() {
+        let post_detail = PostDetail { post: post(), comments: vec![comment(10, None, 7), comment(11, Some(10), 8)], moderators: vec![] };
+        let page = to_json(post_page(&post_detail, &time("2023-06-01 14:00")));
+        let thread = &page["comments"][0];
+        assert_eq!(keys(thread), ["age", "by_post_creator", "community", "content", "creator", "deleted", "depth", "downvotes",
+            "highlighted", "id", "my_vote", "parent_id", "post_id", "published", "removed", "replies", "saved", "score", "upvotes"]);
+        assert_eq!((&thread["id"], &thread["depth"], &thread["by_post_creator"]), (&json!(10), &json!(0), &json!(true)));
+        let reply = &thread["replies"][0];
+        assert_eq!((&reply["id"], &reply["depth"], &reply["parent_id"]), (&json!(11), &json!(1), &json!(10)));
+        assert_eq!(reply["replies"], json!([]));
+        assert_eq!(page["comments"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn modlog_actions_are_machine_values() {
+        let modlog = Modlog {
+            entries: vec![
+                ModlogEntry {
+                    when: time("2023-06-01 12:00"), moderator_name: Some("mod".to_string()), action: ModAction::StickyPost,
+                    target: ModlogTarget::Post { id: 1, name: "Hello".to_string() }, community_name: Some("rust".to_string()), reason: None
+                },
+                ModlogEntry {
+                    when: time("2023-06-01 11:00"), moderator_name: None, action: ModAction::SiteBan,
+                    target: ModlogTarget::User { name: "spammer".to_string() }, community_name: None, reason: Some("spam".to_string())
+                }
+            ]
+        };
+        let page = to_json(modlog_page(&modlog, None, None, &time("2023-06-01 14:00")));
+        assert_eq!(keys(&page), ["actions", "community", "instance", "page", "version"]);
+        assert_eq!(page["actions"][0], json!({
+            "action": "sticky_post", "moderator": "mod", "target": {"type": "post", "id": 1, "title": "Hello"},
+            "community": "rust", "reason": null, "published": "2023-06-01T12:00:00Z", "age": "2h"
+        }));
+        assert_eq!(page["actions"][1]["action"], "ban_from_site");
+        assert_eq!(page["actions"][1]["target"], json!({"type": "user", "name": "spammer"}));
+    }
+}
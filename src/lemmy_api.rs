@@ -1,30 +1,85 @@
+/*
+Versioned Lemmy API layer
+
+Each supported API version lives in its own module with structs mirroring the upstream responses.
+Responses are converted into the version independent views below, which are what the templates consume.
+*/
+
+mod nodeinfo;
+mod v1;
+mod v3;
+
+pub use nodeinfo::{Instances, Software};
+
+use std::{cmp::Reverse, time::Duration};
 use chrono::naive::NaiveDateTime;
-use serde::Deserialize;
-use actix_web::{Result, client::Client, error::ErrorBadRequest};
-use url::{Url, ParseError};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use actix_web::{client::{Client, ClientRequest, ClientResponse}, http::{Method, StatusCode}, error::PayloadError, web::Bytes};
+use futures_util::{Stream, future::try_join};
+use ring::digest;
+use url::Url;
+use crate::cache::ApiCache;
+use crate::config;
+use crate::error::LiteError;
+use crate::session::Role;
 
-const REQ_MAX_SIZE: usize = 8388608; // 8MB limit
+// How long responses stay cached, comment threads move faster than communities
+const POST_LIST_TTL: Duration = Duration::from_secs(30);
+const POST_TTL: Duration = Duration::from_secs(15);
+const COMMUNITY_LIST_TTL: Duration = Duration::from_secs(600);
+const COMMUNITY_TTL: Duration = Duration::from_secs(300);
+const USER_TTL: Duration = Duration::from_secs(60);
+const SEARCH_TTL: Duration = Duration::from_secs(60);
+const UNREAD_TTL: Duration = Duration::from_secs(60);
+const SITE_TTL: Duration = Duration::from_secs(300);
+const MODLOG_TTL: Duration = Duration::from_secs(60);
 
-#[derive(Deserialize, Clone)]
-pub struct PagingParams {
-    pub s: Option<String>,  // Sort
-    pub p: Option<i32>,     // Page
-    pub l: Option<i32>      // Limit size
+// Conversations are read from the list of all messages, which the instances cap at 50 per page
+const MESSAGE_PAGE_SIZE: i32 = 50;    // Of all private messages, filtered down to one conversation
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ApiVersion {
+    V1, // Lemmy <= 0.9
+    V3  // Lemmy >= 0.17
 }
 
-impl PagingParams {
-    pub fn to_search_params(&self) -> SearchParams {
-        SearchParams {
-            q: None,
-            t: None,
-            c: None,
-            s: self.s.clone(),
-            p: self.p,
-            l: self.l
+impl ApiVersion {
+    fn path(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V3 => "v3"
         }
     }
 }
 
+// Everything needed to make a request to an instance, auth is the JWT of the logged in user
+pub struct Api<'a> {
+    client: &'a Client,
+    cache: &'a ApiCache,
+    instance: &'a str,
+    version: ApiVersion,
+    auth: Option<&'a str>
+}
+
+impl<'a> Api<'a> {
+    pub fn new(client: &'a Client, cache: &'a ApiCache, instance: &'a str, version: ApiVersion, auth: Option<&'a str>) -> Self {
+        Api { client, cache, instance, version, auth }
+    }
+
+    pub fn with_auth(&self, auth: &'a str) -> Self {
+        Api { auth: Some(auth), ..*self }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PagingParams {
+    pub s: Option<String>,  // Sort
+    pub p: Option<i32>,     // Page
+    pub l: Option<i32>,     // Limit size
+    pub t: Option<String>   // Listing type
+}
+
 #[derive(Deserialize, Clone)]
 pub struct SearchParams {
     pub q: Option<String>,  // Query
@@ -40,215 +95,421 @@ impl SearchParams {
         PagingParams {
             s: self.s.clone(),
             p: self.p,
-            l: self.l
+            l: self.l,
+            t: None
         }
     }
 }
 
-#[derive(Deserialize)]
 pub struct CommunityView {
     pub id: i32,
     pub name: String,
     pub title: String,
     pub description: Option<String>,
-    category_id: i32,
-    creator_id: i32,
-    removed: bool,
-    published: NaiveDateTime,
-    updated: Option<NaiveDateTime>,
-    deleted: bool,
-    nsfw: bool,
-    creator_name: String,
-    creator_avatar: Option<String>,
-    pub category_name: String,
+    pub nsfw: bool,
+    pub removed: bool,
+    pub deleted: bool,
+    pub published: NaiveDateTime,
+    pub category_name: Option<String>,  // v1 only
     pub number_of_subscribers: i32,
     pub number_of_posts: i32,
     pub number_of_comments: i32,
-    pub hot_rank: i32,
-    user_id: Option<i32>,
-    subscribed: Option<bool>
+    pub hot_rank: Option<i32>,
+    pub subscribed: Option<Subscription>
+}
+
+// Following a remote community is pending until its instance accepts it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Subscription {
+    Subscribed,
+    NotSubscribed,
+    Pending
 }
 
-#[derive(Deserialize)]
 pub struct CommunityList {
     pub communities: Vec<CommunityView>
 }
 
-#[derive(Deserialize)]
 pub struct PostView {
     pub id: i32,
     pub name: String,
     pub url: Option<String>,
     pub body: Option<String>,
     pub creator_id: i32,
-    community_id: i32,
-    removed: bool,
-    locked: bool,
-    pub published: NaiveDateTime,
-    updated: Option<String>,
-    deleted: bool,
-    nsfw: bool,
-    pub stickied: bool,
-    embed_title: Option<String>,
-    embed_description: Option<String>,
-    embed_html: Option<String>,
-    thumbnail_url: Option<String>,
-    banned: bool,
-    banned_from_community: bool,
     pub creator_name: String,
-    creator_avatar: Option<String>,
+    pub community_id: i32,
     pub community_name: String,
-    community_removed: bool,
-    community_deleted: bool,
-    community_nsfw: bool,
+    pub removed: bool,
+    pub locked: bool,
+    pub deleted: bool,
+    pub nsfw: bool,
+    pub stickied: bool,
+    pub published: NaiveDateTime,
+    pub banned_from_community: bool,
     pub number_of_comments: i32,
     pub score: i32,
     pub upvotes: i32,
     pub downvotes: i32,
-    hot_rank: i32,
-    newest_activity_time: String,
-    pub user_id: Option<i32>,
-    my_vote: Option<i32>,
-    subscribed: Option<bool>,
-    read: Option<bool>,
-    saved: Option<bool>
+    pub my_vote: Option<i32>,
+    pub saved: Option<bool>
 }
 
-#[derive(Deserialize)]
 pub struct PostList {
     pub posts: Vec<PostView>
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct CommentView {
     pub id: i32,
     pub creator_id: i32,
+    pub creator_name: String,
     pub post_id: i32,
     pub parent_id: Option<i32>,
+    pub community_id: i32,
+    pub community_name: String,
     pub content: String,
-    removed: bool,
-    read: Option<bool>,
+    pub removed: bool,
+    pub deleted: bool,
     pub published: NaiveDateTime,
-    updated: Option<NaiveDateTime>,
-    deleted: Option<bool>,
-    community_id: i32,
-    community_name: String,
-    banned: bool,
-    banned_from_community: bool,
-    pub creator_name: String,
-    creator_avatar: Option<String>,
+    pub banned_from_community: bool,
     pub score: i32,
     pub upvotes: i32,
     pub downvotes: i32,
-    hot_rank: i32,
-    user_id: Option<i32>,
-    my_vote: Option<i32>,
-    subscribed: Option<bool>,
-    saved: Option<bool>,
+    pub my_vote: Option<i32>,
+    pub saved: Option<bool>
 }
 
-#[derive(Deserialize)]
 pub struct PostDetail {
     pub post: PostView,
-    pub comments: Vec<CommentView>
+    pub comments: Vec<CommentView>,
+    pub moderators: Vec<CommunityModeratorView>
 }
 
-#[derive(Deserialize)]
 pub struct CommunityModeratorView {
-    id: i32,
-    community_id: i32,
-    user_id: i32,
-    published: String,
-    pub user_name: String,
-    avatar: Option<String>,
-    community_name: String,
+    pub community_name: String,
+    pub user_id: i32,
+    pub user_name: String
 }
 
-#[derive(Deserialize)]
 pub struct UserView {
-    id: i32,
+    pub id: i32,
     pub name: String,
-    avatar: Option<String>,
-    email: Option<String>,
-    matrix_user_id: Option<String>,
-    fedi_name: Option<String>,
-    admin: bool,
-    banned: bool,
-    show_avatars: bool,
-    send_notifications_to_email: bool,
-    published: NaiveDateTime,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub bio: Option<String>,
+    pub admin: bool,
+    pub banned: bool,
+    pub published: NaiveDateTime,
     pub number_of_posts: i32,
     pub post_score: i32,
     pub number_of_comments: i32,
     pub comment_score: i32,
 }
 
-#[derive(Deserialize)]
 pub struct CommunityDetail {
     pub community: CommunityView,
     pub moderators: Vec<CommunityModeratorView>,
     pub admins: Option<Vec<UserView>>,
-    pub online: i32
-}
-
-#[derive(Deserialize)]
-struct CommunityFollowerView {	
-    id: i32,
-    community_id: i32,
-    user_id: i32,
-    published: NaiveDateTime,
-    user_name: String,
-    avatar: Option<String>,
-    community_name: String,
+    pub online: Option<i32>
 }
 
-#[derive(Deserialize)]
 pub struct UserDetail {
     pub user: UserView,
-    follows: Vec<CommunityFollowerView>,
-    moderates: Vec<CommunityModeratorView>,
+    pub moderates: Vec<CommunityModeratorView>,
     pub comments: Vec<CommentView>,
     pub posts: Vec<PostView>,
 }
 
-#[derive(Deserialize)]
 pub struct SearchResponse {
-    pub type_: String,
     pub comments: Vec<CommentView>,
     pub posts: Vec<PostView>,
     pub communities: Vec<CommunityView>,
     pub users: Vec<UserView>,
 }
 
-pub async fn get_community_list(client: &Client, instance: &String, paging_params: Option<&PagingParams>) -> Result<CommunityList> {
-    let url = build_url(instance, "v1/community/list", paging_params)
-        .map_err(|e| ErrorBadRequest(e.to_string()))?.to_string();
+// A reply to the user or a mention of them, id is the notification to mark as read
+pub struct ReplyView {
+    pub id: i32,
+    pub read: bool,
+    pub comment: CommentView
+}
+
+pub struct ReplyList {
+    pub replies: Vec<ReplyView>
+}
 
-    println!("Making request: {}", url);
-    Ok(CommunityList::from(
-        client.get(url).send().await?.json().limit(REQ_MAX_SIZE).await?
-    ))
+pub struct PrivateMessageView {
+    pub id: i32,
+    pub creator_name: String,
+    pub recipient_name: String,
+    pub content: String,
+    pub deleted: bool,
+    pub read: bool,
+    pub published: NaiveDateTime
 }
 
-pub async fn get_community(client: &Client, instance: &String, community: &String) -> Result<CommunityDetail> {
-    let mut base_url = build_url(instance, "v1/community", None)
-        .map_err(|e| ErrorBadRequest(e.to_string()))?;
-    let mut url_builder = base_url.query_pairs_mut();
-    let url = url_builder.append_pair("name", community.as_str()).finish().to_string();
+pub struct PrivateMessageList {
+    pub messages: Vec<PrivateMessageView>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InboxTab {
+    Replies,
+    Mentions,
+    Messages
+}
+
+impl InboxTab {
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "replies" => Some(InboxTab::Replies),
+            "mentions" => Some(InboxTab::Mentions),
+            "messages" => Some(InboxTab::Messages),
+            _ => None
+        }
+    }
+
+    pub fn path(self) -> &'static str {
+        match self {
+            InboxTab::Replies => "replies",
+            InboxTab::Mentions => "mentions",
+            InboxTab::Messages => "messages"
+        }
+    }
+}
+
+pub enum Inbox {
+    Replies(ReplyList),
+    Mentions(ReplyList),
+    Messages(PrivateMessageList)
+}
 
-    println!("Making request: {}", url);
-    Ok(CommunityDetail::from(
-        client.get(url).send().await?.json().limit(REQ_MAX_SIZE).await?
-    ))
+impl Inbox {
+    pub fn tab(&self) -> InboxTab {
+        match self {
+            Inbox::Replies(_) => InboxTab::Replies,
+            Inbox::Mentions(_) => InboxTab::Mentions,
+            Inbox::Messages(_) => InboxTab::Messages
+        }
+    }
+}
+
+// An open report, content is the post title or comment text as it was reported
+pub struct ReportView {
+    pub id: i32,
+    pub post_id: i32,
+    pub comment_id: Option<i32>,    // None for a post report
+    pub community_name: String,
+    pub creator_name: String,       // Reporter
+    pub reported_name: String,      // Creator of the reported post or comment
+    pub content: String,
+    pub reason: String,
+    pub published: NaiveDateTime
+}
+
+pub struct ReportList {
+    pub posts: Vec<ReportView>,
+    pub comments: Vec<ReportView>
 }
 
-pub async fn get_post_list(client: &Client, instance: &String, community: Option<&i32>, community_name: Option<&String>,
-    paging_params: Option<&PagingParams>) -> Result<PostList> {
-    let mut base_url = build_url(instance, "v1/post/list", paging_params)
-        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+pub enum ModlogTarget {
+    Post { id: i32, name: String },
+    Comment { id: i32, post_id: i32, content: String },
+    User { name: String }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ModAction {
+    RemovePost,
+    RestorePost,
+    LockPost,
+    UnlockPost,
+    StickyPost,
+    UnstickyPost,
+    RemoveComment,
+    RestoreComment,
+    Ban,                // From a community
+    Unban,
+    SiteBan,
+    SiteUnban
+}
+
+impl ModAction {
+    // As the modlog pages show it
+    pub fn label(self) -> &'static str {
+        match self {
+            ModAction::RemovePost => "Removed post",
+            ModAction::RestorePost => "Restored post",
+            ModAction::LockPost => "Locked post",
+            ModAction::UnlockPost => "Unlocked post",
+            ModAction::StickyPost => "Stickied post",
+            ModAction::UnstickyPost => "Unstickied post",
+            ModAction::RemoveComment => "Removed comment",
+            ModAction::RestoreComment => "Restored comment",
+            ModAction::Ban => "Banned",
+            ModAction::Unban => "Unbanned",
+            ModAction::SiteBan => "Banned from site",
+            ModAction::SiteUnban => "Unbanned from site"
+        }
+    }
+}
+
+// moderator_name is None when the instance hides who moderated
+pub struct ModlogEntry {
+    pub when: NaiveDateTime,
+    pub moderator_name: Option<String>,
+    pub action: ModAction,
+    pub target: ModlogTarget,
+    pub community_name: Option<String>,     // None for site bans
+    pub reason: Option<String>
+}
+
+pub struct Modlog {
+    pub entries: Vec<ModlogEntry>
+}
+
+impl Modlog {
+    // The API lists each kind of action separately
+    fn new(mut entries: Vec<ModlogEntry>) -> Self {
+        entries.sort_by_key(|e| Reverse(e.when));
+        Modlog { entries }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Registration {
+    Open,
+    RequireApplication,
+    Closed
+}
+
+pub struct SiteInfo {
+    pub registration: Registration,
+    pub application_question: Option<String>,   // Markdown
+    pub email_required: bool
+}
+
+// Images are base64, shown inline since lemmy-lite keeps no state between the form and its submission
+#[derive(Deserialize)]
+pub struct Captcha {
+    pub png: String,
+    pub wav: Option<String>,
+    pub uuid: String
+}
+
+// Profile and preferences of the logged in user
+pub struct UserSettings {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub matrix_user_id: Option<String>,
+    pub default_sort_type: String,
+    pub default_listing_type: String,
+    pub show_nsfw: bool,
+    pub show_avatars: bool,
+    pub send_notifications_to_email: bool
+}
+
+// Only an open instance without email verification logs the new user in right away
+pub enum Registered {
+    LoggedIn(String),   // JWT
+    ApplicationSent,
+    VerifyEmail
+}
+
+#[derive(Deserialize)]
+pub struct VoteForm {
+    pub v: i32  // Score
+}
+
+#[derive(Deserialize)]
+pub struct SaveForm {
+    pub s: bool     // Save, false unsaves
+}
+
+#[derive(Deserialize)]
+pub struct PostForm {
+    pub name: String,           // Title
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub body: String,
+    pub nsfw: Option<String>    // Checkbox, only sent when checked
+}
+
+#[derive(Deserialize)]
+pub struct CommentForm {
+    pub content: String,
+    pub preview: Option<String>     // Set by the preview button, shows the comment instead of posting it
+}
+
+#[derive(Deserialize)]
+pub struct SignupForm {
+    pub u: String,              // Username
+    #[serde(default)]
+    pub email: String,
+    pub pw: String,             // Password
+    pub pw2: String,            // Password again
+    pub nsfw: Option<String>,   // Checkbox, only sent when checked
+    pub cu: Option<String>,     // Captcha uuid
+    pub ca: Option<String>,     // Captcha answer
+    pub answer: Option<String>  // Registration application, v3 only
+}
+
+// Checkboxes are only sent when checked, empty text fields clear the setting
+#[derive(Deserialize)]
+pub struct SettingsForm {
+    #[serde(default)]
+    pub name: String,           // Display name
+    #[serde(default)]
+    pub bio: String,
+    #[serde(default)]
+    pub avatar: String,
+    #[serde(default)]
+    pub matrix: String,         // Matrix user id
+    pub s: String,              // Default sort
+    pub t: String,              // Default listing type
+    pub nsfw: Option<String>,
+    pub avatars: Option<String>,
+    pub notify: Option<String>, // Email notifications
+    pub preview: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    pub u: String,              // Username or email
+    pub pw: String,             // Password
+    pub totp: Option<String>    // 2FA token, v3 only
+}
+
+pub async fn get_community_list(api: &Api<'_>, paging_params: Option<&PagingParams>) -> Result<CommunityList, LiteError> {
+    let mut base_url = build_url(api, "community/list", paging_params)?;
+    if api.version == ApiVersion::V3 {
+        base_url.query_pairs_mut().append_pair("type_", "All");
+    }
+
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::CommunityList>(api, base_url, COMMUNITY_LIST_TTL).await?.into(),
+        ApiVersion::V3 => fetch::<v3::CommunityList>(api, base_url, COMMUNITY_LIST_TTL).await?.into()
+    })
+}
+
+pub async fn get_community(api: &Api<'_>, community: &str) -> Result<CommunityDetail, LiteError> {
+    let mut url = build_url(api, "community", None)?;
+    url.query_pairs_mut().append_pair("name", community);
+
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::CommunityDetail>(api, url, COMMUNITY_TTL).await?.into(),
+        ApiVersion::V3 => fetch::<v3::CommunityResponse>(api, url, COMMUNITY_TTL).await?.into()
+    })
+}
+
+pub async fn get_post_list(api: &Api<'_>, community: Option<&i32>, community_name: Option<&String>,
+    paging_params: Option<&PagingParams>) -> Result<PostList, LiteError> {
+    let mut base_url = build_url(api, "post/list", paging_params)?;
     let mut url_builder = base_url.query_pairs_mut();
 
-    url_builder.append_pair("type_", "All");
+    url_builder.append_pair("type_", paging_params.and_then(|p| p.t.as_deref()).unwrap_or("All"));
     if let Some(cid) = community {
         url_builder.append_pair("community_id", cid.to_string().as_str());
     } else if let Some(cn) = community_name {
@@ -256,52 +517,669 @@ pub async fn get_post_list(client: &Client, instance: &String, community: Option
             url_builder.append_pair("community_name", cn);
         }
     }
-    let url = url_builder.finish().to_string();
+    let url = url_builder.finish().to_owned();
 
-    println!("Making request: {}", url);
-    Ok(PostList::from(
-        client.get(url).send().await?.json().limit(REQ_MAX_SIZE).await?
-    ))
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::PostList>(api, url, POST_LIST_TTL).await?.into(),
+        ApiVersion::V3 => fetch::<v3::PostList>(api, url, POST_LIST_TTL).await?.into()
+    })
 }
 
-pub async fn get_post(client: &Client, instance: &String, post_id: &String) -> Result<PostDetail> {
-    let url = build_url(instance, "v1/post", None)
-        .map_err(|e| ErrorBadRequest(e.to_string()))?.query_pairs_mut()
-            .append_pair("id", post_id)
-        .finish().to_string();
+pub async fn get_post(api: &Api<'_>, post_id: &str) -> Result<PostDetail, LiteError> {
+    let mut url = build_url(api, "post", None)?;
+    url.query_pairs_mut().append_pair("id", post_id);
+
+    match api.version {
+        ApiVersion::V1 => Ok(fetch::<v1::PostDetail>(api, url, POST_TTL).await?.into()),
+        ApiVersion::V3 => {
+            // Comments are a separate endpoint in v3, fetch both at once to keep a critical chain of 1
+            let mut comments_url = build_url(api, "comment/list", None)?;
+            comments_url.query_pairs_mut()
+                .append_pair("post_id", post_id)
+                .append_pair("type_", "All")
+                .append_pair("max_depth", "8");
 
-    println!("Making request: {}", url);
-    Ok(PostDetail::from(client.get(url).send().await?.json().limit(REQ_MAX_SIZE).await?))
+            let (post, comments) = try_join(
+                fetch::<v3::PostResponse>(api, url, POST_TTL),
+                fetch::<v3::CommentList>(api, comments_url, POST_TTL)
+            ).await?;
+            Ok(v3::post_detail(post, comments))
+        }
+    }
 }
 
-pub async fn get_user(client: &Client, instance: &String, username: &String, paging_params: Option<&PagingParams>) -> Result<UserDetail> {
-    let url = build_url(instance, "v1/user", paging_params)
-        .map_err(|e| ErrorBadRequest(e.to_string()))?.query_pairs_mut()
-            .append_pair("saved_only", "false")
-            .append_pair("username", username)
-        .finish().to_string();
+// Saved items are those of the logged in user, whatever the username
+pub async fn get_user(api: &Api<'_>, username: &str, saved_only: bool, paging_params: Option<&PagingParams>) -> Result<UserDetail, LiteError> {
+    let mut url = build_url(api, "user", paging_params)?;
+    url.query_pairs_mut()
+        .append_pair("saved_only", if saved_only {"true"} else {"false"})
+        .append_pair("username", username);
 
-    println!("Making request: {}", url);
-    Ok(UserDetail::from(client.get(url).send().await?.json().limit(REQ_MAX_SIZE).await?))
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::UserDetail>(api, url, USER_TTL).await?.into(),
+        ApiVersion::V3 => fetch::<v3::PersonDetails>(api, url, USER_TTL).await?.into()
+    })
 }
 
-pub async fn search(client: &Client, instance: &String, search_params: &SearchParams) -> Result<SearchResponse> {
-    let query = search_params.q.as_ref().ok_or(ErrorBadRequest("Query cannot be empty"))?;
+// Looked up at login, both show on the public profile so it's fetched anonymously and shared in the cache
+pub async fn get_role(api: &Api<'_>, username: &str) -> Result<Role, LiteError> {
+    let user = get_user(&Api { auth: None, ..*api }, username, false, None).await?;
+    Ok(if user.user.admin {
+        Role::Admin
+    } else if !user.moderates.is_empty() {
+        Role::Moderator
+    } else {
+        Role::User
+    })
+}
 
-    let mut base_url = build_url(instance, "v1/search", Some(&search_params.to_paging_params()))
-        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+pub async fn search(api: &Api<'_>, search_params: &SearchParams) -> Result<SearchResponse, LiteError> {
+    let query = search_params.q.as_ref().ok_or_else(|| LiteError::InvalidPath {
+        instance: Some(api.instance.to_string()),
+        detail: "Query cannot be empty".to_string()
+    })?;
+
+    let mut base_url = build_url(api, "search", Some(&search_params.to_paging_params()))?;
     let mut url_builder = base_url.query_pairs_mut();
     url_builder.append_pair("q", query.as_str());
     url_builder.append_pair("type_", search_params.t.as_ref().map_or("All", |t| &**t));
-    search_params.c.as_ref().map(|c| url_builder.append_pair("community_name", c.as_str()));
-    let url = url_builder.finish().to_string();
+    if let Some(c) = search_params.c.as_ref().filter(|c| !c.is_empty()) {
+        url_builder.append_pair("community_name", c.as_str());
+    }
+    let url = url_builder.finish().to_owned();
 
-    println!("Making request: {}", url);
-    Ok(SearchResponse::from(client.get(url).send().await?.json().limit(REQ_MAX_SIZE).await?))
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::SearchResponse>(api, url, SEARCH_TTL).await?.into(),
+        ApiVersion::V3 => fetch::<v3::SearchResponse>(api, url, SEARCH_TTL).await?.into()
+    })
 }
 
-fn build_url(instance: &String, endpoint: &str, paging_params: Option<&PagingParams>) -> Result<Url, ParseError> {
-    let mut url = Url::parse(format!("https://{}/api/{}", instance, endpoint).as_str())?;
+// Returns the JWT of the user
+pub async fn login(api: &Api<'_>, form: &LoginForm) -> Result<String, LiteError> {
+    let mut body = json!({
+        "username_or_email": form.u,
+        "password": form.pw
+    });
+    if let Some(totp) = form.totp.as_ref().filter(|t| !t.is_empty()) {
+        body["totp_2fa_token"] = json!(totp);
+    }
+
+    let res: LoginResponse = send_json(api, Method::POST, "user/login", body).await?;
+    res.jwt.ok_or_else(|| LiteError::UpstreamError {
+        instance: api.instance.to_string(),
+        message: "your account is awaiting approval or email verification".to_string()
+    })
+}
+
+pub async fn get_site_info(api: &Api<'_>) -> Result<SiteInfo, LiteError> {
+    let url = build_endpoint(api, "site")?;
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::SiteResponse>(api, url, SITE_TTL).await?.into(),
+        ApiVersion::V3 => fetch::<v3::SiteResponse>(api, url, SITE_TTL).await?.into()
+    })
+}
+
+// None when the instance has captchas disabled, each one can only be answered once so it's never cached
+pub async fn get_captcha(api: &Api<'_>) -> Result<Option<Captcha>, LiteError> {
+    let url = build_endpoint(api, "user/get_captcha")?;
+    let res: CaptchaResponse = get_json(api.client, api.instance, url).await?;
+    Ok(res.ok)
+}
+
+pub async fn register(api: &Api<'_>, form: &SignupForm) -> Result<Registered, LiteError> {
+    let email = Some(form.email.trim()).filter(|e| !e.is_empty());
+    let mut body = json!({
+        "username": form.u.trim(),
+        "email": email,
+        "password": form.pw,
+        "password_verify": form.pw2,
+        "show_nsfw": form.nsfw.is_some(),
+        "captcha_uuid": form.cu,
+        "captcha_answer": form.ca
+    });
+    match api.version {
+        ApiVersion::V1 => body["admin"] = json!(false),
+        ApiVersion::V3 => body["answer"] = json!(form.answer)
+    }
+
+    let res: RegisterResponse = send_json(api, Method::POST, "user/register", body).await?;
+    Ok(match res.jwt {
+        Some(jwt) => Registered::LoggedIn(jwt),
+        None if res.registration_created => Registered::ApplicationSent,
+        None => Registered::VerifyEmail
+    })
+}
+
+// Name of the user api is authenticated as, login accepts an email so it can't be taken from the form
+pub async fn get_username(api: &Api<'_>) -> Result<String, LiteError> {
+    let url = build_url(api, "site", None)?;
+    let name = match api.version {
+        ApiVersion::V1 => fetch::<v1::SiteResponse>(api, url, Duration::ZERO).await?.username(),
+        ApiVersion::V3 => fetch::<v3::SiteResponse>(api, url, Duration::ZERO).await?.username()
+    };
+    name.ok_or_else(|| LiteError::UpstreamError {
+        instance: api.instance.to_string(),
+        message: "the login was not accepted".to_string()
+    })
+}
+
+pub async fn get_user_settings(api: &Api<'_>) -> Result<UserSettings, LiteError> {
+    let url = build_endpoint(api, "site")?;
+    let settings = match api.version {
+        ApiVersion::V1 => fetch::<v1::SiteResponse>(api, url, Duration::ZERO).await?.user_settings(),
+        ApiVersion::V3 => fetch::<v3::SiteResponse>(api, url, Duration::ZERO).await?.user_settings()
+    };
+    settings.ok_or_else(|| LiteError::LoginRequired { instance: api.instance.to_string() })
+}
+
+pub async fn save_user_settings(api: &Api<'_>, form: &SettingsForm) -> Result<(), LiteError> {
+    let mut body = json!({
+        "bio": form.bio.trim(),
+        "avatar": form.avatar.trim(),
+        "matrix_user_id": form.matrix.trim(),
+        "show_nsfw": form.nsfw.is_some(),
+        "show_avatars": form.avatars.is_some(),
+        "send_notifications_to_email": form.notify.is_some()
+    });
+    match api.version {
+        ApiVersion::V1 => {
+            let site = fetch::<v1::SiteResponse>(api, build_endpoint(api, "site")?, Duration::ZERO).await?;
+            let fields = site.settings_fields(form)
+                .ok_or_else(|| LiteError::LoginRequired { instance: api.instance.to_string() })?;
+            for (field, value) in fields {
+                body[field] = value;
+            }
+        },
+        ApiVersion::V3 => {
+            body["display_name"] = json!(form.name.trim());
+            body["default_sort_type"] = json!(form.s);
+            body["default_listing_type"] = json!(form.t);
+        }
+    }
+    send_json::<Value>(api, Method::PUT, "user/save_user_settings", body).await?;
+    Ok(())
+}
+
+pub async fn create_post(api: &Api<'_>, community_id: i32, form: &PostForm) -> Result<PostView, LiteError> {
+    let mut body = post_fields(form);
+    body["community_id"] = json!(community_id);
+
+    Ok(match api.version {
+        ApiVersion::V1 => send_json::<v1::PostResponse>(api, Method::POST, "post", body).await?.into(),
+        ApiVersion::V3 => send_json::<v3::PostResponse>(api, Method::POST, "post", body).await?.into()
+    })
+}
+
+pub async fn edit_post(api: &Api<'_>, post_id: i32, form: &PostForm) -> Result<PostView, LiteError> {
+    let mut body = post_fields(form);
+    body[id_field(api, "post_id")] = json!(post_id);
+    Ok(match api.version {
+        ApiVersion::V1 => send_json::<v1::PostResponse>(api, Method::PUT, "post", body).await?.into(),
+        ApiVersion::V3 => send_json::<v3::PostResponse>(api, Method::PUT, "post", body).await?.into()
+    })
+}
+
+// Deleting is reversible, deleted false restores the post
+pub async fn delete_post(api: &Api<'_>, post_id: i32, deleted: bool) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "post/delete", json!({
+        id_field(api, "post_id"): post_id,
+        "deleted": deleted
+    })).await?;
+    Ok(())
+}
+
+// v1 names the target of most actions edit_id
+fn id_field(api: &Api, v3_field: &'static str) -> &'static str {
+    match api.version {
+        ApiVersion::V1 => "edit_id",
+        ApiVersion::V3 => v3_field
+    }
+}
+
+// Empty optional fields are sent as null, Lemmy rejects an empty URL
+fn post_fields(form: &PostForm) -> Value {
+    let optional = |s: &str| Some(s.trim()).filter(|s| !s.is_empty()).map(str::to_string);
+    json!({
+        "name": form.name.trim(),
+        "url": optional(&form.url),
+        "body": optional(&form.body),
+        "nsfw": form.nsfw.is_some()
+    })
+}
+
+pub async fn create_comment(api: &Api<'_>, post_id: i32, parent_id: Option<i32>, content: &str) -> Result<CommentView, LiteError> {
+    let body = json!({
+        "post_id": post_id,
+        "parent_id": parent_id,
+        "content": content
+    });
+
+    Ok(match api.version {
+        ApiVersion::V1 => send_json::<v1::CommentResponse>(api, Method::POST, "comment", body).await?.into(),
+        ApiVersion::V3 => send_json::<v3::CommentResponse>(api, Method::POST, "comment", body).await?.into()
+    })
+}
+
+// Following a remote community stays pending until its instance accepts it
+pub async fn follow_community(api: &Api<'_>, community_id: i32, follow: bool) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "community/follow", json!({
+        "community_id": community_id,
+        "follow": follow
+    })).await?;
+    Ok(())
+}
+
+// Moderation, the instance checks that the user moderates the community
+pub async fn remove_post(api: &Api<'_>, post_id: i32, removed: bool, reason: &str) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "post/remove", json!({
+        id_field(api, "post_id"): post_id,
+        "removed": removed,
+        "reason": reason
+    })).await?;
+    Ok(())
+}
+
+pub async fn lock_post(api: &Api<'_>, post_id: i32, locked: bool) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "post/lock", json!({
+        id_field(api, "post_id"): post_id,
+        "locked": locked
+    })).await?;
+    Ok(())
+}
+
+// Lemmy 0.18 replaced stickies with featuring a post in its community
+pub async fn sticky_post(api: &Api<'_>, post_id: i32, stickied: bool) -> Result<(), LiteError> {
+    if api.version == ApiVersion::V3 {
+        match send_json::<Value>(api, Method::POST, "post/feature", json!({
+            "post_id": post_id,
+            "featured": stickied,
+            "feature_type": "Community"
+        })).await {
+            Err(LiteError::NotFound { .. }) => (),
+            res => return res.map(|_| ())
+        }
+    }
+    send_json::<Value>(api, Method::POST, "post/sticky", json!({
+        id_field(api, "post_id"): post_id,
+        "stickied": stickied
+    })).await?;
+    Ok(())
+}
+
+pub async fn remove_comment(api: &Api<'_>, comment_id: i32, removed: bool, reason: &str) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "comment/remove", json!({
+        id_field(api, "comment_id"): comment_id,
+        "removed": removed,
+        "reason": reason
+    })).await?;
+    Ok(())
+}
+
+// Bans are permanent and keep the user's content, removing it is left to the instance's own UI
+pub async fn ban_from_community(api: &Api<'_>, community_id: i32, user_id: i32, ban: bool, reason: &str) -> Result<(), LiteError> {
+    let user_field = match api.version {
+        ApiVersion::V1 => "user_id",
+        ApiVersion::V3 => "person_id"
+    };
+    send_json::<Value>(api, Method::POST, "community/ban_user", json!({
+        "community_id": community_id,
+        user_field: user_id,
+        "ban": ban,
+        "remove_data": false,
+        "reason": reason
+    })).await?;
+    Ok(())
+}
+
+pub async fn report_post(api: &Api<'_>, post_id: i32, reason: &str) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "post/report", json!({
+        "post_id": post_id,
+        "reason": reason
+    })).await?;
+    Ok(())
+}
+
+pub async fn report_comment(api: &Api<'_>, comment_id: i32, reason: &str) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "comment/report", json!({
+        "comment_id": comment_id,
+        "reason": reason
+    })).await?;
+    Ok(())
+}
+
+// Unresolved reports in the communities the user moderates, or on the whole instance for admins
+pub async fn get_reports(api: &Api<'_>, paging_params: Option<&PagingParams>) -> Result<ReportList, LiteError> {
+    let posts_url = report_list_url(api, "post/report/list", paging_params)?;
+    let comments_url = report_list_url(api, "comment/report/list", paging_params)?;
+
+    Ok(match api.version {
+        ApiVersion::V1 => {
+            let (posts, comments) = try_join(
+                fetch::<v1::PostReportList>(api, posts_url, Duration::ZERO),
+                fetch::<v1::CommentReportList>(api, comments_url, Duration::ZERO)
+            ).await?;
+            v1::report_list(posts, comments)
+        },
+        ApiVersion::V3 => {
+            let (posts, comments) = try_join(
+                fetch::<v3::PostReportList>(api, posts_url, Duration::ZERO),
+                fetch::<v3::CommentReportList>(api, comments_url, Duration::ZERO)
+            ).await?;
+            v3::report_list(posts, comments)
+        }
+    })
+}
+
+pub async fn resolve_post_report(api: &Api<'_>, report_id: i32, resolved: bool) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::PUT, "post/report/resolve", json!({
+        "report_id": report_id,
+        "resolved": resolved
+    })).await?;
+    Ok(())
+}
+
+pub async fn resolve_comment_report(api: &Api<'_>, report_id: i32, resolved: bool) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::PUT, "comment/report/resolve", json!({
+        "report_id": report_id,
+        "resolved": resolved
+    })).await?;
+    Ok(())
+}
+
+// Removals, bans, locks and stickies, of the whole instance or one community
+pub async fn get_modlog(api: &Api<'_>, community_id: Option<i32>, paging_params: Option<&PagingParams>) -> Result<Modlog, LiteError> {
+    let mut url = build_url(api, "modlog", paging_params)?;
+    if let Some(id) = community_id {
+        url.query_pairs_mut().append_pair("community_id", id.to_string().as_str());
+    }
+
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::Modlog>(api, url, MODLOG_TTL).await?.into(),
+        ApiVersion::V3 => fetch::<v3::Modlog>(api, url, MODLOG_TTL).await?.into()
+    })
+}
+
+// Score is 1, -1 or 0 to remove the vote
+pub async fn vote_post(api: &Api<'_>, post_id: i32, score: i32) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "post/like", json!({
+        "post_id": post_id,
+        "score": score
+    })).await?;
+    Ok(())
+}
+
+pub async fn vote_comment(api: &Api<'_>, comment_id: i32, score: i32) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "comment/like", json!({
+        "comment_id": comment_id,
+        "score": score
+    })).await?;
+    Ok(())
+}
+
+pub async fn save_post(api: &Api<'_>, post_id: i32, save: bool) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::PUT, "post/save", json!({
+        "post_id": post_id,
+        "save": save
+    })).await?;
+    Ok(())
+}
+
+pub async fn save_comment(api: &Api<'_>, comment_id: i32, save: bool) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::PUT, "comment/save", json!({
+        "comment_id": comment_id,
+        "save": save
+    })).await?;
+    Ok(())
+}
+
+// Read items stay listed, the page shows which ones are new
+pub async fn get_inbox(api: &Api<'_>, tab: InboxTab, paging_params: Option<&PagingParams>) -> Result<Inbox, LiteError> {
+    Ok(match (tab, api.version) {
+        (InboxTab::Replies, ApiVersion::V1) =>
+            Inbox::Replies(fetch::<v1::ReplyList>(api, inbox_url(api, "user/replies", paging_params)?, Duration::ZERO).await?.into()),
+        (InboxTab::Replies, ApiVersion::V3) =>
+            Inbox::Replies(fetch::<v3::ReplyList>(api, inbox_url(api, "user/replies", paging_params)?, Duration::ZERO).await?.into()),
+        (InboxTab::Mentions, ApiVersion::V1) =>
+            Inbox::Mentions(fetch::<v1::MentionList>(api, inbox_url(api, "user/mention", paging_params)?, Duration::ZERO).await?.into()),
+        (InboxTab::Mentions, ApiVersion::V3) =>
+            Inbox::Mentions(fetch::<v3::MentionList>(api, inbox_url(api, "user/mention", paging_params)?, Duration::ZERO).await?.into()),
+        (InboxTab::Messages, _) => Inbox::Messages(get_private_messages(api, paging_params).await?)
+    })
+}
+
+// Both received and sent messages, newest first
+pub async fn get_private_messages(api: &Api<'_>, paging_params: Option<&PagingParams>) -> Result<PrivateMessageList, LiteError> {
+    let url = inbox_url(api, "private_message/list", paging_params)?;
+    Ok(match api.version {
+        ApiVersion::V1 => fetch::<v1::PrivateMessageList>(api, url, Duration::ZERO).await?.into(),
+        ApiVersion::V3 => fetch::<v3::PrivateMessageList>(api, url, Duration::ZERO).await?.into()
+    })
+}
+
+// Messages exchanged with one user, newest first
+// No API filters on both correspondents, 0.19's creator_id only keeps received messages,
+// so each page is one page of all messages with the other conversations left out, it can be short or empty
+pub async fn get_conversation(api: &Api<'_>, username: &str, other: &str, page: Option<i32>) -> Result<PrivateMessageList, LiteError> {
+    let paging_params = &PagingParams {
+        s: None,
+        p: page,
+        l: Some(MESSAGE_PAGE_SIZE),
+        t: None
+    };
+    let mut list = get_private_messages(api, Some(paging_params)).await?;
+    list.messages.retain(|m| (m.creator_name == other && m.recipient_name == username) ||
+        (m.creator_name == username && m.recipient_name == other));
+    Ok(list)
+}
+
+pub async fn create_private_message(api: &Api<'_>, recipient_id: i32, content: &str) -> Result<PrivateMessageView, LiteError> {
+    let body = json!({
+        "recipient_id": recipient_id,
+        "content": content
+    });
+
+    Ok(match api.version {
+        ApiVersion::V1 => send_json::<v1::PrivateMessageResponse>(api, Method::POST, "private_message", body).await?.into(),
+        ApiVersion::V3 => send_json::<v3::PrivateMessageResponse>(api, Method::POST, "private_message", body).await?.into()
+    })
+}
+
+// Shown on every page, so unlike other personal responses it's cached for a while, keyed on a hash of the user's token
+// None when the API has no unread count
+pub async fn get_unread_count(api: &Api<'_>) -> Result<Option<i64>, LiteError> {
+    let (url, auth) = match unread_count_url(api)? {
+        Some(url) => url,
+        None => return Ok(None)
+    };
+    let body = api.cache.get_or_fetch(&personal_key(&url, auth), UNREAD_TTL, || async {
+        let mut url = url.clone();
+        url.query_pairs_mut().append_pair("auth", auth);
+        get_body(api.client, api.instance, &url, Some(auth)).await
+    }).await?;
+    Ok(Some(parse_json::<v3::UnreadCount>(api.instance, &body)?.total()))
+}
+
+pub async fn mark_read(api: &Api<'_>, tab: InboxTab, id: i32) -> Result<(), LiteError> {
+    let (endpoint, id_field) = match (tab, api.version) {
+        (InboxTab::Replies, _) => ("comment/mark_as_read", id_field(api, "comment_reply_id")),
+        (InboxTab::Mentions, ApiVersion::V1) => ("user/mention/mark_as_read", "user_mention_id"),
+        (InboxTab::Mentions, ApiVersion::V3) => ("user/mention/mark_as_read", "person_mention_id"),
+        (InboxTab::Messages, _) => ("private_message/mark_as_read", id_field(api, "private_message_id"))
+    };
+    send_json::<Value>(api, Method::POST, endpoint, json!({
+        id_field: id,
+        "read": true
+    })).await?;
+    forget_unread_count(api);
+    Ok(())
+}
+
+pub async fn mark_all_read(api: &Api<'_>) -> Result<(), LiteError> {
+    send_json::<Value>(api, Method::POST, "user/mark_all_as_read", json!({})).await?;
+    forget_unread_count(api);
+    Ok(())
+}
+
+// Only Lemmy 0.19+ invalidates tokens, older instances have nothing to call
+pub async fn logout(api: &Api<'_>) -> Result<(), LiteError> {
+    if api.version == ApiVersion::V3 {
+        send_json::<Value>(api, Method::POST, "user/logout", json!({})).await?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    jwt: Option<String>     // None while a v3 registration awaits approval
+}
+
+#[derive(Deserialize)]
+struct RegisterResponse {
+    jwt: Option<String>,
+    #[serde(default)]
+    registration_created: bool  // v3 only
+}
+
+#[derive(Deserialize)]
+struct CaptchaResponse {
+    ok: Option<Captcha>
+}
+
+// Responses are cached by URL, so the comment page reuses the post fetched for the post page
+// Authenticated responses are personal and never cached
+async fn fetch<T: DeserializeOwned>(api: &Api<'_>, mut url: Url, ttl: Duration) -> Result<T, LiteError> {
+    if let Some(auth) = api.auth {
+        url.query_pairs_mut().append_pair("auth", auth);
+        return parse_json(api.instance, &get_body(api.client, api.instance, &url, Some(auth)).await?);
+    }
+
+    let body = api.cache.get_or_fetch(url.as_str(), ttl, ||
+        get_body(api.client, api.instance, &url, None)
+    ).await?;
+    parse_json(api.instance, &body)
+}
+
+// POST or PUT a JSON body to an endpoint, v1 and v3 before 0.19 expect auth in the body
+async fn send_json<T: DeserializeOwned>(api: &Api<'_>, method: Method, endpoint: &str, mut body: Value) -> Result<T, LiteError> {
+    let url = build_endpoint(api, endpoint)?;
+    if let (Some(auth), Value::Object(map)) = (api.auth, &mut body) {
+        map.insert("auth".to_string(), json!(auth));
+    }
+
+    println!("Making {} request: {}", method, url.path());
+    let req = authorize(api.client.request(method, url.as_str()), api.auth);
+    let res = req.send_json(&body).await
+        .map_err(|e| LiteError::from_send(api.instance, e))?;
+    parse_json(api.instance, &read_body(api.instance, res).await?)
+}
+
+// Lemmy 0.19 reads the JWT from the Authorization header and ignores auth parameters
+fn authorize(req: ClientRequest, auth: Option<&str>) -> ClientRequest {
+    match auth {
+        Some(auth) => req.bearer_auth(auth),
+        None => req
+    }
+}
+
+async fn get_json<T: DeserializeOwned>(client: &Client, instance: &str, url: Url) -> Result<T, LiteError> {
+    parse_json(instance, &get_body(client, instance, &url, None).await?)
+}
+
+async fn get_body(client: &Client, instance: &str, url: &Url, auth: Option<&str>) -> Result<Bytes, LiteError> {
+    // Keep tokens out of the logs
+    println!("Making request: {}", if auth.is_some() { url.path() } else { url.as_str() });
+    let res = authorize(client.get(url.as_str()), auth).send().await
+        .map_err(|e| LiteError::from_send(instance, e))?;
+    read_body(instance, res).await
+}
+
+async fn read_body<S>(instance: &str, mut res: ClientResponse<S>) -> Result<Bytes, LiteError>
+    where S: Stream<Item = Result<Bytes, PayloadError>> + Unpin
+{
+    let body = res.body().limit(config::get().max_body_size).await.map_err(|e| match e {
+        PayloadError::Overflow => LiteError::BadJson {
+            instance: instance.to_string(),
+            detail: "response is larger than max_body_size".to_string()
+        },
+        _ => LiteError::UpstreamUnreachable { instance: instance.to_string() }
+    })?;
+
+    if !res.status().is_success() {
+        return Err(upstream_error(instance, res.status(), &body));
+    }
+    Ok(body)
+}
+
+fn parse_json<T: DeserializeOwned>(instance: &str, body: &[u8]) -> Result<T, LiteError> {
+    serde_json::from_slice(body).map_err(|e| LiteError::BadJson {
+        instance: instance.to_string(),
+        detail: e.to_string()
+    })
+}
+
+// Lemmy reports missing objects as couldnt_find_* errors, or not_found since 0.19
+fn upstream_error(instance: &str, status: StatusCode, body: &[u8]) -> LiteError {
+    let message = serde_json::from_slice::<ErrorResponse>(body)
+        .map(|e| e.error)
+        .unwrap_or_else(|_| status.to_string());
+
+    if status == StatusCode::NOT_FOUND || message.starts_with("couldnt_find") || message == "not_found" {
+        LiteError::NotFound {
+            instance: Some(instance.to_string()),
+            detail: format!("{} could not find what you were looking for ({}).", instance, message)
+        }
+    } else {
+        LiteError::UpstreamError { instance: instance.to_string(), message }
+    }
+}
+
+fn inbox_url(api: &Api, endpoint: &str, paging_params: Option<&PagingParams>) -> Result<Url, LiteError> {
+    let mut url = build_url(api, endpoint, paging_params)?;
+    url.query_pairs_mut().append_pair("unread_only", "false");
+    Ok(url)
+}
+
+// v1 only ever lists unresolved reports
+fn report_list_url(api: &Api, endpoint: &str, paging_params: Option<&PagingParams>) -> Result<Url, LiteError> {
+    let mut url = build_url(api, endpoint, paging_params)?;
+    url.query_pairs_mut().append_pair("unresolved_only", "true");
+    Ok(url)
+}
+
+// The v1 API has no unread count endpoint
+fn unread_count_url<'a>(api: &Api<'a>) -> Result<Option<(Url, &'a str)>, LiteError> {
+    match (api.version, api.auth) {
+        (ApiVersion::V3, Some(auth)) => Ok(Some((build_endpoint(api, "user/unread_count")?, auth))),
+        _ => Ok(None)
+    }
+}
+
+fn forget_unread_count(api: &Api) {
+    if let Ok(Some((url, auth))) = unread_count_url(api) {
+        api.cache.remove(&personal_key(&url, auth));
+    }
+}
+
+// Keeps session tokens out of the shared cache, a digest can't collide with another user's entry
+fn personal_key(url: &Url, auth: &str) -> String {
+    let digest = digest::digest(&digest::SHA256, auth.as_bytes());
+    let hex: String = digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{} {}", url, hex)
+}
+
+fn build_endpoint(api: &Api, endpoint: &str) -> Result<Url, LiteError> {
+    Url::parse(format!("{}://{}/api/{}/{}",
+        config::get().upstream_scheme, api.instance, api.version.path(), endpoint).as_str())
+        .map_err(|_| invalid_instance(api.instance))
+}
+
+fn build_url(api: &Api, endpoint: &str, paging_params: Option<&PagingParams>) -> Result<Url, LiteError> {
+    let mut url = build_endpoint(api, endpoint)?;
     let mut url_queries = url.query_pairs_mut();
     
     match paging_params {
@@ -314,4 +1192,11 @@ fn build_url(instance: &String, endpoint: &str, paging_params: Option<&PagingPar
         }
     }
     Ok(url_queries.finish().to_owned())
-}
\ No newline at end of file
+}
+
+pub(crate) fn invalid_instance(instance: &str) -> LiteError {
+    LiteError::InvalidPath {
+        instance: None,
+        detail: format!("{} is not a valid instance name.", instance)
+    }
+}
@@ -0,0 +1,173 @@
+// Instance software detection through nodeinfo, falling back to probing the v3 API
+
+use std::{collections::HashMap, sync::RwLock, time::{Duration, Instant}};
+use serde::Deserialize;
+use actix_web::client::Client;
+use url::Url;
+use crate::config;
+use crate::error::LiteError;
+use super::{ApiVersion, get_json, invalid_instance};
+
+const NODEINFO_SCHEMA: &str = "http://nodeinfo.diaspora.software/ns/schema/2.";
+const DETECTION_TTL: Duration = Duration::from_secs(3600); // Pick up instance upgrades
+const UNSUPPORTED_TTL: Duration = Duration::from_secs(600); // Typos and servers being set up get another chance soon
+const MAX_KNOWN: usize = 1000; // Any path segment is probed, unknown names can't grow the map forever
+
+#[derive(Clone)]
+pub enum Software {
+    Lemmy(ApiVersion),
+    Unsupported {
+        name: String,
+        version: String
+    }
+}
+
+// Detected software per instance and when to probe it again, shared between workers
+#[derive(Default)]
+pub struct Instances {
+    known: RwLock<HashMap<String, (Software, Instant)>>
+}
+
+#[derive(Deserialize)]
+struct NodeInfoLinks {
+    links: Vec<NodeInfoLink>
+}
+
+#[derive(Deserialize)]
+struct NodeInfoLink {
+    rel: String,
+    href: String
+}
+
+#[derive(Deserialize)]
+struct NodeInfo {
+    software: NodeInfoSoftware
+}
+
+#[derive(Deserialize)]
+struct NodeInfoSoftware {
+    name: String,
+    version: String
+}
+
+#[derive(Deserialize)]
+struct SiteResponse {
+    version: String
+}
+
+impl Instances {
+    // Cached software of an instance, probing it on first use
+    pub async fn software(&self, client: &Client, instance: &str) -> Result<Software, LiteError> {
+        if let Some((software, expires)) = self.known.read().unwrap().get(instance) {
+            if *expires > Instant::now() {
+                return Ok(software.clone());
+            }
+        }
+
+        let software = detect(client, instance).await?;
+        self.remember(instance, software.clone());
+        Ok(software)
+    }
+
+    fn remember(&self, instance: &str, software: Software) {
+        let ttl = match software {
+            Software::Lemmy(_) => DETECTION_TTL,
+            Software::Unsupported { .. } => UNSUPPORTED_TTL
+        };
+        let mut known = self.known.write().unwrap();
+        if known.len() >= MAX_KNOWN && !known.contains_key(instance) {
+            let now = Instant::now();
+            known.retain(|_, (_, expires)| *expires > now);
+            // Still full, the entry expiring first makes room
+            if known.len() >= MAX_KNOWN {
+                let first = known.iter().min_by_key(|(_, (_, expires))| *expires).map(|(k, _)| k.clone());
+                if let Some(first) = first {
+                    known.remove(&first);
+                }
+            }
+        }
+        known.insert(instance.to_string(), (software, Instant::now() + ttl));
+    }
+}
+
+async fn detect(client: &Client, instance: &str) -> Result<Software, LiteError> {
+    let base = Url::parse(format!("{}://{}", config::get().upstream_scheme, instance).as_str())
+        .map_err(|_| invalid_instance(instance))?;
+
+    match nodeinfo(client, instance, &base).await {
+        Ok(Some(info)) => return Ok(classify(info.software.name, info.software.version)),
+        Err(e @ LiteError::UpstreamUnreachable { .. }) | Err(e @ LiteError::UpstreamTimeout { .. }) => return Err(e),
+        _ => ()
+    }
+
+    // Some instances hide nodeinfo behind their proxy, a v3 site response is still conclusive
+    let site_url = base.join("/api/v3/site").map_err(|_| invalid_instance(instance))?;
+    match get_json::<SiteResponse>(client, instance, site_url).await {
+        Ok(site) => Ok(classify("lemmy".to_string(), site.version)),
+        Err(e @ LiteError::UpstreamUnreachable { .. }) | Err(e @ LiteError::UpstreamTimeout { .. }) => Err(e),
+        Err(_) => Ok(Software::Unsupported {
+            name: "an unrecognized server".to_string(),
+            version: String::new()
+        })
+    }
+}
+
+async fn nodeinfo(client: &Client, instance: &str, base: &Url) -> Result<Option<NodeInfo>, LiteError> {
+    let links_url = base.join("/.well-known/nodeinfo").map_err(|_| invalid_instance(instance))?;
+    let links: NodeInfoLinks = get_json(client, instance, links_url).await?;
+
+    let href = links.links.iter()
+        .filter(|l| l.rel.starts_with(NODEINFO_SCHEMA))
+        .filter_map(|l| Url::parse(&l.href).ok())
+        .find(|u| u.host_str() == base.host_str()); // Never follow nodeinfo to another host
+    match href {
+        Some(href) => Ok(Some(get_json(client, instance, href).await?)),
+        None => Ok(None)
+    }
+}
+
+// Lemmy 0.10 to 0.16 only speak the v2 API which has no client
+fn classify(name: String, version: String) -> Software {
+    if name.to_lowercase() != "lemmy" {
+        return Software::Unsupported { name, version };
+    }
+    let mut numbers = version.split(|c: char| !c.is_ascii_digit()).map(|n| n.parse::<u32>().ok());
+    match (numbers.next().flatten(), numbers.next().flatten()) {
+        (Some(0), Some(minor)) if minor <= 9 => Software::Lemmy(ApiVersion::V1),
+        (Some(0), Some(minor)) if minor >= 17 => Software::Lemmy(ApiVersion::V3),
+        (Some(major), _) if major >= 1 => Software::Lemmy(ApiVersion::V3),
+        _ => Software::Unsupported { name, version }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsupported() -> Software {
+        Software::Unsupported { name: "mastodon".to_string(), version: "4.1.0".to_string() }
+    }
+
+    #[test]
+    fn probed_names_are_capped() {
+        let instances = Instances::default();
+        instances.remember("lemmy.ml", Software::Lemmy(ApiVersion::V3));
+        for i in 0..MAX_KNOWN {
+            instances.remember(&format!("random-{}.example", i), unsupported());
+        }
+        let known = instances.known.read().unwrap();
+        assert_eq!(known.len(), MAX_KNOWN);
+        // Unsupported results expire first, so they are the ones evicted
+        assert!(known.contains_key("lemmy.ml"));
+        assert!(!known.contains_key("random-0.example"));
+    }
+
+    #[test]
+    fn unsupported_results_expire_sooner() {
+        let instances = Instances::default();
+        instances.remember("lemmy.ml", Software::Lemmy(ApiVersion::V3));
+        instances.remember("mastodon.social", unsupported());
+        let known = instances.known.read().unwrap();
+        assert!(known["mastodon.social"].1 < known["lemmy.ml"].1);
+    }
+}
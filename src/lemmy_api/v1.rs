@@ -0,0 +1,733 @@
+// Legacy /api/v1 response shapes (Lemmy <= 0.9)
+
+use chrono::naive::NaiveDateTime;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use crate::lemmy_api as model;
+
+// User settings store sort and listing types as enum indexes
+const SORT_TYPES: [&str; 8] = ["Active", "Hot", "New", "TopDay", "TopWeek", "TopMonth", "TopYear", "TopAll"];
+const LISTING_TYPES: [&str; 4] = ["All", "Local", "Subscribed", "Community"];
+
+#[derive(Deserialize)]
+pub struct CommunityView {
+    id: i32,
+    name: String,
+    title: String,
+    description: Option<String>,
+    removed: bool,
+    published: NaiveDateTime,
+    deleted: bool,
+    nsfw: bool,
+    category_name: String,
+    number_of_subscribers: i32,
+    number_of_posts: i32,
+    number_of_comments: i32,
+    hot_rank: i32,
+    subscribed: Option<bool>
+}
+
+#[derive(Deserialize)]
+pub struct CommunityList {
+    communities: Vec<CommunityView>
+}
+
+#[derive(Deserialize)]
+pub struct PostView {
+    id: i32,
+    name: String,
+    url: Option<String>,
+    body: Option<String>,
+    creator_id: i32,
+    community_id: i32,
+    removed: bool,
+    locked: bool,
+    published: NaiveDateTime,
+    deleted: bool,
+    nsfw: bool,
+    stickied: bool,
+    banned_from_community: bool,
+    creator_name: String,
+    community_name: String,
+    number_of_comments: i32,
+    score: i32,
+    upvotes: i32,
+    downvotes: i32,
+    my_vote: Option<i32>,
+    saved: Option<bool>
+}
+
+#[derive(Deserialize)]
+pub struct PostList {
+    posts: Vec<PostView>
+}
+
+#[derive(Deserialize)]
+pub struct CommentView {
+    id: i32,
+    creator_id: i32,
+    post_id: i32,
+    parent_id: Option<i32>,
+    content: String,
+    removed: bool,
+    read: Option<bool>,
+    published: NaiveDateTime,
+    deleted: Option<bool>,
+    community_id: i32,
+    community_name: String,
+    banned_from_community: bool,
+    creator_name: String,
+    score: i32,
+    upvotes: i32,
+    downvotes: i32,
+    my_vote: Option<i32>,
+    saved: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct PostDetail {
+    post: PostView,
+    comments: Vec<CommentView>,
+    moderators: Vec<CommunityModeratorView>
+}
+
+#[derive(Deserialize)]
+pub struct CommunityModeratorView {
+    user_id: i32,
+    user_name: String,
+    community_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct UserView {
+    id: i32,
+    name: String,
+    avatar: Option<String>,
+    admin: bool,
+    banned: bool,
+    published: NaiveDateTime,
+    number_of_posts: i32,
+    post_score: i32,
+    number_of_comments: i32,
+    comment_score: i32,
+}
+
+#[derive(Deserialize)]
+pub struct CommunityDetail {
+    community: CommunityView,
+    moderators: Vec<CommunityModeratorView>,
+    admins: Option<Vec<UserView>>,
+    online: i32
+}
+
+#[derive(Deserialize)]
+pub struct UserDetail {
+    user: UserView,
+    moderates: Vec<CommunityModeratorView>,
+    comments: Vec<CommentView>,
+    posts: Vec<PostView>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchResponse {
+    comments: Vec<CommentView>,
+    posts: Vec<PostView>,
+    communities: Vec<CommunityView>,
+    users: Vec<UserView>,
+}
+
+#[derive(Deserialize)]
+pub struct PostResponse {
+    post: PostView,
+}
+
+#[derive(Deserialize)]
+pub struct CommentResponse {
+    comment: CommentView,
+}
+
+// Replies are plain comment views, their read flag is the one marked as read
+#[derive(Deserialize)]
+pub struct ReplyList {
+    replies: Vec<CommentView>
+}
+
+#[derive(Deserialize)]
+pub struct UserMentionView {
+    user_mention_id: i32,
+    #[serde(flatten)]
+    comment: CommentView
+}
+
+#[derive(Deserialize)]
+pub struct MentionList {
+    mentions: Vec<UserMentionView>
+}
+
+#[derive(Deserialize)]
+pub struct PrivateMessageView {
+    id: i32,
+    content: String,
+    deleted: bool,
+    read: bool,
+    published: NaiveDateTime,
+    creator_name: String,
+    recipient_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct PrivateMessageList {
+    messages: Vec<PrivateMessageView>
+}
+
+#[derive(Deserialize)]
+pub struct PrivateMessageResponse {
+    message: PrivateMessageView,
+}
+
+// Report views keep the reported content as it was, next to its current version
+#[derive(Deserialize)]
+pub struct PostReportView {
+    id: i32,
+    post_id: i32,
+    post_name: String,
+    reason: String,
+    published: NaiveDateTime,
+    community_name: String,
+    creator_name: String,
+    post_creator_name: String
+}
+
+#[derive(Deserialize)]
+pub struct PostReportList {
+    posts: Vec<PostReportView>
+}
+
+#[derive(Deserialize)]
+pub struct CommentReportView {
+    id: i32,
+    comment_id: i32,
+    comment_text: String,
+    reason: String,
+    published: NaiveDateTime,
+    post_id: i32,
+    community_name: String,
+    creator_name: String,
+    comment_creator_name: String
+}
+
+#[derive(Deserialize)]
+pub struct CommentReportList {
+    comments: Vec<CommentReportView>
+}
+
+// Mod actions flags are optional, a missing flag means the action was done
+#[derive(Deserialize)]
+pub struct ModRemovePostView {
+    post_id: i32,
+    reason: Option<String>,
+    removed: Option<bool>,
+    when_: NaiveDateTime,
+    mod_user_name: String,
+    post_name: String,
+    community_name: String
+}
+
+#[derive(Deserialize)]
+pub struct ModLockPostView {
+    post_id: i32,
+    locked: Option<bool>,
+    when_: NaiveDateTime,
+    mod_user_name: String,
+    post_name: String,
+    community_name: String
+}
+
+#[derive(Deserialize)]
+pub struct ModStickyPostView {
+    post_id: i32,
+    stickied: Option<bool>,
+    when_: NaiveDateTime,
+    mod_user_name: String,
+    post_name: String,
+    community_name: String
+}
+
+#[derive(Deserialize)]
+pub struct ModRemoveCommentView {
+    comment_id: i32,
+    reason: Option<String>,
+    removed: Option<bool>,
+    when_: NaiveDateTime,
+    mod_user_name: String,
+    comment_content: String,
+    post_id: i32,
+    community_name: String
+}
+
+#[derive(Deserialize)]
+pub struct ModBanFromCommunityView {
+    reason: Option<String>,
+    banned: Option<bool>,
+    when_: NaiveDateTime,
+    mod_user_name: String,
+    other_user_name: String,
+    community_name: String
+}
+
+#[derive(Deserialize)]
+pub struct ModBanView {
+    reason: Option<String>,
+    banned: Option<bool>,
+    when_: NaiveDateTime,
+    mod_user_name: String,
+    other_user_name: String
+}
+
+#[derive(Deserialize)]
+pub struct Modlog {
+    removed_posts: Vec<ModRemovePostView>,
+    locked_posts: Vec<ModLockPostView>,
+    stickied_posts: Vec<ModStickyPostView>,
+    removed_comments: Vec<ModRemoveCommentView>,
+    banned_from_community: Vec<ModBanFromCommunityView>,
+    banned: Vec<ModBanView>
+}
+
+// Settings are only read by the settings page
+#[derive(Deserialize)]
+pub struct User {
+    name: String,
+    preferred_username: Option<String>,
+    email: Option<String>,
+    avatar: Option<String>,
+    bio: Option<String>,
+    matrix_user_id: Option<String>,
+    #[serde(default)]
+    show_nsfw: bool,
+    #[serde(default)]
+    theme: String,
+    #[serde(default)]
+    default_sort_type: i16,
+    #[serde(default)]
+    default_listing_type: i16,
+    #[serde(default)]
+    lang: String,
+    #[serde(default)]
+    show_avatars: bool,
+    #[serde(default)]
+    send_notifications_to_email: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SiteView {
+    open_registration: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SiteResponse {
+    site: Option<SiteView>,     // None before the instance is set up
+    my_user: Option<User>,
+}
+
+impl SiteResponse {
+    pub fn username(self) -> Option<String> {
+        self.my_user.map(|u| u.name)
+    }
+
+    pub fn user_settings(self) -> Option<model::UserSettings> {
+        self.my_user.map(|u| model::UserSettings {
+            display_name: u.preferred_username,
+            bio: u.bio,
+            avatar: u.avatar,
+            matrix_user_id: u.matrix_user_id,
+            default_sort_type: SORT_TYPES.get(u.default_sort_type as usize).unwrap_or(&"Hot").to_string(),
+            default_listing_type: LISTING_TYPES.get(u.default_listing_type as usize).unwrap_or(&"All").to_string(),
+            show_nsfw: u.show_nsfw,
+            show_avatars: u.show_avatars,
+            send_notifications_to_email: u.send_notifications_to_email
+        })
+    }
+
+    // Saving replaces every setting, those the form doesn't show are sent back as they are
+    pub fn settings_fields(self, form: &model::SettingsForm) -> Option<Vec<(&'static str, Value)>> {
+        let u = self.my_user?;
+        Some(vec![
+            ("preferred_username", json!(form.name.trim())),
+            ("default_sort_type", json!(SORT_TYPES.iter().position(|s| *s == form.s).unwrap_or(1))),
+            ("default_listing_type", json!(LISTING_TYPES.iter().position(|t| *t == form.t).unwrap_or(0))),
+            ("theme", json!(u.theme)),
+            ("lang", json!(u.lang)),
+            ("email", json!(u.email))
+        ])
+    }
+}
+
+// v1 has neither applications nor email verification
+impl From<SiteResponse> for model::SiteInfo {
+    fn from(r: SiteResponse) -> Self {
+        let open = r.site.is_some_and(|s| s.open_registration);
+        model::SiteInfo {
+            registration: if open { model::Registration::Open } else { model::Registration::Closed },
+            application_question: None,
+            email_required: false
+        }
+    }
+}
+
+impl From<CommunityView> for model::CommunityView {
+    fn from(c: CommunityView) -> Self {
+        model::CommunityView {
+            id: c.id,
+            name: c.name,
+            title: c.title,
+            description: c.description,
+            nsfw: c.nsfw,
+            removed: c.removed,
+            deleted: c.deleted,
+            published: c.published,
+            category_name: Some(c.category_name),
+            number_of_subscribers: c.number_of_subscribers,
+            number_of_posts: c.number_of_posts,
+            number_of_comments: c.number_of_comments,
+            hot_rank: Some(c.hot_rank),
+            subscribed: c.subscribed.map(|s| if s { model::Subscription::Subscribed } else { model::Subscription::NotSubscribed })
+        }
+    }
+}
+
+impl From<CommunityList> for model::CommunityList {
+    fn from(l: CommunityList) -> Self {
+        model::CommunityList {
+            communities: l.communities.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<PostView> for model::PostView {
+    fn from(p: PostView) -> Self {
+        model::PostView {
+            id: p.id,
+            name: p.name,
+            url: p.url,
+            body: p.body,
+            creator_id: p.creator_id,
+            creator_name: p.creator_name,
+            community_id: p.community_id,
+            community_name: p.community_name,
+            removed: p.removed,
+            locked: p.locked,
+            deleted: p.deleted,
+            nsfw: p.nsfw,
+            stickied: p.stickied,
+            published: p.published,
+            banned_from_community: p.banned_from_community,
+            number_of_comments: p.number_of_comments,
+            score: p.score,
+            upvotes: p.upvotes,
+            downvotes: p.downvotes,
+            my_vote: p.my_vote,
+            saved: p.saved
+        }
+    }
+}
+
+impl From<PostList> for model::PostList {
+    fn from(l: PostList) -> Self {
+        model::PostList {
+            posts: l.posts.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<CommentView> for model::CommentView {
+    fn from(c: CommentView) -> Self {
+        model::CommentView {
+            id: c.id,
+            creator_id: c.creator_id,
+            creator_name: c.creator_name,
+            post_id: c.post_id,
+            parent_id: c.parent_id,
+            community_id: c.community_id,
+            community_name: c.community_name,
+            content: c.content,
+            removed: c.removed,
+            deleted: c.deleted.unwrap_or(false),
+            published: c.published,
+            banned_from_community: c.banned_from_community,
+            score: c.score,
+            upvotes: c.upvotes,
+            downvotes: c.downvotes,
+            my_vote: c.my_vote,
+            saved: c.saved
+        }
+    }
+}
+
+impl From<PostResponse> for model::PostView {
+    fn from(r: PostResponse) -> Self {
+        r.post.into()
+    }
+}
+
+impl From<CommentResponse> for model::CommentView {
+    fn from(r: CommentResponse) -> Self {
+        r.comment.into()
+    }
+}
+
+impl From<ReplyList> for model::ReplyList {
+    fn from(l: ReplyList) -> Self {
+        model::ReplyList {
+            replies: l.replies.into_iter().map(|c| model::ReplyView {
+                id: c.id,
+                read: c.read.unwrap_or(false),
+                comment: c.into()
+            }).collect()
+        }
+    }
+}
+
+impl From<MentionList> for model::ReplyList {
+    fn from(l: MentionList) -> Self {
+        model::ReplyList {
+            replies: l.mentions.into_iter().map(|m| model::ReplyView {
+                id: m.user_mention_id,
+                read: m.comment.read.unwrap_or(false),
+                comment: m.comment.into()
+            }).collect()
+        }
+    }
+}
+
+impl From<PrivateMessageView> for model::PrivateMessageView {
+    fn from(m: PrivateMessageView) -> Self {
+        model::PrivateMessageView {
+            id: m.id,
+            creator_name: m.creator_name,
+            recipient_name: m.recipient_name,
+            content: m.content,
+            deleted: m.deleted,
+            read: m.read,
+            published: m.published
+        }
+    }
+}
+
+impl From<PrivateMessageList> for model::PrivateMessageList {
+    fn from(l: PrivateMessageList) -> Self {
+        model::PrivateMessageList {
+            messages: l.messages.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<PrivateMessageResponse> for model::PrivateMessageView {
+    fn from(r: PrivateMessageResponse) -> Self {
+        r.message.into()
+    }
+}
+
+impl From<PostReportView> for model::ReportView {
+    fn from(r: PostReportView) -> Self {
+        model::ReportView {
+            id: r.id,
+            post_id: r.post_id,
+            comment_id: None,
+            community_name: r.community_name,
+            creator_name: r.creator_name,
+            reported_name: r.post_creator_name,
+            content: r.post_name,
+            reason: r.reason,
+            published: r.published
+        }
+    }
+}
+
+impl From<CommentReportView> for model::ReportView {
+    fn from(r: CommentReportView) -> Self {
+        model::ReportView {
+            id: r.id,
+            post_id: r.post_id,
+            comment_id: Some(r.comment_id),
+            community_name: r.community_name,
+            creator_name: r.creator_name,
+            reported_name: r.comment_creator_name,
+            content: r.comment_text,
+            reason: r.reason,
+            published: r.published
+        }
+    }
+}
+
+impl From<Modlog> for model::Modlog {
+    fn from(l: Modlog) -> Self {
+        let mut entries = Vec::new();
+        entries.extend(l.removed_posts.into_iter().map(|m| model::ModlogEntry {
+            when: m.when_,
+            moderator_name: Some(m.mod_user_name),
+            action: if m.removed.unwrap_or(true) {model::ModAction::RemovePost} else {model::ModAction::RestorePost},
+            target: model::ModlogTarget::Post { id: m.post_id, name: m.post_name },
+            community_name: Some(m.community_name),
+            reason: m.reason
+        }));
+        entries.extend(l.locked_posts.into_iter().map(|m| model::ModlogEntry {
+            when: m.when_,
+            moderator_name: Some(m.mod_user_name),
+            action: if m.locked.unwrap_or(true) {model::ModAction::LockPost} else {model::ModAction::UnlockPost},
+            target: model::ModlogTarget::Post { id: m.post_id, name: m.post_name },
+            community_name: Some(m.community_name),
+            reason: None
+        }));
+        entries.extend(l.stickied_posts.into_iter().map(|m| model::ModlogEntry {
+            when: m.when_,
+            moderator_name: Some(m.mod_user_name),
+            action: if m.stickied.unwrap_or(true) {model::ModAction::StickyPost} else {model::ModAction::UnstickyPost},
+            target: model::ModlogTarget::Post { id: m.post_id, name: m.post_name },
+            community_name: Some(m.community_name),
+            reason: None
+        }));
+        entries.extend(l.removed_comments.into_iter().map(|m| model::ModlogEntry {
+            when: m.when_,
+            moderator_name: Some(m.mod_user_name),
+            action: if m.removed.unwrap_or(true) {model::ModAction::RemoveComment} else {model::ModAction::RestoreComment},
+            target: model::ModlogTarget::Comment {
+                id: m.comment_id,
+                post_id: m.post_id,
+                content: m.comment_content
+            },
+            community_name: Some(m.community_name),
+            reason: m.reason
+        }));
+        entries.extend(l.banned_from_community.into_iter().map(|m| model::ModlogEntry {
+            when: m.when_,
+            moderator_name: Some(m.mod_user_name),
+            action: if m.banned.unwrap_or(true) {model::ModAction::Ban} else {model::ModAction::Unban},
+            target: model::ModlogTarget::User { name: m.other_user_name },
+            community_name: Some(m.community_name),
+            reason: m.reason
+        }));
+        entries.extend(l.banned.into_iter().map(|m| model::ModlogEntry {
+            when: m.when_,
+            moderator_name: Some(m.mod_user_name),
+            action: if m.banned.unwrap_or(true) {model::ModAction::SiteBan} else {model::ModAction::SiteUnban},
+            target: model::ModlogTarget::User { name: m.other_user_name },
+            community_name: None,
+            reason: m.reason
+        }));
+        model::Modlog::new(entries)
+    }
+}
+
+impl From<PostDetail> for model::PostDetail {
+    fn from(d: PostDetail) -> Self {
+        model::PostDetail {
+            post: d.post.into(),
+            comments: d.comments.into_iter().map(Into::into).collect(),
+            moderators: d.moderators.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<CommunityModeratorView> for model::CommunityModeratorView {
+    fn from(m: CommunityModeratorView) -> Self {
+        model::CommunityModeratorView {
+            community_name: m.community_name,
+            user_id: m.user_id,
+            user_name: m.user_name
+        }
+    }
+}
+
+impl From<UserView> for model::UserView {
+    fn from(u: UserView) -> Self {
+        model::UserView {
+            id: u.id,
+            name: u.name,
+            display_name: None,
+            avatar: u.avatar,
+            bio: None,
+            admin: u.admin,
+            banned: u.banned,
+            published: u.published,
+            number_of_posts: u.number_of_posts,
+            post_score: u.post_score,
+            number_of_comments: u.number_of_comments,
+            comment_score: u.comment_score,
+        }
+    }
+}
+
+impl From<CommunityDetail> for model::CommunityDetail {
+    fn from(d: CommunityDetail) -> Self {
+        model::CommunityDetail {
+            community: d.community.into(),
+            moderators: d.moderators.into_iter().map(Into::into).collect(),
+            admins: d.admins.map(|a| a.into_iter().map(Into::into).collect()),
+            online: Some(d.online)
+        }
+    }
+}
+
+impl From<UserDetail> for model::UserDetail {
+    fn from(d: UserDetail) -> Self {
+        model::UserDetail {
+            user: d.user.into(),
+            moderates: d.moderates.into_iter().map(Into::into).collect(),
+            comments: d.comments.into_iter().map(Into::into).collect(),
+            posts: d.posts.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<SearchResponse> for model::SearchResponse {
+    fn from(r: SearchResponse) -> Self {
+        model::SearchResponse {
+            comments: r.comments.into_iter().map(Into::into).collect(),
+            posts: r.posts.into_iter().map(Into::into).collect(),
+            communities: r.communities.into_iter().map(Into::into).collect(),
+            users: r.users.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+pub fn report_list(posts: PostReportList, comments: CommentReportList) -> model::ReportList {
+    model::ReportList {
+        posts: posts.posts.into_iter().map(Into::into).collect(),
+        comments: comments.comments.into_iter().map(Into::into).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn community_subscription_flag() {
+        let view = |subscribed: Value| json!({
+            "id": 3, "name": "rust", "title": "Rust", "description": null, "removed": false, "published": "2020-06-01T12:00:00.123",
+            "deleted": false, "nsfw": false, "category_name": "Programming", "number_of_subscribers": 10,
+            "number_of_posts": 2, "number_of_comments": 3, "hot_rank": 1, "subscribed": subscribed
+        });
+        let convert = |v| model::CommunityView::from(serde_json::from_value::<CommunityView>(v).unwrap());
+        assert_eq!(convert(view(json!(true))).subscribed, Some(model::Subscription::Subscribed));
+        assert_eq!(convert(view(json!(false))).subscribed, Some(model::Subscription::NotSubscribed));
+        assert_eq!(convert(view(Value::Null)).subscribed, None);
+    }
+
+    #[test]
+    fn missing_mod_flag_means_the_action_was_done() {
+        let modlog: Modlog = serde_json::from_value(json!({
+            "removed_posts": [
+                {"post_id": 1, "reason": "spam", "when_": "2020-06-02T12:00:00", "mod_user_name": "me", "post_name": "A", "community_name": "rust"},
+                {"post_id": 1, "reason": null, "removed": false, "when_": "2020-06-03T12:00:00", "mod_user_name": "me", "post_name": "A", "community_name": "rust"}
+            ],
+            "locked_posts": [], "stickied_posts": [], "removed_comments": [], "banned_from_community": [], "banned": []
+        })).unwrap();
+        let modlog = model::Modlog::from(modlog);
+        // Newest first
+        let actions: Vec<model::ModAction> = modlog.entries.iter().map(|e| e.action).collect();
+        assert_eq!(actions, [model::ModAction::RestorePost, model::ModAction::RemovePost]);
+        assert_eq!(modlog.entries[1].reason.as_deref(), Some("spam"));
+    }
+}
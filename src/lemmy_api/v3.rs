@@ -0,0 +1,990 @@
+// Current /api/v3 response shapes (Lemmy >= 0.17)
+// Views are nested objects, fields that changed between minor releases are defaulted
+
+use chrono::{DateTime, naive::NaiveDateTime};
+use serde::{Deserialize, Deserializer, de};
+use url::Url;
+use crate::lemmy_api as model;
+
+#[derive(Deserialize)]
+pub struct Person {
+    id: i32,
+    name: String,
+    display_name: Option<String>,
+    avatar: Option<String>,
+    #[serde(default)]
+    banned: bool,
+    #[serde(deserialize_with = "timestamp")]
+    published: NaiveDateTime,
+    actor_id: String,
+    local: bool,
+    #[serde(default)]
+    admin: bool,
+    bio: Option<String>,
+    matrix_user_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Community {
+    id: i32,
+    name: String,
+    title: String,
+    description: Option<String>,
+    removed: bool,
+    #[serde(deserialize_with = "timestamp")]
+    published: NaiveDateTime,
+    deleted: bool,
+    nsfw: bool,
+    actor_id: String,
+    local: bool,
+}
+
+#[derive(Deserialize)]
+pub struct Post {
+    id: i32,
+    name: String,
+    url: Option<String>,
+    body: Option<String>,
+    creator_id: i32,
+    community_id: i32,
+    removed: bool,
+    locked: bool,
+    #[serde(deserialize_with = "timestamp")]
+    published: NaiveDateTime,
+    deleted: bool,
+    nsfw: bool,
+    #[serde(default)]
+    stickied: bool,             // 0.17
+    #[serde(default)]
+    featured_community: bool,   // 0.18+
+    #[serde(default)]
+    featured_local: bool,       // 0.18+
+}
+
+#[derive(Deserialize)]
+pub struct Comment {
+    id: i32,
+    creator_id: i32,
+    post_id: i32,
+    content: String,
+    removed: bool,
+    #[serde(deserialize_with = "timestamp")]
+    published: NaiveDateTime,
+    deleted: bool,
+    path: String,   // ltree of ancestor ids, ex: 0.12.34
+}
+
+#[derive(Deserialize)]
+pub struct CommunityAggregates {
+    subscribers: i32,
+    posts: i32,
+    comments: i32,
+    hot_rank: Option<i32>,  // Removed in 0.19
+}
+
+#[derive(Deserialize)]
+pub struct PostAggregates {
+    comments: i32,
+    score: i32,
+    upvotes: i32,
+    downvotes: i32,
+}
+
+#[derive(Deserialize)]
+pub struct CommentAggregates {
+    score: i32,
+    upvotes: i32,
+    downvotes: i32,
+}
+
+#[derive(Deserialize)]
+pub struct PersonAggregates {
+    post_count: i32,
+    #[serde(default)]
+    post_score: i32,    // Removed in 0.19
+    comment_count: i32,
+    #[serde(default)]
+    comment_score: i32, // Removed in 0.19
+}
+
+#[derive(Deserialize)]
+pub enum SubscribedType {
+    Subscribed,
+    NotSubscribed,
+    Pending
+}
+
+#[derive(Deserialize)]
+pub struct CommunityView {
+    community: Community,
+    subscribed: SubscribedType,
+    counts: CommunityAggregates,
+}
+
+#[derive(Deserialize)]
+pub struct CommunityList {
+    communities: Vec<CommunityView>
+}
+
+#[derive(Deserialize)]
+pub struct PostView {
+    post: Post,
+    creator: Person,
+    community: Community,
+    creator_banned_from_community: bool,
+    counts: PostAggregates,
+    saved: bool,
+    my_vote: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct PostList {
+    posts: Vec<PostView>
+}
+
+#[derive(Deserialize)]
+pub struct PostResponse {
+    post_view: PostView,
+    #[serde(default)]
+    moderators: Vec<CommunityModeratorView>,    // Only when getting a post
+}
+
+#[derive(Deserialize)]
+pub struct CommentView {
+    comment: Comment,
+    creator: Person,
+    community: Community,
+    counts: CommentAggregates,
+    creator_banned_from_community: bool,
+    saved: bool,
+    my_vote: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct CommentResponse {
+    comment_view: CommentView,
+}
+
+#[derive(Deserialize)]
+pub struct CommentList {
+    comments: Vec<CommentView>
+}
+
+#[derive(Deserialize)]
+pub struct CommunityModeratorView {
+    community: Community,
+    moderator: Person,
+}
+
+#[derive(Deserialize)]
+pub struct PersonView {
+    person: Person,
+    counts: PersonAggregates,
+    #[serde(default)]
+    is_admin: bool,     // 0.19+
+}
+
+#[derive(Deserialize)]
+pub struct CommunityResponse {
+    community_view: CommunityView,
+    moderators: Vec<CommunityModeratorView>,
+    online: Option<i32>,    // Removed in 0.18
+}
+
+#[derive(Deserialize)]
+pub struct PersonDetails {
+    person_view: PersonView,
+    comments: Vec<CommentView>,
+    posts: Vec<PostView>,
+    moderates: Vec<CommunityModeratorView>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchResponse {
+    comments: Vec<CommentView>,
+    posts: Vec<PostView>,
+    communities: Vec<CommunityView>,
+    users: Vec<PersonView>,
+}
+
+#[derive(Deserialize)]
+pub struct CommentReply {
+    id: i32,
+    read: bool,
+}
+
+// Reply and mention views extend the comment view with the notification
+#[derive(Deserialize)]
+pub struct CommentReplyView {
+    comment_reply: CommentReply,
+    #[serde(flatten)]
+    comment_view: CommentView,
+}
+
+#[derive(Deserialize)]
+pub struct ReplyList {
+    replies: Vec<CommentReplyView>
+}
+
+#[derive(Deserialize)]
+pub struct PersonMention {
+    id: i32,
+    read: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PersonMentionView {
+    person_mention: PersonMention,
+    #[serde(flatten)]
+    comment_view: CommentView,
+}
+
+#[derive(Deserialize)]
+pub struct MentionList {
+    mentions: Vec<PersonMentionView>
+}
+
+#[derive(Deserialize)]
+pub struct PrivateMessage {
+    id: i32,
+    content: String,
+    deleted: bool,
+    read: bool,
+    #[serde(deserialize_with = "timestamp")]
+    published: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct PrivateMessageView {
+    private_message: PrivateMessage,
+    creator: Person,
+    recipient: Person,
+}
+
+#[derive(Deserialize)]
+pub struct PrivateMessageList {
+    private_messages: Vec<PrivateMessageView>
+}
+
+#[derive(Deserialize)]
+pub struct PrivateMessageResponse {
+    private_message_view: PrivateMessageView,
+}
+
+#[derive(Deserialize)]
+pub struct PostReport {
+    id: i32,
+    original_post_name: String,
+    reason: String,
+    #[serde(deserialize_with = "timestamp")]
+    published: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct PostReportView {
+    post_report: PostReport,
+    post: Post,
+    community: Community,
+    creator: Person,
+    post_creator: Person,
+}
+
+#[derive(Deserialize)]
+pub struct PostReportList {
+    post_reports: Vec<PostReportView>
+}
+
+#[derive(Deserialize)]
+pub struct CommentReport {
+    id: i32,
+    original_comment_text: String,
+    reason: String,
+    #[serde(deserialize_with = "timestamp")]
+    published: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct CommentReportView {
+    comment_report: CommentReport,
+    comment: Comment,
+    post: Post,
+    community: Community,
+    creator: Person,
+    comment_creator: Person,
+}
+
+#[derive(Deserialize)]
+pub struct CommentReportList {
+    comment_reports: Vec<CommentReportView>
+}
+
+// Mod actions flags are optional in 0.17, where a missing flag means the action was done
+#[derive(Deserialize)]
+pub struct ModRemovePost {
+    reason: Option<String>,
+    removed: Option<bool>,
+    #[serde(deserialize_with = "timestamp")]
+    when_: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ModRemovePostView {
+    mod_remove_post: ModRemovePost,
+    moderator: Option<Person>,  // Hidden by some instances
+    post: Post,
+    community: Community,
+}
+
+#[derive(Deserialize)]
+pub struct ModLockPost {
+    locked: Option<bool>,
+    #[serde(deserialize_with = "timestamp")]
+    when_: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ModLockPostView {
+    mod_lock_post: ModLockPost,
+    moderator: Option<Person>,
+    post: Post,
+    community: Community,
+}
+
+#[derive(Deserialize)]
+pub struct ModStickyPost {
+    stickied: Option<bool>,
+    #[serde(deserialize_with = "timestamp")]
+    when_: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ModStickyPostView {
+    mod_sticky_post: ModStickyPost,
+    moderator: Option<Person>,
+    post: Post,
+    community: Community,
+}
+
+#[derive(Deserialize)]
+pub struct ModFeaturePost {
+    featured: bool,
+    #[serde(deserialize_with = "timestamp")]
+    when_: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ModFeaturePostView {
+    mod_feature_post: ModFeaturePost,
+    moderator: Option<Person>,
+    post: Post,
+    community: Community,
+}
+
+#[derive(Deserialize)]
+pub struct ModRemoveComment {
+    reason: Option<String>,
+    removed: Option<bool>,
+    #[serde(deserialize_with = "timestamp")]
+    when_: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ModRemoveCommentView {
+    mod_remove_comment: ModRemoveComment,
+    moderator: Option<Person>,
+    comment: Comment,
+    post: Post,
+    community: Community,
+}
+
+#[derive(Deserialize)]
+pub struct ModBanFromCommunity {
+    reason: Option<String>,
+    banned: Option<bool>,
+    #[serde(deserialize_with = "timestamp")]
+    when_: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ModBanFromCommunityView {
+    mod_ban_from_community: ModBanFromCommunity,
+    moderator: Option<Person>,
+    community: Community,
+    banned_person: Person,
+}
+
+#[derive(Deserialize)]
+pub struct ModBan {
+    reason: Option<String>,
+    banned: Option<bool>,
+    #[serde(deserialize_with = "timestamp")]
+    when_: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ModBanView {
+    mod_ban: ModBan,
+    moderator: Option<Person>,
+    banned_person: Person,
+}
+
+#[derive(Deserialize)]
+pub struct Modlog {
+    removed_posts: Vec<ModRemovePostView>,
+    locked_posts: Vec<ModLockPostView>,
+    #[serde(default)]
+    stickied_posts: Vec<ModStickyPostView>,     // 0.17
+    #[serde(default)]
+    featured_posts: Vec<ModFeaturePostView>,    // 0.18+
+    removed_comments: Vec<ModRemoveCommentView>,
+    banned_from_community: Vec<ModBanFromCommunityView>,
+    banned: Vec<ModBanView>,
+}
+
+#[derive(Deserialize)]
+pub struct UnreadCount {
+    replies: i64,
+    mentions: i64,
+    private_messages: i64,
+}
+
+impl UnreadCount {
+    pub fn total(&self) -> i64 {
+        self.replies + self.mentions + self.private_messages
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LocalUser {
+    show_nsfw: bool,
+    default_sort_type: String,
+    default_listing_type: String,
+    show_avatars: bool,
+    send_notifications_to_email: bool,
+}
+
+#[derive(Deserialize)]
+pub struct LocalUserView {
+    local_user: LocalUser,
+    person: Person,
+}
+
+#[derive(Deserialize)]
+pub struct MyUserInfo {
+    local_user_view: LocalUserView,
+}
+
+#[derive(Deserialize)]
+pub enum RegistrationMode {
+    Closed,
+    RequireApplication,
+    Open
+}
+
+#[derive(Deserialize)]
+pub struct LocalSite {
+    registration_mode: Option<RegistrationMode>,    // 0.18+
+    #[serde(default)]
+    open_registration: bool,                        // 0.17
+    #[serde(default)]
+    require_application: bool,                      // 0.17
+    application_question: Option<String>,
+    #[serde(default)]
+    require_email_verification: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SiteView {
+    local_site: LocalSite,
+}
+
+#[derive(Deserialize)]
+pub struct SiteResponse {
+    site_view: SiteView,
+    my_user: Option<MyUserInfo>,
+}
+
+impl SiteResponse {
+    pub fn username(self) -> Option<String> {
+        self.my_user.map(|u| u.local_user_view.person.name)
+    }
+
+    pub fn user_settings(self) -> Option<model::UserSettings> {
+        self.my_user.map(|u| {
+            let (local_user, person) = (u.local_user_view.local_user, u.local_user_view.person);
+            model::UserSettings {
+                display_name: person.display_name,
+                bio: person.bio,
+                avatar: person.avatar,
+                matrix_user_id: person.matrix_user_id,
+                default_sort_type: local_user.default_sort_type,
+                default_listing_type: local_user.default_listing_type,
+                show_nsfw: local_user.show_nsfw,
+                show_avatars: local_user.show_avatars,
+                send_notifications_to_email: local_user.send_notifications_to_email
+            }
+        })
+    }
+}
+
+// Accepts both naive (0.17, 0.18) and RFC 3339 (0.19+) timestamps
+fn timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDateTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    DateTime::parse_from_rfc3339(&s).map(|t| t.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(&s, "%Y-%m-%dT%H:%M:%S%.f"))
+        .map_err(de::Error::custom)
+}
+
+// Remote actors are addressed as name@host, the same form the v3 API accepts as a lookup key
+fn qualified_name(name: String, actor_id: &str, local: bool) -> String {
+    if local {
+        return name;
+    }
+    match Url::parse(actor_id).ok().as_ref().and_then(|u| u.host_str()) {
+        Some(host) => format!("{}@{}", name, host),
+        None => name
+    }
+}
+
+impl Person {
+    fn qualified_name(&self) -> String {
+        qualified_name(self.name.clone(), &self.actor_id, self.local)
+    }
+}
+
+impl Community {
+    fn qualified_name(&self) -> String {
+        qualified_name(self.name.clone(), &self.actor_id, self.local)
+    }
+}
+
+impl From<SiteResponse> for model::SiteInfo {
+    fn from(r: SiteResponse) -> Self {
+        let local_site = r.site_view.local_site;
+        model::SiteInfo {
+            registration: match local_site.registration_mode {
+                Some(RegistrationMode::Closed) => model::Registration::Closed,
+                Some(RegistrationMode::RequireApplication) => model::Registration::RequireApplication,
+                Some(RegistrationMode::Open) => model::Registration::Open,
+                None if !local_site.open_registration => model::Registration::Closed,
+                None if local_site.require_application => model::Registration::RequireApplication,
+                None => model::Registration::Open
+            },
+            application_question: local_site.application_question,
+            email_required: local_site.require_email_verification
+        }
+    }
+}
+
+impl From<CommunityView> for model::CommunityView {
+    fn from(c: CommunityView) -> Self {
+        model::CommunityView {
+            id: c.community.id,
+            name: c.community.qualified_name(),
+            title: c.community.title,
+            description: c.community.description,
+            nsfw: c.community.nsfw,
+            removed: c.community.removed,
+            deleted: c.community.deleted,
+            published: c.community.published,
+            category_name: None,
+            number_of_subscribers: c.counts.subscribers,
+            number_of_posts: c.counts.posts,
+            number_of_comments: c.counts.comments,
+            hot_rank: c.counts.hot_rank,
+            subscribed: Some(match c.subscribed {
+                SubscribedType::Subscribed => model::Subscription::Subscribed,
+                SubscribedType::NotSubscribed => model::Subscription::NotSubscribed,
+                SubscribedType::Pending => model::Subscription::Pending
+            })
+        }
+    }
+}
+
+impl From<CommunityList> for model::CommunityList {
+    fn from(l: CommunityList) -> Self {
+        model::CommunityList {
+            communities: l.communities.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<PostView> for model::PostView {
+    fn from(p: PostView) -> Self {
+        model::PostView {
+            creator_name: p.creator.qualified_name(),
+            community_name: p.community.qualified_name(),
+            id: p.post.id,
+            name: p.post.name,
+            url: p.post.url,
+            body: p.post.body,
+            creator_id: p.post.creator_id,
+            community_id: p.post.community_id,
+            removed: p.post.removed,
+            locked: p.post.locked,
+            deleted: p.post.deleted,
+            nsfw: p.post.nsfw,
+            stickied: p.post.stickied || p.post.featured_community || p.post.featured_local,
+            published: p.post.published,
+            banned_from_community: p.creator_banned_from_community,
+            number_of_comments: p.counts.comments,
+            score: p.counts.score,
+            upvotes: p.counts.upvotes,
+            downvotes: p.counts.downvotes,
+            my_vote: p.my_vote,
+            saved: Some(p.saved)
+        }
+    }
+}
+
+impl From<PostList> for model::PostList {
+    fn from(l: PostList) -> Self {
+        model::PostList {
+            posts: l.posts.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<CommentView> for model::CommentView {
+    fn from(c: CommentView) -> Self {
+        // The last path element is the comment itself, the one before it is the parent (0 is the root)
+        let parent_id = c.comment.path.rsplit('.').nth(1)
+            .and_then(|id| id.parse().ok())
+            .filter(|id| *id != 0);
+        model::CommentView {
+            creator_name: c.creator.qualified_name(),
+            community_name: c.community.qualified_name(),
+            id: c.comment.id,
+            creator_id: c.comment.creator_id,
+            post_id: c.comment.post_id,
+            parent_id,
+            community_id: c.community.id,
+            content: c.comment.content,
+            removed: c.comment.removed,
+            deleted: c.comment.deleted,
+            published: c.comment.published,
+            banned_from_community: c.creator_banned_from_community,
+            score: c.counts.score,
+            upvotes: c.counts.upvotes,
+            downvotes: c.counts.downvotes,
+            my_vote: c.my_vote,
+            saved: Some(c.saved)
+        }
+    }
+}
+
+impl From<PostResponse> for model::PostView {
+    fn from(r: PostResponse) -> Self {
+        r.post_view.into()
+    }
+}
+
+impl From<CommentResponse> for model::CommentView {
+    fn from(r: CommentResponse) -> Self {
+        r.comment_view.into()
+    }
+}
+
+impl From<ReplyList> for model::ReplyList {
+    fn from(l: ReplyList) -> Self {
+        model::ReplyList {
+            replies: l.replies.into_iter().map(|r| model::ReplyView {
+                id: r.comment_reply.id,
+                read: r.comment_reply.read,
+                comment: r.comment_view.into()
+            }).collect()
+        }
+    }
+}
+
+impl From<MentionList> for model::ReplyList {
+    fn from(l: MentionList) -> Self {
+        model::ReplyList {
+            replies: l.mentions.into_iter().map(|m| model::ReplyView {
+                id: m.person_mention.id,
+                read: m.person_mention.read,
+                comment: m.comment_view.into()
+            }).collect()
+        }
+    }
+}
+
+impl From<PrivateMessageView> for model::PrivateMessageView {
+    fn from(m: PrivateMessageView) -> Self {
+        model::PrivateMessageView {
+            creator_name: m.creator.qualified_name(),
+            recipient_name: m.recipient.qualified_name(),
+            id: m.private_message.id,
+            content: m.private_message.content,
+            deleted: m.private_message.deleted,
+            read: m.private_message.read,
+            published: m.private_message.published
+        }
+    }
+}
+
+impl From<PrivateMessageList> for model::PrivateMessageList {
+    fn from(l: PrivateMessageList) -> Self {
+        model::PrivateMessageList {
+            messages: l.private_messages.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<PrivateMessageResponse> for model::PrivateMessageView {
+    fn from(r: PrivateMessageResponse) -> Self {
+        r.private_message_view.into()
+    }
+}
+
+impl From<PostReportView> for model::ReportView {
+    fn from(r: PostReportView) -> Self {
+        model::ReportView {
+            community_name: r.community.qualified_name(),
+            creator_name: r.creator.qualified_name(),
+            reported_name: r.post_creator.qualified_name(),
+            id: r.post_report.id,
+            post_id: r.post.id,
+            comment_id: None,
+            content: r.post_report.original_post_name,
+            reason: r.post_report.reason,
+            published: r.post_report.published
+        }
+    }
+}
+
+impl From<CommentReportView> for model::ReportView {
+    fn from(r: CommentReportView) -> Self {
+        model::ReportView {
+            community_name: r.community.qualified_name(),
+            creator_name: r.creator.qualified_name(),
+            reported_name: r.comment_creator.qualified_name(),
+            id: r.comment_report.id,
+            post_id: r.post.id,
+            comment_id: Some(r.comment.id),
+            content: r.comment_report.original_comment_text,
+            reason: r.comment_report.reason,
+            published: r.comment_report.published
+        }
+    }
+}
+
+impl From<Modlog> for model::Modlog {
+    fn from(l: Modlog) -> Self {
+        let post = |p: Post| model::ModlogTarget::Post { id: p.id, name: p.name };
+        let mut entries = Vec::new();
+        entries.extend(l.removed_posts.into_iter().map(|m| model::ModlogEntry {
+            when: m.mod_remove_post.when_,
+            moderator_name: m.moderator.map(|p| p.qualified_name()),
+            action: if m.mod_remove_post.removed.unwrap_or(true) {model::ModAction::RemovePost} else {model::ModAction::RestorePost},
+            target: post(m.post),
+            community_name: Some(m.community.qualified_name()),
+            reason: m.mod_remove_post.reason
+        }));
+        entries.extend(l.locked_posts.into_iter().map(|m| model::ModlogEntry {
+            when: m.mod_lock_post.when_,
+            moderator_name: m.moderator.map(|p| p.qualified_name()),
+            action: if m.mod_lock_post.locked.unwrap_or(true) {model::ModAction::LockPost} else {model::ModAction::UnlockPost},
+            target: post(m.post),
+            community_name: Some(m.community.qualified_name()),
+            reason: None
+        }));
+        entries.extend(l.stickied_posts.into_iter().map(|m| model::ModlogEntry {
+            when: m.mod_sticky_post.when_,
+            moderator_name: m.moderator.map(|p| p.qualified_name()),
+            action: if m.mod_sticky_post.stickied.unwrap_or(true) {model::ModAction::StickyPost} else {model::ModAction::UnstickyPost},
+            target: post(m.post),
+            community_name: Some(m.community.qualified_name()),
+            reason: None
+        }));
+        // Featuring in the local feed is an admin action, shown as a sticky all the same
+        entries.extend(l.featured_posts.into_iter().map(|m| model::ModlogEntry {
+            when: m.mod_feature_post.when_,
+            moderator_name: m.moderator.map(|p| p.qualified_name()),
+            action: if m.mod_feature_post.featured {model::ModAction::StickyPost} else {model::ModAction::UnstickyPost},
+            target: post(m.post),
+            community_name: Some(m.community.qualified_name()),
+            reason: None
+        }));
+        entries.extend(l.removed_comments.into_iter().map(|m| model::ModlogEntry {
+            when: m.mod_remove_comment.when_,
+            moderator_name: m.moderator.map(|p| p.qualified_name()),
+            action: if m.mod_remove_comment.removed.unwrap_or(true) {model::ModAction::RemoveComment} else {model::ModAction::RestoreComment},
+            target: model::ModlogTarget::Comment {
+                id: m.comment.id,
+                post_id: m.post.id,
+                content: m.comment.content
+            },
+            community_name: Some(m.community.qualified_name()),
+            reason: m.mod_remove_comment.reason
+        }));
+        entries.extend(l.banned_from_community.into_iter().map(|m| model::ModlogEntry {
+            when: m.mod_ban_from_community.when_,
+            moderator_name: m.moderator.map(|p| p.qualified_name()),
+            action: if m.mod_ban_from_community.banned.unwrap_or(true) {model::ModAction::Ban} else {model::ModAction::Unban},
+            target: model::ModlogTarget::User { name: m.banned_person.qualified_name() },
+            community_name: Some(m.community.qualified_name()),
+            reason: m.mod_ban_from_community.reason
+        }));
+        entries.extend(l.banned.into_iter().map(|m| model::ModlogEntry {
+            when: m.mod_ban.when_,
+            moderator_name: m.moderator.map(|p| p.qualified_name()),
+            action: if m.mod_ban.banned.unwrap_or(true) {model::ModAction::SiteBan} else {model::ModAction::SiteUnban},
+            target: model::ModlogTarget::User { name: m.banned_person.qualified_name() },
+            community_name: None,
+            reason: m.mod_ban.reason
+        }));
+        model::Modlog::new(entries)
+    }
+}
+
+impl From<CommunityModeratorView> for model::CommunityModeratorView {
+    fn from(m: CommunityModeratorView) -> Self {
+        model::CommunityModeratorView {
+            community_name: m.community.qualified_name(),
+            user_id: m.moderator.id,
+            user_name: m.moderator.qualified_name()
+        }
+    }
+}
+
+impl From<PersonView> for model::UserView {
+    fn from(u: PersonView) -> Self {
+        model::UserView {
+            name: u.person.qualified_name(),
+            id: u.person.id,
+            display_name: u.person.display_name,
+            avatar: u.person.avatar,
+            bio: u.person.bio,
+            admin: u.person.admin || u.is_admin,
+            banned: u.person.banned,
+            published: u.person.published,
+            number_of_posts: u.counts.post_count,
+            post_score: u.counts.post_score,
+            number_of_comments: u.counts.comment_count,
+            comment_score: u.counts.comment_score,
+        }
+    }
+}
+
+impl From<CommunityResponse> for model::CommunityDetail {
+    fn from(d: CommunityResponse) -> Self {
+        model::CommunityDetail {
+            community: d.community_view.into(),
+            moderators: d.moderators.into_iter().map(Into::into).collect(),
+            admins: None,
+            online: d.online
+        }
+    }
+}
+
+impl From<PersonDetails> for model::UserDetail {
+    fn from(d: PersonDetails) -> Self {
+        model::UserDetail {
+            user: d.person_view.into(),
+            moderates: d.moderates.into_iter().map(Into::into).collect(),
+            comments: d.comments.into_iter().map(Into::into).collect(),
+            posts: d.posts.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+impl From<SearchResponse> for model::SearchResponse {
+    fn from(r: SearchResponse) -> Self {
+        model::SearchResponse {
+            comments: r.comments.into_iter().map(Into::into).collect(),
+            posts: r.posts.into_iter().map(Into::into).collect(),
+            communities: r.communities.into_iter().map(Into::into).collect(),
+            users: r.users.into_iter().map(Into::into).collect()
+        }
+    }
+}
+
+// v3 returns the post and its comments from separate endpoints
+pub fn post_detail(post: PostResponse, comments: CommentList) -> model::PostDetail {
+    model::PostDetail {
+        post: post.post_view.into(),
+        comments: comments.comments.into_iter().map(Into::into).collect(),
+        moderators: post.moderators.into_iter().map(Into::into).collect()
+    }
+}
+
+// Post and comment reports are listed by separate endpoints
+pub fn report_list(posts: PostReportList, comments: CommentReportList) -> model::ReportList {
+    model::ReportList {
+        posts: posts.post_reports.into_iter().map(Into::into).collect(),
+        comments: comments.comment_reports.into_iter().map(Into::into).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn person(name: &str, local: bool) -> Value {
+        json!({
+            "id": 2, "name": name, "display_name": null, "avatar": null, "published": "2023-06-01T12:00:00",
+            "actor_id": format!("https://remote.example/u/{}", name), "local": local, "bio": null, "matrix_user_id": null
+        })
+    }
+
+    fn community() -> Value {
+        json!({
+            "id": 3, "name": "rust", "title": "Rust", "description": null, "removed": false, "published": "2023-06-01T12:00:00",
+            "deleted": false, "nsfw": false, "actor_id": "https://lemmy.example/c/rust", "local": true
+        })
+    }
+
+    fn comment(path: &str, published: &str) -> model::CommentView {
+        let view = json!({
+            "comment": {
+                "id": 34, "creator_id": 2, "post_id": 1, "content": "hi", "removed": false,
+                "published": published, "deleted": false, "path": path
+            },
+            "creator": person("alice", false),
+            "community": community(),
+            "counts": {"score": 1, "upvotes": 1, "downvotes": 0},
+            "creator_banned_from_community": false, "saved": false, "my_vote": null
+        });
+        serde_json::from_value::<CommentView>(view).unwrap().into()
+    }
+
+    fn subscription(subscribed: &str) -> Option<model::Subscription> {
+        let view = json!({
+            "community": community(),
+            "subscribed": subscribed,
+            "counts": {"subscribers": 10, "posts": 2, "comments": 3}
+        });
+        model::CommunityView::from(serde_json::from_value::<CommunityView>(view).unwrap()).subscribed
+    }
+
+    #[test]
+    fn parent_id_comes_from_the_path() {
+        assert_eq!(comment("0.34", "2023-06-01T12:00:00").parent_id, None);
+        assert_eq!(comment("0.12.34", "2023-06-01T12:00:00").parent_id, Some(12));
+        assert_eq!(comment("0.5.12.34", "2023-06-01T12:00:00").parent_id, Some(12));
+    }
+
+    #[test]
+    fn naive_and_rfc3339_timestamps_are_utc() {
+        let expected = NaiveDateTime::parse_from_str("2023-06-01T12:00:00.5", "%Y-%m-%dT%H:%M:%S%.f").unwrap();
+        assert_eq!(comment("0.34", "2023-06-01T12:00:00.5").published, expected);
+        assert_eq!(comment("0.34", "2023-06-01T12:00:00.5Z").published, expected);
+        assert_eq!(comment("0.34", "2023-06-01T14:00:00.5+02:00").published, expected);
+    }
+
+    #[test]
+    fn remote_names_are_qualified() {
+        let c = comment("0.34", "2023-06-01T12:00:00");
+        assert_eq!(c.creator_name, "alice@remote.example");
+        assert_eq!(c.community_name, "rust");
+    }
+
+    #[test]
+    fn pending_follow_is_not_a_subscription() {
+        assert_eq!(subscription("Subscribed"), Some(model::Subscription::Subscribed));
+        assert_eq!(subscription("NotSubscribed"), Some(model::Subscription::NotSubscribed));
+        assert_eq!(subscription("Pending"), Some(model::Subscription::Pending));
+    }
+}
@@ -9,15 +9,44 @@ voyager.lemmy.ml
 ds9.lemmy.ml
 */
 
+use std::{process, time::Duration};
 use chrono::offset::Utc;
 use serde::Deserialize;
-use actix_web::{web, App, HttpServer, Result, error, client::Client, HttpResponse, http::StatusCode};
+use futures_util::{TryFutureExt, future::try_join};
+use actix_web::{web, App, dev::Service, HttpServer, HttpRequest, client::Client, HttpResponse, http::{StatusCode, header}};
 use maud::Markup;
+use url::Url;
+mod cache;
+mod config;
+mod error;
+mod feeds;
+mod gemini;
+mod json;
 mod templates;
 mod lemmy_api;
+mod routes;
+mod session;
+mod text;
 
-use crate::templates::{redirect_page, post_list_page, post_page, comment_page, community_info_page, communities_page, user_page, search_page};
-use crate::lemmy_api::{PagingParams, SearchParams, get_post_list, get_post, get_community, get_community_list, get_user, search};
+use crate::cache::ApiCache;
+use crate::config::{Config, Command};
+use crate::error::LiteError;
+use crate::feeds::{Feed, FeedFormat};
+use crate::routes::{base_url, connect, parse_id, unknown_page};
+use crate::session::{Role, Session};
+
+use crate::templates::{Account, redirect_page, settings_page, modlog_page, mod_page, report_page, reports_page, inbox_page, message_page, signup_page, registered_page, login_page, reply_page, post_form_page, PostFormTarget, post_list_page, post_page, comment_page, community_info_page, communities_page, user_page, search_page, comment_context};
+use crate::lemmy_api::{Api, Instances, PagingParams, SearchParams, LoginForm, SignupForm, Registered, VoteForm, SaveForm, CommentForm, PostForm, get_post_list, get_post, get_community, get_community_list, get_user, search, login, get_username, get_site_info, get_captcha, register, logout, vote_post, vote_comment, save_post, save_comment, remove_post, lock_post, sticky_post, remove_comment, ban_from_community, SettingsForm, get_user_settings, save_user_settings, get_modlog, report_post, report_comment, get_reports, resolve_post_report, resolve_comment_report, PostDetail, CommentView, InboxTab, get_inbox, get_unread_count, get_role, mark_read, mark_all_read, get_conversation, create_private_message, create_comment, create_post, edit_post, delete_post, follow_community};
+
+// Actions that change anything on the instance need a session, visitors without one are sent to the login form
+macro_rules! logged_in {
+    ($session:expr, $instance:expr) => {
+        match $session {
+            Some(session) => session,
+            None => return Ok(login_redirect($instance))
+        }
+    }
+}
 
 #[derive(Deserialize)]
 struct RedirForm {
@@ -44,6 +73,56 @@ struct PathParams4 {
     sub_command: String
 }
 
+// Comment composition, sub_id is the comment being replied to
+#[derive(Deserialize)]
+struct CommentPath {
+    inst: String,
+    id: String,
+    sub_id: Option<String>
+}
+
+#[derive(Deserialize)]
+struct ItemPath {
+    inst: String,
+    id: String
+}
+
+#[derive(Deserialize)]
+struct SubscribeForm {
+    f: bool     // Follow, false unsubscribes
+}
+
+#[derive(Deserialize)]
+struct ReadForm {
+    t: String,      // Inbox tab
+    id: Option<i32> // None marks the whole inbox as read
+}
+
+#[derive(Deserialize)]
+struct ModForm {
+    a: String,      // Action
+    #[serde(default)]
+    reason: String, // Not sent for locks and stickies
+    c: i32,         // Community of the post or comment
+    u: i32          // Its creator, for bans
+}
+
+#[derive(Deserialize)]
+struct ReportForm {
+    reason: String
+}
+
+#[derive(Deserialize)]
+struct ResolveForm {
+    t: String,  // post or comment
+    id: i32     // Report
+}
+
+#[derive(Deserialize)]
+struct DeleteForm {
+    d: bool     // False restores
+}
+
 #[derive(Deserialize)]
 struct PathParams5 {
     inst: String,
@@ -55,119 +134,908 @@ struct PathParams5 {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| { App::new()
-        .data(Client::default())
+    let config = match Config::load() {
+        Ok(Command::Run(c)) => c,
+        Ok(Command::Help) => {
+            println!("{}", config::HELP);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(2);
+        }
+    };
+    config::init(*config);
+    let config = config::get();
+    let instances = web::Data::new(Instances::default());
+    let cache = web::Data::new(ApiCache::new(config.cache_size));
+    let cache_stats = cache.clone();
+
+    // Shares the API cache and instance versions with the HTTP server
+    let gemini = match (&config.gemini.listen, &config.gemini.cert, &config.gemini.key) {
+        (Some(addr), Some(cert), Some(key)) => {
+            let tls = gemini::tls_config(cert, key).unwrap_or_else(|e| {
+                eprintln!("Invalid configuration: gemini: {}", e);
+                process::exit(2);
+            });
+            Some(gemini::server(addr, tls, instances.clone(), cache.clone()).map_err(|e| {
+                eprintln!("Unable to listen on {}: {}", addr, e);
+                e
+            })?)
+        },
+        _ => None
+    };
+
+    let mut server = HttpServer::new(move || { App::new()
+        .app_data(instances.clone())
+        .app_data(cache.clone())
+        .wrap_fn(|req, srv| {
+            let json = json::wants_json(req.query_string());
+            srv.call(req).map_ok(move |res| if json { json::error_response(res) } else { res })
+        })
+        .app_data(web::QueryConfig::default().error_handler(|e, req|
+            invalid_path(req, e.to_string()).into()))
+        .app_data(web::PathConfig::default().error_handler(|e, req|
+            invalid_path(req, e.to_string()).into()))
+        .app_data(web::FormConfig::default().error_handler(|e, req|
+            invalid_path(req, e.to_string()).into()))
+        .data(Client::builder()
+            .timeout(Duration::from_secs(config.upstream_timeout))
+            .finish())
         .route(
             "/", web::get().to(index)
         ).route(
             "/{inst}", web::get().to(lvl1)
         ).route(
             "/{inst}/{command}", web::get().to(lvl2)
+        ).route(
+            "/{inst}/login", web::post().to(login_action)
+        ).route(
+            "/{inst}/signup", web::post().to(signup_action)
+        ).route(
+            "/{inst}/logout", web::post().to(logout_action)
+        ).route(
+            "/{inst}/settings", web::post().to(settings_action)
+        ).route(
+            "/{inst}/inbox/read", web::post().to(read_action)
+        ).route(
+            "/{inst}/reports/resolve", web::post().to(resolve_action)
+        ).route(
+            "/{inst}/{command}/{id}/vote", web::post().to(vote_action)
+        ).route(
+            "/{inst}/{command}/{id}/save", web::post().to(save_action)
+        ).route(
+            "/{inst}/{command}/{id}/mod", web::post().to(mod_action)
+        ).route(
+            "/{inst}/c/{id}/submit", web::post().to(submit_action)
+        ).route(
+            "/{inst}/c/{id}/subscribe", web::post().to(subscribe_action)
+        ).route(
+            "/{inst}/u/{id}/message", web::post().to(message_action)
+        ).route(
+            "/{inst}/post/{id}/edit", web::post().to(edit_action)
+        ).route(
+            "/{inst}/post/{id}/delete", web::post().to(delete_action)
+        ).route(
+            "/{inst}/post/{id}/comment", web::post().to(comment_action)
+        ).route(
+            "/{inst}/post/{id}/comment/{sub_id}/reply", web::get().to(reply)
+        ).route(
+            "/{inst}/post/{id}/comment/{sub_id}/reply", web::post().to(comment_action)
+        ).route(
+            "/{inst}/post/{id}/report", web::get().to(report)
+        ).route(
+            "/{inst}/post/{id}/report", web::post().to(report_action)
+        ).route(
+            "/{inst}/post/{id}/comment/{sub_id}/report", web::get().to(report)
+        ).route(
+            "/{inst}/post/{id}/comment/{sub_id}/report", web::post().to(report_action)
         ).route(
             "/{inst}/{command}/{id}", web::get().to(lvl3)
         ).route(
             "/{inst}/{command}/{id}/{sub_command}", web::get().to(lvl4)
         ).route(
             "/{inst}/{command}/{id}/{sub_command}/{sub_id}", web::get().to(lvl5)
+        ).default_service(
+            web::route().to(not_found)
         )
-    })
-    .bind("127.0.0.1:1131")?
-    .run().await
+    });
+
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    for addr in &config.listen {
+        server = server.bind(addr).map_err(|e| {
+            eprintln!("Unable to listen on {}: {}", addr, e);
+            e
+        })?;
+    }
+    let res = match gemini {
+        Some(gemini) => try_join(server.run(), gemini).await.map(|_| ()),
+        None => server.run().await
+    };
+    // Hit rates on shutdown, to help size cache_size
+    println!("Cache: {}", cache_stats.stats());
+    res
+}
+
+async fn index(web::Query(query): web::Query<RedirForm>) -> Result<HttpResponse, LiteError>{
+    html_res(redirect_page(query.i.ok_or_else(|| LiteError::InvalidPath {
+        instance: None,
+        detail: "i parameter missing. Is NginX running?".to_string()
+    })?))
 }
 
-async fn index(web::Query(query): web::Query<RedirForm>) -> Result<HttpResponse>{
-    html_res(redirect_page(query.i.ok_or(error::ErrorExpectationFailed("i parameter missing. Is NginX running?"))?))
+async fn not_found(req: HttpRequest) -> Result<HttpResponse, LiteError> {
+    Err(LiteError::NotFound {
+        instance: None,
+        detail: format!("There is no page at {}", req.path())
+    })
 }
 
-async fn lvl1(path: web::Path<String>, query: web::Query<PagingParams>, data_client: web::Data<Client>) -> Result<HttpResponse>{
-    let inst = &path.to_string();
+async fn lvl1(req: HttpRequest, path: web::Path<String>, query: web::Query<PagingParams>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError>{
+    let (inst, feed) = FeedFormat::split(&path);
+    let inst = &inst.to_string();
     let client = &data_client.into_inner();
+    let paging_params = &query.into_inner();
+    if let Some(format) = feed {
+        let api = &connect(client, &cache, &instances, inst, None).await?;
+        let post_list = get_post_list(api, None, None, Some(paging_params)).await?;
+        let base = &base_url(&req);
+        let mut feed = Feed::new(base, inst, inst.clone(), &format!("/{}", inst), &req.uri().to_string());
+        feed.push_posts(&post_list.posts);
+        return feed_res(&feed, format);
+    }
+    let (ref api, account) = connect_page(client, &cache, &instances, inst, session.as_ref()).await?;
 
     let now = &Utc::now().naive_utc();
-    let paging_params = &query.into_inner();
+    if paging_params.t.as_deref() == Some("Subscribed") {
+        logged_in!(session.as_ref(), inst);
+    }
 
-    let post_list = get_post_list(client, inst, None, None, Some(paging_params)).await?;
-    html_res(post_list_page(inst, post_list, now, None, Some(paging_params)))
+    let post_list = get_post_list(api, None, None, Some(paging_params)).await?;
+    if json::wants_json(req.query_string()) {
+        return json::page(inst, json::post_list_page(None, &post_list.posts, paging_params.p, now));
+    }
+    if let Some(ref text_req) = text::wants_text(&req) {
+        return text_res(text::post_list_page(text_req, inst, None, &post_list.posts, now, paging_params));
+    }
+    html_res(post_list_page(inst, session.as_ref(), account, post_list, now, None, None, Some(paging_params)))
 }
 
-async fn lvl2(p: web::Path<PathParams2>, query: web::Query<SearchParams>, data_client: web::Data<Client>) -> Result<HttpResponse> {
+async fn lvl2(req: HttpRequest, p: web::Path<PathParams2>, query: web::Query<SearchParams>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
     let client = &data_client.into_inner();
     let search_params = &query.into_inner();
+    if let (command, Some(format)) = FeedFormat::split(&p.command) {
+        if command != "search" {
+            return Err(unknown_page(&p.inst));
+        }
+        let api = &connect(client, &cache, &instances, &p.inst, None).await?;
+        let search_res = search(api, search_params).await?;
+        let base = &base_url(&req);
+        let title = format!("{} - search for {}", p.inst, search_params.q.as_deref().unwrap_or_default());
+        let mut feed = Feed::new(base, &p.inst, title, &format!("/{}/search", p.inst), &req.uri().to_string());
+        feed.push_posts(&search_res.posts);
+        feed.push_comments(&search_res.comments, search_params.s.as_deref());
+        return feed_res(&feed, format);
+    }
+    let (ref api, account) = connect_page(client, &cache, &instances, &p.inst, session.as_ref()).await?;
 
     if p.command == "communities" {
         let paging_params = &PagingParams {
             s: search_params.s.clone().or(Some("TopAll".to_string())),
             p: search_params.p,
-            l: search_params.l
+            l: search_params.l,
+            t: None
         };
-        let communities = get_community_list(client, &p.inst, Some(paging_params)).await?;
-        html_res(communities_page(&p.inst, communities, Some(paging_params)))
+        let communities = get_community_list(api, Some(paging_params)).await?;
+        if json::wants_json(req.query_string()) {
+            return json::page(&p.inst, json::communities_page(&communities, paging_params.p));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::communities_page(text_req, &p.inst, communities, paging_params));
+        }
+        html_res(communities_page(&p.inst, session.as_ref(), account, communities, Some(paging_params)))
     } else if p.command == "search" {
         let now = &Utc::now().naive_utc();
         let search_res = match search_params.q {
-            Some(ref query) if !query.is_empty() => Some(search(client, &p.inst, search_params).await?),
+            Some(ref query) if !query.is_empty() => Some(search(api, search_params).await?),
             _ => None
         };
-
-        html_res(search_page(&p.inst, now, search_res, search_params))
+        if json::wants_json(req.query_string()) {
+            let query = search_params.q.as_deref().unwrap_or_default();
+            return json::page(&p.inst, json::search_page(query, search_res.as_ref(), search_params.p, now));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::search_page(text_req, &p.inst, search_res, search_params, now));
+        }
+        html_res(search_page(&p.inst, session.as_ref(), account, now, search_res, search_params))
+    } else if p.command == "login" {
+        html_res(login_page(&p.inst, None, None))
+    } else if p.command == "signup" {
+        signup_res(api, &p.inst, None, None).await
+    } else if p.command == "inbox" {
+        inbox_res(api, &p.inst, session.as_ref(), account, InboxTab::Replies, search_params.p).await
+    } else if p.command == "settings" {
+        let session = logged_in!(session.as_ref(), &p.inst);
+        let settings = get_user_settings(api).await?;
+        let form = SettingsForm {
+            name: settings.display_name.unwrap_or_default(),
+            bio: settings.bio.unwrap_or_default(),
+            avatar: settings.avatar.unwrap_or_default(),
+            matrix: settings.matrix_user_id.unwrap_or_default(),
+            s: settings.default_sort_type,
+            t: settings.default_listing_type,
+            nsfw: settings.show_nsfw.then(|| "on".to_string()),
+            avatars: settings.show_avatars.then(|| "on".to_string()),
+            notify: settings.send_notifications_to_email.then(|| "on".to_string()),
+            preview: None
+        };
+        html_res(settings_page(&p.inst, session, account, &form, None))
+    } else if p.command == "modlog" {
+        let paging_params = &search_params.to_paging_params();
+        let modlog = get_modlog(api, None, Some(paging_params)).await?;
+        let now = &Utc::now().naive_utc();
+        if json::wants_json(req.query_string()) {
+            return json::page(&p.inst, json::modlog_page(&modlog, None, paging_params.p, now));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::modlog_page(text_req, &p.inst, modlog, None, now, paging_params));
+        }
+        html_res(modlog_page(&p.inst, session.as_ref(), account, modlog, None, now, Some(paging_params)))
+    } else if p.command == "reports" {
+        let session = logged_in!(session.as_ref(), &p.inst);
+        let paging_params = &PagingParams {
+            s: None,
+            p: search_params.p,
+            l: None,
+            t: None
+        };
+        let reports = get_reports(api, Some(paging_params)).await?;
+        let now = &Utc::now().naive_utc();
+        html_res(reports_page(&p.inst, session, account, reports, now, paging_params))
     } else {
-        Err(error::ErrorExpectationFailed("Invalid parameters"))
+        Err(unknown_page(&p.inst))
     }
 }
 
-async fn lvl3(p: web::Path<PathParams3>, query: web::Query<PagingParams>, data_client: web::Data<Client>) -> Result<HttpResponse>{
+async fn lvl3(req: HttpRequest, p: web::Path<PathParams3>, query: web::Query<PagingParams>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError>{
     let client = &data_client.into_inner();
-    let now = &Utc::now().naive_utc();
     let paging_params = &query.into_inner();
+    if let (id, Some(format)) = FeedFormat::split(&p.id) {
+        let api = &connect(client, &cache, &instances, &p.inst, None).await?;
+        let base = &base_url(&req);
+        let path = format!("/{}/{}/{}", p.inst, p.command, id);
+        let self_path = &req.uri().to_string();
+        let feed = if p.command == "c" {
+            let post_list = get_post_list(api, None, Some(&id.to_string()), Some(paging_params)).await?;
+            let mut feed = Feed::new(base, &p.inst, format!("{} - /c/{}", p.inst, id), &path, self_path);
+            feed.push_posts(&post_list.posts);
+            feed
+        } else if p.command == "post" {
+            let post_detail = get_post(api, id).await?;
+            let title = format!("{} - comments on {}", p.inst, post_detail.post.name);
+            let mut feed = Feed::new(base, &p.inst, title, &path, self_path);
+            feed.push_thread(&post_detail.post, &post_detail.comments);
+            feed
+        } else if p.command == "u" {
+            let user = get_user(api, id, false, Some(paging_params)).await?;
+            let mut feed = Feed::new(base, &p.inst, format!("{} - /u/{}", p.inst, id), &path, self_path);
+            feed.push_posts(&user.posts);
+            feed.push_comments(&user.comments, paging_params.s.as_deref());
+            feed
+        } else {
+            return Err(unknown_page(&p.inst));
+        };
+        return feed_res(&feed, format);
+    }
+    let (ref api, account) = connect_page(client, &cache, &instances, &p.inst, session.as_ref()).await?;
+    let now = &Utc::now().naive_utc();
 
     if p.command == "post" {
-        let post_detail = get_post(client, &p.inst, &p.id).await?;
-        html_res(post_page(&p.inst, post_detail, now))
+        let post_detail = get_post(api, &p.id).await?;
+        if json::wants_json(req.query_string()) {
+            return json::page(&p.inst, json::post_page(&post_detail, now));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::post_page(text_req, &p.inst, post_detail, now));
+        }
+        html_res(post_page(&p.inst, session.as_ref(), account, post_detail, now))
+    } else if p.command == "c" && json::wants_json(req.query_string()) {
+        let post_list = get_post_list(api, None, Some(&p.id), Some(paging_params)).await?;
+        json::page(&p.inst, json::post_list_page(Some(&p.id), &post_list.posts, paging_params.p, now))
+    } else if let Some(ref text_req) = text::wants_text(&req).filter(|_| p.command == "c") {
+        let post_list = get_post_list(api, None, Some(&p.id), Some(paging_params)).await?;
+        text_res(text::post_list_page(text_req, &p.inst, Some(&p.id), &post_list.posts, now, paging_params))
     } else if p.command == "c" {
-        let post_list = get_post_list(client, &p.inst, None,
-            Some(&p.id), Some(paging_params)).await?;
-        html_res(post_list_page(&p.inst, post_list, now, Some(&p.id), Some(paging_params)))
+        // Only a logged in user has a subscription or moderator tools to show
+        let (post_list, community) = if session.is_some() {
+            let (post_list, community) = try_join(
+                get_post_list(api, None, Some(&p.id), Some(paging_params)),
+                get_community(api, &p.id)
+            ).await?;
+            (post_list, Some(community))
+        } else {
+            (get_post_list(api, None, Some(&p.id), Some(paging_params)).await?, None)
+        };
+        html_res(post_list_page(&p.inst, session.as_ref(), account, post_list, now, Some(&p.id), community.as_ref(), Some(paging_params)))
+    } else if p.command == "inbox" {
+        let tab = InboxTab::from_path(&p.id).ok_or_else(|| unknown_page(&p.inst))?;
+        inbox_res(api, &p.inst, session.as_ref(), account, tab, paging_params.p).await
     } else if p.command == "u" {
-        let user = get_user(client, &p.inst, &p.id, Some(paging_params)).await?;
-        html_res(user_page(&p.inst, user, now, Some(paging_params)))
+        let user = get_user(api, &p.id, false, Some(paging_params)).await?;
+        if json::wants_json(req.query_string()) {
+            return json::page(&p.inst, json::user_page(&user, false, paging_params.p, now));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::user_page(text_req, &p.inst, user, false, now, paging_params));
+        }
+        html_res(user_page(&p.inst, session.as_ref(), account, user, false, now, Some(paging_params)))
     } else {
-        Err(error::ErrorExpectationFailed("Invalid parameters"))
+        Err(unknown_page(&p.inst))
     }
 }
 
-async fn lvl4(p: web::Path<PathParams4>, query: web::Query<PagingParams>, data_client: web::Data<Client>) -> Result<HttpResponse> {
+async fn lvl4(req: HttpRequest, p: web::Path<PathParams4>, query: web::Query<PagingParams>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
     let client = &data_client.into_inner();
+    let (ref api, account) = connect_page(client, &cache, &instances, &p.inst, session.as_ref()).await?;
     if p.command == "c" && p.sub_command == "info" {
-        let community = get_community(client, &p.inst, &p.id).await?;
-        html_res(community_info_page(&p.inst, community))
+        let community = get_community(api, &p.id).await?;
+        if json::wants_json(req.query_string()) {
+            return json::page(&p.inst, json::community_page(&community));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::community_info_page(text_req, &p.inst, community));
+        }
+        html_res(community_info_page(&p.inst, session.as_ref(), account, community))
+    } else if p.command == "c" && p.sub_command == "modlog" {
+        // The modlog is filtered by community id, not name
+        let community = get_community(api, &p.id).await?;
+        let now = &Utc::now().naive_utc();
+        let paging_params = &query.into_inner();
+        let modlog = get_modlog(api, Some(community.community.id), Some(paging_params)).await?;
+        if json::wants_json(req.query_string()) {
+            return json::page(&p.inst, json::modlog_page(&modlog, Some(&p.id), paging_params.p, now));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::modlog_page(text_req, &p.inst, modlog, Some(&p.id), now, paging_params));
+        }
+        html_res(modlog_page(&p.inst, session.as_ref(), account, modlog, Some(&p.id), now, Some(paging_params)))
+    } else if p.command == "u" && p.sub_command == "saved" {
+        let session = logged_in!(session.as_ref(), &p.inst);
+        // The instance would return the viewer's saved items under any username
+        if session.username != p.id {
+            return Err(LiteError::NotFound {
+                instance: Some(p.inst.clone()),
+                detail: "Saved items are only visible to their owner".to_string()
+            });
+        }
+        let now = &Utc::now().naive_utc();
+        let paging_params = &query.into_inner();
+        let user = get_user(api, &p.id, true, Some(paging_params)).await?;
+        if json::wants_json(req.query_string()) {
+            return json::page(&p.inst, json::user_page(&user, true, paging_params.p, now));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::user_page(text_req, &p.inst, user, true, now, paging_params));
+        }
+        html_res(user_page(&p.inst, Some(session), account, user, true, now, Some(paging_params)))
+    } else if p.command == "u" && p.sub_command == "message" {
+        let session = logged_in!(session.as_ref(), &p.inst);
+        message_res(api, &p.inst, &p.id, session, account, query.p, None, None).await
+    } else if p.command == "c" && p.sub_command == "submit" {
+        let session = logged_in!(session.as_ref(), &p.inst);
+        html_res(post_form_page(&p.inst, Some(session), account, PostFormTarget::Submit(&p.id), None, None))
+    } else if p.command == "post" && p.sub_command == "edit" {
+        let session = logged_in!(session.as_ref(), &p.inst);
+        let post = get_post(api, &p.id).await?.post;
+        let form = PostForm {
+            name: post.name,
+            url: post.url.unwrap_or_default(),
+            body: post.body.unwrap_or_default(),
+            nsfw: if post.nsfw { Some("on".to_string()) } else { None }
+        };
+        html_res(post_form_page(&p.inst, Some(session), account, PostFormTarget::Edit(post.id), Some(&form), None))
     } else {
-        Err(error::ErrorExpectationFailed("Invalid path"))
+        Err(unknown_page(&p.inst))
     }
 }
 
-async fn lvl5(p: web::Path<PathParams5>, query: web::Query<PagingParams>, data_client: web::Data<Client>) -> Result<HttpResponse> {
+async fn lvl5(req: HttpRequest, p: web::Path<PathParams5>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
     let client = &data_client.into_inner();
+    let (ref api, account) = connect_page(client, &cache, &instances, &p.inst, session.as_ref()).await?;
     let now = &Utc::now().naive_utc();
 
     if p.command == "post" && p.sub_command == "comment" {
-        let post_detail = get_post(client, &p.inst, &p.id).await?;
-        let comment_id = match p.sub_id.parse::<i32>() {
-            Ok(cid) => cid,
-            Err(_) => return Err(error::ErrorExpectationFailed("Comment ID is invalid"))
-        };
-        let comment = match post_detail.comments.iter().find(|ref c| c.id == comment_id) {
+        let post_detail = get_post(api, &p.id).await?;
+        let comment_id = parse_id(&p.inst, &p.sub_id, "comment")?;
+        let comment = match post_detail.comments.iter().find(|c| c.id == comment_id) {
             Some(c) => c.clone(),
-            None => return Err(error::ErrorExpectationFailed("Comment doesn't belong to this post"))
+            None => return Err(LiteError::NotFound {
+                instance: Some(p.inst.clone()),
+                detail: format!("Comment {} doesn't belong to post {}", comment_id, p.id)
+            })
         };
-        html_res(comment_page(&p.inst, comment, post_detail, now))
-        
+        if json::wants_json(req.query_string()) {
+            let (comments, root_id) = comment_context(&comment, post_detail.comments.clone());
+            return json::page(&p.inst, json::comment_page(&post_detail, &comments, root_id, comment.id, now));
+        }
+        if let Some(ref text_req) = text::wants_text(&req) {
+            return text_res(text::comment_page(text_req, &p.inst, comment, post_detail, now));
+        }
+        html_res(comment_page(&p.inst, session.as_ref(), account, comment, post_detail, now))
+
     } else {
-        Err(error::ErrorExpectationFailed("Invalid parameters"))
+        Err(unknown_page(&p.inst))
+    }
+}
+
+async fn login_action(path: web::Path<String>, form: web::Form<LoginForm>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let inst = &path.into_inner();
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, inst, None).await?;
+
+    // Wrong credentials are shown on the form rather than as an error page
+    let jwt = match login(api, &form).await {
+        Ok(jwt) => jwt,
+        Err(e) => return match e.form_message() {
+            Some(message) => html_status(StatusCode::BAD_REQUEST, login_page(inst, Some(&form.u), Some(&message))),
+            None => Err(e)
+        }
+    };
+    let username = get_username(&api.with_auth(&jwt)).await?;
+    Ok(logged_in_res(inst, &Session {
+        // Only decides which links are shown, logging in still works when it can't be looked up
+        role: get_role(api, &username).await.unwrap_or(Role::User),
+        username,
+        jwt
+    }))
+}
+
+async fn signup_action(path: web::Path<String>, form: web::Form<SignupForm>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let inst = &path.into_inner();
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, inst, None).await?;
+    if form.pw != form.pw2 {
+        return signup_res(api, inst, Some(&form), Some("The passwords don't match".to_string())).await;
+    }
+
+    match register(api, &form).await {
+        Ok(Registered::LoggedIn(jwt)) => {
+            let username = form.u.trim().to_string();
+            Ok(logged_in_res(inst, &Session {
+                role: get_role(api, &username).await.unwrap_or(Role::User),
+                username,
+                jwt
+            }))
+        },
+        Ok(Registered::ApplicationSent) => html_res(registered_page(inst,
+            "Your application was sent. You will be able to log in once an admin accepts it.")),
+        Ok(Registered::VerifyEmail) => html_res(registered_page(inst,
+            "Check your email for a link to verify your account, then log in.")),
+        Err(e) => match e.form_message() {
+            Some(message) => signup_res(api, inst, Some(&form), Some(message)).await,
+            None => Err(e)
+        }
+    }
+}
+
+// A captcha can only be answered once, so the form always comes with a new one
+async fn signup_res(api: &Api<'_>, inst: &String, form: Option<&SignupForm>, error: Option<String>) -> Result<HttpResponse, LiteError> {
+    let (site, captcha) = try_join(get_site_info(api), get_captcha(api)).await?;
+    let status = if error.is_some() { StatusCode::BAD_REQUEST } else { StatusCode::OK };
+    html_status(status, signup_page(inst, &site, captcha.as_ref(), form, error.as_deref()))
+}
+
+fn logged_in_res(instance: &str, session: &Session) -> HttpResponse {
+    let mut res = HttpResponse::SeeOther();
+    session::set_cookies(&mut res, instance, session);
+    res.header(header::LOCATION, format!("/{}", instance)).finish()
+}
+
+async fn vote_action(req: HttpRequest, p: web::Path<PathParams3>, form: web::Form<VoteForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let id = parse_id(&p.inst, &p.id, &p.command)?;
+    if !(-1..=1).contains(&form.v) {
+        return Err(LiteError::InvalidPath {
+            instance: Some(p.inst.clone()),
+            detail: format!("{} is not a valid vote", form.v)
+        });
+    }
+
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+    let anchor = match p.command.as_str() {
+        "post" => {
+            vote_post(api, id, form.v).await?;
+            format!("p{}", id)
+        },
+        "comment" => {
+            vote_comment(api, id, form.v).await?;
+            format!("c{}", id)
+        },
+        _ => return Err(unknown_page(&p.inst))
+    };
+    Ok(see_other(format!("{}#{}", back_path(&req, &p.inst), anchor)))
+}
+
+async fn save_action(req: HttpRequest, p: web::Path<PathParams3>, form: web::Form<SaveForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let id = parse_id(&p.inst, &p.id, &p.command)?;
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+    let anchor = match p.command.as_str() {
+        "post" => {
+            save_post(api, id, form.s).await?;
+            format!("p{}", id)
+        },
+        "comment" => {
+            save_comment(api, id, form.s).await?;
+            format!("c{}", id)
+        },
+        _ => return Err(unknown_page(&p.inst))
+    };
+    Ok(see_other(format!("{}#{}", back_path(&req, &p.inst), anchor)))
+}
+
+async fn read_action(req: HttpRequest, path: web::Path<String>, form: web::Form<ReadForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let inst = &path.into_inner();
+    let session = logged_in!(session.as_ref(), inst);
+    let tab = InboxTab::from_path(&form.t).ok_or_else(|| LiteError::InvalidPath {
+        instance: Some(inst.clone()),
+        detail: format!("{} is not an inbox tab", form.t)
+    })?;
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, inst, Some(session)).await?;
+    match form.id {
+        Some(id) => mark_read(api, tab, id).await?,
+        None => mark_all_read(api).await?
+    }
+    Ok(see_other(back_path(&req, inst)))
+}
+
+async fn mod_action(req: HttpRequest, p: web::Path<PathParams3>, form: web::Form<ModForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let id = parse_id(&p.inst, &p.id, &p.command)?;
+    // Lemmy doesn't record a reason for locks and stickies
+    let reason = form.reason.trim();
+    if reason.is_empty() && !matches!(form.a.as_str(), "lock" | "unlock" | "sticky" | "unsticky") {
+        return html_status(StatusCode::BAD_REQUEST, mod_page(&p.inst, session, Account::default(), &p.command, id, form.c, form.u,
+            &form.a, "A reason is required"));
+    }
+
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+    match (p.command.as_str(), form.a.as_str()) {
+        ("post", "remove") | ("post", "restore") => remove_post(api, id, form.a == "remove", reason).await?,
+        ("post", "lock") | ("post", "unlock") => lock_post(api, id, form.a == "lock").await?,
+        ("post", "sticky") | ("post", "unsticky") => sticky_post(api, id, form.a == "sticky").await?,
+        ("comment", "remove") | ("comment", "restore") => remove_comment(api, id, form.a == "remove", reason).await?,
+        ("post", "ban") | ("post", "unban") | ("comment", "ban") | ("comment", "unban") =>
+            ban_from_community(api, form.c, form.u, form.a == "ban", reason).await?,
+        _ => return Err(LiteError::InvalidPath {
+            instance: Some(p.inst.clone()),
+            detail: format!("{} is not a moderator action on a {}", form.a, p.command)
+        })
+    }
+    let anchor = if p.command == "post" { "p" } else { "c" };
+    Ok(see_other(format!("{}#{}{}", back_path(&req, &p.inst), anchor, id)))
+}
+
+async fn settings_action(path: web::Path<String>, form: web::Form<SettingsForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let inst = &path.into_inner();
+    let session = logged_in!(session.as_ref(), inst);
+    if form.preview.is_some() {
+        return html_res(settings_page(inst, session, Account::default(), &form, None));
+    }
+
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, inst, Some(session)).await?;
+    match save_user_settings(api, &form).await {
+        Ok(()) => Ok(see_other(format!("/{}/u/{}", inst, session.username))),
+        Err(e) => match e.form_message() {
+            Some(message) => html_status(StatusCode::BAD_REQUEST, settings_page(inst, session, Account::default(), &form, Some(&message))),
+            None => Err(e)
+        }
+    }
+}
+
+async fn logout_action(path: web::Path<String>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let inst = &path.into_inner();
+    if let Some(ref s) = session {
+        let client = &data_client.into_inner();
+        // The cookies are removed even if the instance can't be told, the token then expires on its own
+        if let Ok(api) = connect(client, &cache, &instances, inst, Some(s)).await {
+            let _ = logout(&api).await;
+        }
+    }
+
+    let mut res = HttpResponse::SeeOther();
+    session::remove_cookies(&mut res, inst);
+    Ok(res.header(header::LOCATION, format!("/{}", inst)).finish())
+}
+
+// Pages also show the unread notification count of a logged in user
+async fn connect_page<'a>(client: &'a Client, cache: &'a ApiCache, instances: &Instances, instance: &'a str, session: Option<&'a Session>) -> Result<(Api<'a>, Account), LiteError> {
+    let api = connect(client, cache, instances, instance, session).await?;
+    let mut account = Account::default();
+    if session.is_some() {
+        // Only a navbar addition, the page is still useful without it
+        account.unread = get_unread_count(&api).await.unwrap_or(None);
+    }
+    Ok((api, account))
+}
+
+// Newest first, the sort and limit options of other pages don't apply to notifications
+async fn inbox_res(api: &Api<'_>, inst: &String, session: Option<&Session>, account: Account, tab: InboxTab, page: Option<i32>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session, inst);
+    let paging_params = &PagingParams {
+        s: Some("New".to_string()),
+        p: page,
+        l: None,
+        t: None
+    };
+    let inbox = get_inbox(api, tab, Some(paging_params)).await?;
+    let now = &Utc::now().naive_utc();
+    html_res(inbox_page(inst, session, account, inbox, now, paging_params))
+}
+
+async fn submit_action(p: web::Path<ItemPath>, form: web::Form<PostForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let target = PostFormTarget::Submit(&p.id);
+    if form.name.trim().is_empty() {
+        return post_form_error(&p.inst, session, target, &form, "Title cannot be empty".to_string());
+    }
+
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+    let community = get_community(api, &p.id).await?;
+    match create_post(api, community.community.id, &form).await {
+        Ok(post) => Ok(see_other(format!("/{}/post/{}", p.inst, post.id))),
+        Err(e) => match e.form_message() {
+            Some(message) => post_form_error(&p.inst, session, target, &form, message),
+            None => Err(e)
+        }
+    }
+}
+
+async fn edit_action(p: web::Path<ItemPath>, form: web::Form<PostForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let post_id = parse_id(&p.inst, &p.id, "post")?;
+    let target = PostFormTarget::Edit(post_id);
+    if form.name.trim().is_empty() {
+        return post_form_error(&p.inst, session, target, &form, "Title cannot be empty".to_string());
     }
+
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+    match edit_post(api, post_id, &form).await {
+        Ok(_) => Ok(see_other(format!("/{}/post/{}", p.inst, post_id))),
+        Err(e) => match e.form_message() {
+            Some(message) => post_form_error(&p.inst, session, target, &form, message),
+            None => Err(e)
+        }
+    }
+}
+
+async fn subscribe_action(req: HttpRequest, p: web::Path<ItemPath>, form: web::Form<SubscribeForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+    let community = get_community(api, &p.id).await?;
+    follow_community(api, community.community.id, form.f).await?;
+    Ok(see_other(back_path(&req, &p.inst)))
+}
+
+fn post_form_error(instance: &String, session: &Session, target: PostFormTarget, form: &PostForm, message: String) -> Result<HttpResponse, LiteError> {
+    html_status(StatusCode::BAD_REQUEST, post_form_page(instance, Some(session), Account::default(), target, Some(form), Some(&message)))
+}
+
+async fn delete_action(p: web::Path<ItemPath>, form: web::Form<DeleteForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let post_id = parse_id(&p.inst, &p.id, "post")?;
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+    delete_post(api, post_id, form.d).await?;
+    Ok(see_other(format!("/{}/post/{}", p.inst, post_id)))
+}
+
+async fn reply(p: web::Path<CommentPath>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let client = &data_client.into_inner();
+    let (ref api, account) = connect_page(client, &cache, &instances, &p.inst, Some(session)).await?;
+    compose_res(api, &p, session, account, None, None).await
+}
+
+// Posts a comment, or shows it back with a preview or the error the instance returned
+async fn comment_action(p: web::Path<CommentPath>, form: web::Form<CommentForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let post_id = parse_id(&p.inst, &p.id, "post")?;
+    let parent_id = p.sub_id.as_ref().map(|id| parse_id(&p.inst, id, "comment")).transpose()?;
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+
+    if form.preview.is_some() {
+        return compose_res(api, &p, session, Account::default(), Some(&form), None).await;
+    }
+    if form.content.trim().is_empty() {
+        return compose_res(api, &p, session, Account::default(), Some(&form), Some("Comment cannot be empty".to_string())).await;
+    }
+    match create_comment(api, post_id, parent_id, &form.content).await {
+        Ok(comment) => Ok(see_other(format!("/{}/post/{}#c{}", p.inst, post_id, comment.id))),
+        Err(e) => match e.form_message() {
+            Some(message) => compose_res(api, &p, session, Account::default(), Some(&form), Some(message)).await,
+            None => Err(e)
+        }
+    }
+}
+
+async fn compose_res(api: &Api<'_>, p: &CommentPath, session: &Session, account: Account, form: Option<&CommentForm>, error: Option<String>) -> Result<HttpResponse, LiteError> {
+    let post_detail = get_post(api, &p.id).await?;
+    let parent = path_comment(p, &post_detail)?;
+
+    let status = if error.is_some() { StatusCode::BAD_REQUEST } else { StatusCode::OK };
+    let now = &Utc::now().naive_utc();
+    html_status(status, reply_page(&p.inst, Some(session), account, &post_detail.post, parent, form, error.as_deref(), now))
+}
+
+async fn report(p: web::Path<CommentPath>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let client = &data_client.into_inner();
+    let (ref api, account) = connect_page(client, &cache, &instances, &p.inst, Some(session)).await?;
+    report_res(api, &p, session, account, "", None).await
+}
+
+// Reports the post, or the comment when there is one in the path
+async fn report_action(p: web::Path<CommentPath>, form: web::Form<ReportForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let post_id = parse_id(&p.inst, &p.id, "post")?;
+    let comment_id = p.sub_id.as_ref().map(|id| parse_id(&p.inst, id, "comment")).transpose()?;
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+
+    let reason = form.reason.trim();
+    if reason.is_empty() {
+        return report_res(api, &p, session, Account::default(), &form.reason, Some("A reason is required".to_string())).await;
+    }
+    let res = match comment_id {
+        Some(id) => report_comment(api, id, reason).await,
+        None => report_post(api, post_id, reason).await
+    };
+    match res {
+        Ok(()) => Ok(see_other(match comment_id {
+            Some(id) => format!("/{}/post/{}#c{}", p.inst, post_id, id),
+            None => format!("/{}/post/{}", p.inst, post_id)
+        })),
+        Err(e) => match e.form_message() {
+            Some(message) => report_res(api, &p, session, Account::default(), &form.reason, Some(message)).await,
+            None => Err(e)
+        }
+    }
+}
+
+async fn report_res(api: &Api<'_>, p: &CommentPath, session: &Session, account: Account, reason: &str, error: Option<String>) -> Result<HttpResponse, LiteError> {
+    let post_detail = get_post(api, &p.id).await?;
+    let comment = path_comment(p, &post_detail)?;
+
+    let status = if error.is_some() { StatusCode::BAD_REQUEST } else { StatusCode::OK };
+    let now = &Utc::now().naive_utc();
+    html_status(status, report_page(&p.inst, session, account, &post_detail.post, comment, reason, error.as_deref(), now))
+}
+
+// The comment a reply or report is about, which has to be one of the post's
+fn path_comment<'a>(p: &CommentPath, post_detail: &'a PostDetail) -> Result<Option<&'a CommentView>, LiteError> {
+    let sub_id = match p.sub_id {
+        Some(ref sub_id) => sub_id,
+        None => return Ok(None)
+    };
+    let comment_id = parse_id(&p.inst, sub_id, "comment")?;
+    post_detail.comments.iter().find(|c| c.id == comment_id).map(Some).ok_or_else(|| LiteError::NotFound {
+        instance: Some(p.inst.clone()),
+        detail: format!("Comment {} doesn't belong to post {}", comment_id, p.id)
+    })
+}
+
+async fn resolve_action(req: HttpRequest, path: web::Path<String>, form: web::Form<ResolveForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let inst = &path.into_inner();
+    let session = logged_in!(session.as_ref(), inst);
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, inst, Some(session)).await?;
+    match form.t.as_str() {
+        "post" => resolve_post_report(api, form.id, true).await?,
+        "comment" => resolve_comment_report(api, form.id, true).await?,
+        _ => return Err(LiteError::InvalidPath {
+            instance: Some(inst.clone()),
+            detail: format!("{} reports can't be resolved", form.t)
+        })
+    }
+    Ok(see_other(back_path(&req, inst)))
+}
+
+async fn message_action(p: web::Path<ItemPath>, form: web::Form<CommentForm>, session: Option<Session>, data_client: web::Data<Client>, instances: web::Data<Instances>, cache: web::Data<ApiCache>) -> Result<HttpResponse, LiteError> {
+    let session = logged_in!(session.as_ref(), &p.inst);
+    let client = &data_client.into_inner();
+    let api = &connect(client, &cache, &instances, &p.inst, Some(session)).await?;
+
+    if form.preview.is_some() {
+        return message_res(api, &p.inst, &p.id, session, Account::default(), None, Some(&form), None).await;
+    }
+    if form.content.trim().is_empty() {
+        return message_res(api, &p.inst, &p.id, session, Account::default(), None, Some(&form), Some("Message cannot be empty".to_string())).await;
+    }
+    let recipient = get_user(api, &p.id, false, None).await?.user;
+    match create_private_message(api, recipient.id, &form.content).await {
+        Ok(message) => Ok(see_other(format!("/{}/u/{}/message#m{}", p.inst, p.id, message.id))),
+        Err(e) => match e.form_message() {
+            Some(message) => message_res(api, &p.inst, &p.id, session, Account::default(), None, Some(&form), Some(message)).await,
+            None => Err(e)
+        }
+    }
+}
+
+// A page of the conversation with a user, oldest first
+#[allow(clippy::too_many_arguments)]
+async fn message_res(api: &Api<'_>, inst: &String, name: &str, session: &Session, account: Account, page: Option<i32>, form: Option<&CommentForm>, error: Option<String>) -> Result<HttpResponse, LiteError> {
+    let mut messages = get_conversation(api, &session.username, name, page).await?.messages;
+    messages.reverse();
+    let paging_params = &PagingParams {
+        s: None,
+        p: page,
+        l: None,
+        t: None
+    };
+
+    let status = if error.is_some() { StatusCode::BAD_REQUEST } else { StatusCode::OK };
+    let now = &Utc::now().naive_utc();
+    html_status(status, message_page(inst, session, account, name, &messages, paging_params, form, error.as_deref(), now))
+}
+
+fn login_redirect(instance: &str) -> HttpResponse {
+    see_other(format!("/{}/login", instance))
+}
+
+// Path of the page a form was posted from, only pages of the same instance are trusted
+fn back_path(req: &HttpRequest, instance: &str) -> String {
+    let home = format!("/{}", instance);
+    req.headers().get(header::REFERER)
+        .and_then(|r| r.to_str().ok())
+        .and_then(|r| Url::parse(r).ok())
+        .filter(|r| r.path() == home || r.path().starts_with(&format!("{}/", home)))
+        .map(|r| match r.query() {
+            Some(q) => format!("{}?{}", r.path(), q),
+            None => r.path().to_string()
+        })
+        .unwrap_or(home)
+}
+
+// Redirect after a form post so refreshing doesn't post it again
+fn see_other(location: String) -> HttpResponse {
+    HttpResponse::SeeOther().header(header::LOCATION, location).finish()
+}
+
+// Malformed path segments or query parameters, the first path segment is the instance
+fn invalid_path(req: &HttpRequest, detail: String) -> LiteError {
+    LiteError::InvalidPath {
+        instance: req.path().split('/').nth(1).filter(|i| !i.is_empty()).map(str::to_string),
+        detail
+    }
+}
+
+fn feed_res(feed: &Feed, format: FeedFormat) -> Result<HttpResponse, LiteError> {
+    Ok(HttpResponse::Ok().content_type(format.content_type()).body(feed.render(format)))
+}
+
+fn text_res(text: String) -> Result<HttpResponse, LiteError> {
+    Ok(HttpResponse::Ok().content_type("text/plain; charset=utf-8").header(header::VARY, "Accept").body(text))
+}
+
+fn html_res(markup: Markup) -> Result<HttpResponse, LiteError> {
+    html_status(StatusCode::OK, markup)
 }
 
-fn html_res(markup: Markup) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).content_type("text/html; charset=utf-8").body(
+fn html_status(status: StatusCode, markup: Markup) -> Result<HttpResponse, LiteError> {
+    // Same URLs serve text to clients preferring it, shared caches must keep both
+    Ok(HttpResponse::build(status).content_type("text/html; charset=utf-8").header(header::VARY, "Accept").body(
         markup.into_string())
     )
 }
\ No newline at end of file
@@ -0,0 +1,54 @@
+/*
+Route helpers
+
+Shared by the HTTP routes in main.rs, the plain text pages and the Gemini routes, so none of them reaches into main.rs.
+*/
+
+use actix_web::{HttpRequest, client::Client};
+use crate::cache::ApiCache;
+use crate::config;
+use crate::error::LiteError;
+use crate::lemmy_api::{Api, Instances, Software};
+use crate::session::Session;
+
+// Resolve the API version spoken by an instance, explaining to the user when it isn't a supported Lemmy
+pub async fn connect<'a>(client: &'a Client, cache: &'a ApiCache, instances: &Instances, instance: &'a str, session: Option<&'a Session>) -> Result<Api<'a>, LiteError> {
+    if config::get().is_blocked(instance) {
+        return Err(LiteError::InstanceBlocked { instance: instance.to_string() });
+    }
+    let version = match config::get().api_version {
+        Some(version) => version,
+        None => match instances.software(client, instance).await? {
+            Software::Lemmy(version) => version,
+            Software::Unsupported { name, version } => return Err(LiteError::UnsupportedInstance {
+                instance: instance.to_string(),
+                software: name,
+                version
+            })
+        }
+    };
+    Ok(Api::new(client, cache, instance, version, session.map(|s| s.jwt.as_str())))
+}
+
+pub fn parse_id(instance: &str, id: &str, kind: &str) -> Result<i32, LiteError> {
+    id.parse().map_err(|_| LiteError::InvalidPath {
+        instance: Some(instance.to_string()),
+        detail: format!("{} is not a valid {} ID", id, kind)
+    })
+}
+
+pub fn unknown_page(instance: &str) -> LiteError {
+    LiteError::NotFound {
+        instance: Some(instance.to_string()),
+        detail: "lemmy-lite has no such page".to_string()
+    }
+}
+
+// Scheme and host lemmy-lite is reached at, unless configured they come from the request and its forwarded headers
+pub fn base_url(req: &HttpRequest) -> String {
+    if let Some(ref url) = config::get().public_url {
+        return url.clone();
+    }
+    let info = req.connection_info();
+    format!("{}://{}", info.scheme(), info.host())
+}
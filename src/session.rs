@@ -0,0 +1,93 @@
+/*
+Login sessions
+
+The instance JWT and username are kept in HttpOnly cookies scoped to /{instance},
+so each instance browsed through lemmy-lite has its own independent session.
+The user's role is looked up once at login, it only decides which links and forms are shown,
+the instance still checks every action.
+*/
+
+use actix_web::{dev::{Payload, HttpResponseBuilder}, FromRequest, HttpMessage, HttpRequest};
+use actix_web::cookie::{Cookie, SameSite};
+use futures_util::future::{ready, Ready};
+use crate::config;
+
+const JWT_COOKIE: &str = "jwt";
+const USER_COOKIE: &str = "user";
+const ROLE_COOKIE: &str = "role";
+
+pub struct Session {
+    pub username: String,
+    pub jwt: String,
+    pub role: Role
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Role {
+    User,
+    Moderator,  // Of at least one community
+    Admin
+}
+
+impl Role {
+    fn from_cookie(value: &str) -> Self {
+        match value {
+            "admin" => Role::Admin,
+            "moderator" => Role::Moderator,
+            _ => Role::User
+        }
+    }
+
+    fn cookie(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin"
+        }
+    }
+
+    pub fn reviews_reports(self) -> bool {
+        self != Role::User
+    }
+}
+
+// Handlers take Option<Session>, which is None when the visitor isn't logged in to the instance
+impl FromRequest for Session {
+    type Error = ();
+    type Future = Ready<Result<Self, ()>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match (req.cookie(JWT_COOKIE), req.cookie(USER_COOKIE)) {
+            (Some(jwt), Some(user)) if !jwt.value().is_empty() => Ok(Session {
+                username: user.value().to_string(),
+                jwt: jwt.value().to_string(),
+                role: req.cookie(ROLE_COOKIE).map_or(Role::User, |r| Role::from_cookie(r.value()))
+            }),
+            _ => Err(())
+        })
+    }
+}
+
+pub fn set_cookies(res: &mut HttpResponseBuilder, instance: &str, session: &Session) {
+    res.cookie(cookie(instance, JWT_COOKIE, &session.jwt));
+    res.cookie(cookie(instance, USER_COOKIE, &session.username));
+    res.cookie(cookie(instance, ROLE_COOKIE, session.role.cookie()));
+}
+
+pub fn remove_cookies(res: &mut HttpResponseBuilder, instance: &str) {
+    res.del_cookie(&cookie(instance, JWT_COOKIE, ""));
+    res.del_cookie(&cookie(instance, USER_COOKIE, ""));
+    res.del_cookie(&cookie(instance, ROLE_COOKIE, ""));
+}
+
+// Lax still sends the cookie when following a link to lemmy-lite, but never on cross-site form posts
+fn cookie(instance: &str, name: &'static str, value: &str) -> Cookie<'static> {
+    Cookie::build(name, value.to_string())
+        .path(format!("/{}", instance))
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(config::get().secure_cookies)
+        .permanent()
+        .finish()
+}
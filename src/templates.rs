@@ -14,6 +14,11 @@ DOM classes
 .l  = Link
 .b  = Badge
 .pb = PageBar
+.f  = Form
+.fe = Form Error
+.v  = Vote
+.va = Vote Active
+.i  = Inline form
 .h  = Highlight
 .m  = Mute
 .ch = Comment Header details
@@ -24,14 +29,16 @@ DOM classes
 
 use chrono::naive::NaiveDateTime;
 use maud::{html, DOCTYPE, Markup, PreEscaped};
-use pulldown_cmark::{Parser, CowStr, Event, Tag, html as pchtml};
-use crate::lemmy_api::{PostView, PostList, PostDetail, CommentView, CommunityView, CommunityModeratorView, CommunityList, UserView, UserDetail, PagingParams, SearchParams, SearchResponse, CommunityDetail};
+use pulldown_cmark::{Parser, CowStr, Event, Tag, LinkType, html as pchtml, escape::{escape_href, escape_html}};
+use url::{Url, form_urlencoded};
+use crate::config;
+use crate::error::LiteError;
+use crate::session::{Role, Session};
+use crate::lemmy_api::{PostView, PostList, PostDetail, CommentView, CommunityView, CommunityModeratorView, CommunityList, UserView, UserDetail, PagingParams, SearchParams, SearchResponse, CommunityDetail, ReportList, ReportView, Modlog, ModlogEntry, ModlogTarget, CommentForm, PostForm, SignupForm, SettingsForm, SiteInfo, Registration, Captcha, Inbox, InboxTab, PrivateMessageView, Subscription};
 
 const MEDIA_EXT: &[&str] = &[".png", "jpg", ".jpeg", ".gif", ".svg", ".webm", ".mp4"];
-const STYLESHEET: &str = "/s.css";
-const LINK_IMG: &str = "/l.svg";
-const MEDIA_IMG: &str = "/m.svg";
-const TEXT_IMG: &str = "/t.svg";
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto", "ftp", "magnet", "gemini"];
+const OUTBOUND_REL: &str = "nofollow noopener ugc";
 
 // Pure HTML redirect
 pub fn redirect_page(instance: String) -> Markup {
@@ -41,14 +48,40 @@ pub fn redirect_page(instance: String) -> Markup {
     }
 }
 
-pub fn communities_page(instance: &String, community_list: CommunityList, paging_params: Option<&PagingParams>) -> Markup {
+pub fn error_page(error: &LiteError) -> Markup {
     html! {
         (headers_markup())
-        (navbar_markup(instance, Some(html!{
+        @if let Some(instance) = error.instance() {
+            (navbar_markup(&instance.to_string(), None, Account::default(), None, None))
+        }
+        #w {
+            h2 {(error.title())}
+            p {(error.to_string())}
+            @match error {
+                LiteError::InstanceBlocked { .. } => {
+                    p { a href="/" {"Choose another instance"} }
+                },
+                LiteError::UnsupportedInstance { instance, .. } => {
+                    p {
+                        a href={(config::get().upstream_scheme) "://" (instance)} {"Visit " (instance) " directly"}
+                        " or "
+                        a href="/" {"choose another instance"}
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn communities_page(instance: &String, session: Option<&Session>, account: Account, community_list: CommunityList, paging_params: Option<&PagingParams>) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, session, account, Some(html!{
             a.l href={"/" (instance) "/communities"} {"/communities"}
         }), None))
         #w {
-            (pagebar_markup(paging_params))
+            (pagebar_markup(paging_params, false))
             .o {
                 table {
                     tr {
@@ -58,24 +91,34 @@ pub fn communities_page(instance: &String, community_list: CommunityList, paging
                         th {"Subscribers"}
                         th {"Posts"}
                         th {"Comments"}
+                        @if session.is_some() { th {} }
                     }
                     @for community in &community_list.communities {
-                        (community_markup(instance, community))
+                        (community_markup(instance, session, community))
                     }
                 }
             }
-            (pagebar_markup(paging_params))
+            (pagebar_markup(paging_params, false))
         }
     }
 }
 
-pub fn post_list_page(instance: &String, post_list: PostList, now: &NaiveDateTime, community: Option<&String>, paging_params: Option<&PagingParams>) -> Markup {
+// community_detail is only fetched for a community page viewed by a logged in user
+#[allow(clippy::too_many_arguments)]
+pub fn post_list_page(instance: &String, session: Option<&Session>, account: Account, post_list: PostList, now: &NaiveDateTime, community: Option<&String>,
+    community_detail: Option<&CommunityDetail>, paging_params: Option<&PagingParams>) -> Markup {
+    let moderator = community_detail.is_some_and(|d| is_moderator(session, &d.moderators));
     html! {
         (headers_markup())
         (navbar_markup(
             instance,
+            session,
+            account,
             community.map(|c| html!{
                 a.l href=(c) {"/c/" (c)}
+                @if let Some(s) = community_detail.and_then(|d| d.community.subscribed) {
+                    (subscribe_markup(instance, c, s))
+                }
             }), 
             community.map(|c| SearchParams {
                 q: None,
@@ -87,26 +130,91 @@ pub fn post_list_page(instance: &String, post_list: PostList, now: &NaiveDateTim
             }).as_ref()
         ))
         #w {
-            (pagebar_markup(paging_params))
+            @if community.is_none() && session.is_some() {
+                p.m {
+                    a href={"/" (instance)} {"All"}
+                    " • "
+                    a href={"/" (instance) "?t=Subscribed"} {"Subscribed"}
+                }
+            }
+            (pagebar_markup(paging_params, community.is_none()))
             @for post in &post_list.posts {
-                div { (post_markup(instance, post, now)) }
+                div { (post_markup(instance, session, post, moderator, now)) }
                 hr;
             }
-            (pagebar_markup(paging_params))
+            (pagebar_markup(paging_params, community.is_none()))
+            #f {
+                @if let Some(c) = community {
+                    a href={"/" (instance) "/c/" (c) "/info"} {
+                        "More info on /c/" (c)
+                    }
+                    @if session.is_some() {
+                        " • "
+                        a href={"/" (instance) "/c/" (c) "/submit"} {"Submit a post"}
+                    }
+                    " • "
+                    a href={"/" (instance) "/c/" (c) "/modlog"} {"Modlog"}
+                } @else {
+                    a href={"/" (instance) "/modlog"} {"Modlog"}
+                }
+                @let feed = match community {
+                    Some(c) => format!("/{}/c/{}", instance, c),
+                    None => format!("/{}", instance)
+                };
+                @let sort = paging_params.and_then(|p| p.s.as_ref()).map(|s| format!("?{}", query_string(&[("s", s.clone())]))).unwrap_or_default();
+                " • "
+                a href={(feed) ".rss" (sort)} {"RSS"}
+                " • "
+                a href={(feed) ".atom" (sort)} {"Atom"}
+            }
+        }
+    }
+}
+
+// Moderation log of the instance, or of a single community
+pub fn modlog_page(instance: &String, session: Option<&Session>, account: Account, modlog: Modlog, community: Option<&String>,
+    now: &NaiveDateTime, paging_params: Option<&PagingParams>) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, session, account, Some(html!{
             @if let Some(c) = community {
-                a#f href={"/" (instance) "/c/" (c) "/info"} {
-                    "More info on /c/" (c)
+                a.l href={"/" (instance) "/c/" (c)} {"/c/" (c)}
+                a href={"/" (instance) "/c/" (c) "/modlog"} {"/modlog"}
+            } @else {
+                a href={"/" (instance) "/modlog"} {"/modlog"}
+            }
+        }), None))
+        #w {
+            (pagebar_markup(paging_params, false))
+            @if modlog.entries.is_empty() {
+                p {"Nothing here"}
+            } @else {
+                .o {
+                    table {
+                        tr {
+                            th {"When"}
+                            th {"Moderator"}
+                            th {"Action"}
+                            th {"Target"}
+                            th {"Community"}
+                            th {"Reason"}
+                        }
+                        @for entry in &modlog.entries {
+                            (modlog_entry_markup(instance, entry, now))
+                        }
+                    }
                 }
             }
+            (pagebar_markup(paging_params, false))
         }
     }
 }
 
-pub fn community_info_page(instance: &String, community_detail: CommunityDetail) -> Markup {
+pub fn community_info_page(instance: &String, session: Option<&Session>, account: Account, community_detail: CommunityDetail) -> Markup {
     let community = &community_detail.community;
     html! {
         (headers_markup())
-        (navbar_markup(instance, Some(html! {
+        (navbar_markup(instance, session, account, Some(html! {
             a.l href={"/" (instance) "/c/" (community.name)} {
                 "/c/" (community_detail.community.name)
             }
@@ -117,13 +225,22 @@ pub fn community_info_page(instance: &String, community_detail: CommunityDetail)
         #w {
             h1 {(community.name)}
             h2 {(community.title)}
-            h3 {"Category: " (community.category_name)}
-            h3 {"Number of online: " (community_detail.online)}
-            h3 {"Number of subscribers: " (community.number_of_subscribers)}
+            @if let Some(ref category) = community.category_name {
+                h3 {"Category: " (category)}
+            }
+            @if let Some(online) = community_detail.online {
+                h3 {"Number of online: " (online)}
+            }
+            h3 {
+                "Number of subscribers: " (community.number_of_subscribers)
+                @if let (Some(_), Some(s)) = (session, community.subscribed) {
+                    " " (subscribe_markup(instance, &community.name, s))
+                }
+            }
             h3 {"Number of posts: " (community.number_of_posts)}
             h3 {"Number of comments: " (community.number_of_comments)}
-            @if community.hot_rank > 0 {
-                h3 {"Hot rank: " (community.hot_rank)}
+            @if let Some(hot_rank) = community.hot_rank.filter(|r| *r > 0) {
+                h3 {"Hot rank: " (hot_rank)}
             }
             @if let Some(ref d) = community.description {
                 h3 {"Description:"}
@@ -169,75 +286,88 @@ pub fn community_info_page(instance: &String, community_detail: CommunityDetail)
     }
 }
 
-pub fn post_page(instance: &String, post_detail: PostDetail, now: &NaiveDateTime) -> Markup {
+pub fn post_page(instance: &String, session: Option<&Session>, account: Account, post_detail: PostDetail, now: &NaiveDateTime) -> Markup {
+    let moderator = is_moderator(session, &post_detail.moderators);
     html! {
         (headers_markup())
-        (navbar_markup(instance, None, None))
+        (navbar_markup(instance, session, account, None, None))
         #w {
-            (post_markup(instance, &post_detail.post, now))
+            (post_markup(instance, session, &post_detail.post, moderator, now))
 
             @if let Some(body) = &post_detail.post.body {
                 p {(mdstr_to_html(body))}
             }
+            (post_actions_markup(instance, session, &post_detail.post))
             hr;
+
+            @if session.is_some() && !post_detail.post.locked {
+                (comment_form_markup(instance, post_detail.post.id, None, ""))
+                hr;
+            }
             
-            (comment_tree_markup(instance, &post_detail.comments, post_detail.post.creator_id, None, 0, None, now))
+            (comment_tree_markup(instance, session, &post_detail.comments, post_detail.post.creator_id, None, 0, None, moderator, now))
+            #f {
+                a href={"/" (instance) "/post/" (post_detail.post.id) ".atom"} {"Comments feed"}
+            }
         }
     }
 }
 
-pub fn comment_page(instance: &String, comment: CommentView, post_detail: PostDetail, now: &NaiveDateTime) -> Markup {
-    let mut comments = post_detail.comments;
-    let comment_id = comment.id;
-    comments.retain(|c| Some(c.id) == comment.parent_id ||
-        c.id == comment_id ||
-        c.parent_id == Some(comment.id));
-    let parent = comments.iter().find(|c| Some(c.id) == comment.parent_id);
+pub fn comment_page(instance: &String, session: Option<&Session>, account: Account, comment: CommentView, post_detail: PostDetail, now: &NaiveDateTime) -> Markup {
+    let moderator = is_moderator(session, &post_detail.moderators);
+    let (comments, root_id) = comment_context(&comment, post_detail.comments);
 
     html! {
         (headers_markup())
-        (navbar_markup(instance, None, None))
+        (navbar_markup(instance, session, account, None, None))
         #w {
-            (post_markup(instance, &post_detail.post, now))
+            (post_markup(instance, session, &post_detail.post, moderator, now))
 
             @if let Some(body) = &post_detail.post.body {
                 p {(mdstr_to_html(body))}
             }
+            (post_actions_markup(instance, session, &post_detail.post))
             hr;
             
-            @match parent {
-                Some(p) => (comment_tree_markup(instance, &comments, post_detail.post.creator_id, p.parent_id, 0, Some(comment_id), now)),
-                None => (comment_tree_markup(instance, &comments, post_detail.post.creator_id, None, 0, Some(comment_id), now))
-            }
+            (comment_tree_markup(instance, session, &comments, post_detail.post.creator_id, root_id, 0, Some(comment.id), moderator, now))
         }
     }
 }
 
-pub fn user_page(instance: &String, user: UserDetail, now: &NaiveDateTime, paging_params: Option<&PagingParams>) -> Markup {
+// saved lists the items the logged in user saved instead of those they wrote
+pub fn user_page(instance: &String, session: Option<&Session>, account: Account, user: UserDetail, saved: bool, now: &NaiveDateTime, paging_params: Option<&PagingParams>) -> Markup {
     html!{
         (headers_markup())
-        (navbar_markup(instance, Some(html!{
+        (navbar_markup(instance, session, account, Some(html!{
             a.u href={"/" (instance) "/u/" (user.user.name)} {"/u/" (user.user.name)}
+            @if saved {
+                a href={"/" (instance) "/u/" (user.user.name) "/saved"} {"/saved"}
+            }
         }), None))
         #w {
-            div { (pagebar_markup(paging_params)) }
+            @if !saved && session.is_some_and(|s| s.username != user.user.name) {
+                p.m { a href={"/" (instance) "/u/" (user.user.name) "/message"} {"Send a message"} }
+            } @else if !saved && session.is_some() {
+                p.m { a href={"/" (instance) "/settings"} {"Settings"} }
+            }
+            div { (pagebar_markup(paging_params, false)) }
             @for post in user.posts {
-                (post_markup(instance, &post, now))
+                (post_markup(instance, session, &post, false, now))
                 hr;
             }
             @for comment in user.comments {
-                (comment_markup(instance, &comment, None, None, now, None))
+                (comment_markup(instance, session, &comment, None, None, false, now, None))
                 hr;
             }
-            (pagebar_markup(paging_params))
+            (pagebar_markup(paging_params, false))
         }
     }
 }
 
-pub fn search_page(instance: &String, now: &NaiveDateTime, search_res: Option<SearchResponse>, search_params: &SearchParams) -> Markup {
+pub fn search_page(instance: &String, session: Option<&Session>, account: Account, now: &NaiveDateTime, search_res: Option<SearchResponse>, search_params: &SearchParams) -> Markup {
     html! {
         (headers_markup())
-        (navbar_markup(instance, Some(html!{
+        (navbar_markup(instance, session, account, Some(html!{
             a.l href={"/" (instance) "/search"} {"/search"}
         }), Some(search_params)))
         #w {
@@ -253,9 +383,10 @@ pub fn search_page(instance: &String, now: &NaiveDateTime, search_res: Option<Se
                                 th {"Subscribers"}
                                 th {"Posts"}
                                 th {"Comments"}
+                                @if session.is_some() { th {} }
                             }
                             @for community in &results.communities {
-                                (community_markup(instance, community))
+                                (community_markup(instance, session, community))
                             }
                         }
                     }
@@ -280,11 +411,11 @@ pub fn search_page(instance: &String, now: &NaiveDateTime, search_res: Option<Se
                     hr;
                 }
                 @for post in &results.posts {
-                    (post_markup(instance, post, now))
+                    (post_markup(instance, session, post, false, now))
                     hr;
                 }
                 @for comment in &results.comments {
-                    (comment_markup(instance, comment, None, None, now, None))
+                    (comment_markup(instance, session, comment, None, None, false, now, None))
                     hr;
                 }
                 (searchbar_markup(search_params))
@@ -295,6 +426,388 @@ pub fn search_page(instance: &String, now: &NaiveDateTime, search_res: Option<Se
     }
 }
 
+pub fn login_page(instance: &String, username: Option<&str>, error: Option<&str>) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, None, Account::default(), Some(html!{
+            a href={"/" (instance) "/login"} {"/login"}
+        }), None))
+        #w {
+            @if let Some(e) = error {
+                p.fe {(e)}
+            }
+            form.f method="post" action={"/" (instance) "/login"} {
+                @if let Some(u) = username {
+                    input name="u" placeholder="Username or email" required autocomplete="username" value=(u);
+                } @else {
+                    input name="u" placeholder="Username or email" required autocomplete="username" autofocus;
+                }
+                input type="password" name="pw" placeholder="Password" required autocomplete="current-password";
+                input name="totp" placeholder="2FA token, if enabled" autocomplete="one-time-code" inputmode="numeric";
+                input type="submit" value="Login";
+            }
+        }
+    }
+}
+
+// Unread items are highlighted and can be marked as read one by one
+pub fn inbox_page(instance: &String, session: &Session, account: Account, inbox: Inbox, now: &NaiveDateTime, paging_params: &PagingParams) -> Markup {
+    let tab = inbox.tab();
+    let empty = match inbox {
+        Inbox::Replies(ref l) | Inbox::Mentions(ref l) => l.replies.is_empty(),
+        Inbox::Messages(ref l) => l.messages.is_empty()
+    };
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, Some(session), account, Some(html!{
+            a href={"/" (instance) "/inbox"} {"/inbox"}
+            a href={"/" (instance) "/inbox/" (tab.path())} {"/" (tab.path())}
+        }), None))
+        #w {
+            p.m {
+                @for (t, name) in &[(InboxTab::Replies, "Replies"), (InboxTab::Mentions, "Mentions"), (InboxTab::Messages, "Messages")] {
+                    @if *t == tab {
+                        b {(name)}
+                    } @else {
+                        a href={"/" (instance) "/inbox/" (t.path())} {(name)}
+                    }
+                    " • "
+                }
+                (read_markup(instance, tab, None))
+            }
+            .pb { (pager_markup(Some(paging_params))) }
+            @match inbox {
+                Inbox::Replies(l) | Inbox::Mentions(l) => {
+                    @for reply in &l.replies {
+                        (comment_markup(instance, Some(session), &reply.comment, None,
+                            if reply.read {None} else {Some(reply.comment.id)}, false, now, None))
+                        @if !reply.read {
+                            (read_markup(instance, tab, Some(reply.id)))
+                        }
+                        hr;
+                    }
+                },
+                Inbox::Messages(l) => {
+                    @for message in &l.messages {
+                        // Sent messages are listed too, only received ones can be marked as read
+                        @let unread = !message.read && message.creator_name != session.username;
+                        @let other = if message.creator_name == session.username {&message.recipient_name} else {&message.creator_name};
+                        (private_message_markup(instance, message, unread, now))
+                        p.m {
+                            a href={"/" (instance) "/u/" (other) "/message"} {"conversation"}
+                            @if unread {
+                                " " (read_markup(instance, tab, Some(message.id)))
+                            }
+                        }
+                        hr;
+                    }
+                }
+            }
+            @if empty {
+                p {"Nothing here"}
+            }
+            .pb { (pager_markup(Some(paging_params))) }
+        }
+    }
+}
+
+// Conversation with another user, form holds what was submitted for a preview or after an error
+#[allow(clippy::too_many_arguments)]
+pub fn message_page(instance: &String, session: &Session, account: Account, name: &str, messages: &[PrivateMessageView], paging_params: &PagingParams,
+    form: Option<&CommentForm>, error: Option<&str>, now: &NaiveDateTime) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, Some(session), account, Some(html!{
+            a.u href={"/" (instance) "/u/" (name)} {"/u/" (name)}
+            a href={"/" (instance) "/u/" (name) "/message"} {"/message"}
+        }), None))
+        #w {
+            // Next goes back in time, the newest messages are on the first page
+            .pb { (pager_markup(Some(paging_params))) }
+            @if messages.is_empty() {
+                p {"No messages with " (name) " on this page, older ones may be on the next pages"}
+            }
+            @for message in messages {
+                @let unread = !message.read && message.creator_name != session.username;
+                (private_message_markup(instance, message, unread, now))
+                @if unread {
+                    (read_markup(instance, InboxTab::Messages, Some(message.id)))
+                }
+                hr;
+            }
+
+            @if let Some(e) = error {
+                p.fe {(e)}
+            }
+            @if let Some(f) = form.filter(|f| f.preview.is_some()) {
+                h3 {"Preview"}
+                div {(mdstr_to_html(&f.content))}
+                hr;
+            }
+            form.f method="post" action={"/" (instance) "/u/" (name) "/message"} {
+                textarea name="content" rows="6" placeholder="Markdown" required {(form.map_or("", |f| f.content.as_str()))}
+                input type="submit" name="preview" value="Preview";
+                input type="submit" value="Send";
+            }
+        }
+    }
+}
+
+// The captcha audio is a data URI download, legacy browsers can't play it inline
+pub fn signup_page(instance: &String, site: &SiteInfo, captcha: Option<&Captcha>, form: Option<&SignupForm>, error: Option<&str>) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, None, Account::default(), Some(html!{
+            a href={"/" (instance) "/signup"} {"/signup"}
+        }), None))
+        #w {
+            @if site.registration == Registration::Closed {
+                p {"Registration is closed on " (instance) "."}
+            } @else {
+                @if let Some(e) = error {
+                    p.fe {(e)}
+                }
+                form.f method="post" action={"/" (instance) "/signup"} {
+                    input name="u" placeholder="Username" required autocomplete="username" value=(form.map_or("", |f| f.u.as_str()));
+                    input type="email" name="email" placeholder=(if site.email_required {"Email"} else {"Email, optional"})
+                        required?[site.email_required] autocomplete="email" value=(form.map_or("", |f| f.email.as_str()));
+                    input type="password" name="pw" placeholder="Password" required autocomplete="new-password";
+                    input type="password" name="pw2" placeholder="Password again" required autocomplete="new-password";
+                    label {
+                        input type="checkbox" name="nsfw" checked?[form.is_some_and(|f| f.nsfw.is_some())];
+                        " Show NSFW content"
+                    }
+                    @if let Some(c) = captcha {
+                        img src={"data:image/png;base64," (c.png)} alt="Captcha";
+                        @if let Some(ref wav) = c.wav {
+                            p { a href={"data:audio/wav;base64," (wav)} download="captcha.wav" {"Listen to the captcha"} }
+                        }
+                        input type="hidden" name="cu" value=(c.uuid);
+                        input name="ca" placeholder="Captcha answer" required autocomplete="off";
+                    }
+                    @if site.registration == Registration::RequireApplication {
+                        @if let Some(ref question) = site.application_question {
+                            div {(mdstr_to_html(question))}
+                        }
+                        textarea name="answer" rows="4" placeholder="Answer" required {
+                            (form.and_then(|f| f.answer.as_deref()).unwrap_or(""))
+                        }
+                    }
+                    input type="submit" value="Sign up";
+                }
+            }
+        }
+    }
+}
+
+// Registration went through but the user can't log in yet
+pub fn registered_page(instance: &String, message: &str) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, None, Account::default(), Some(html!{
+            a href={"/" (instance) "/signup"} {"/signup"}
+        }), None))
+        #w {
+            p {(message)}
+            p { a href={"/" (instance) "/login"} {"Login"} }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum PostFormTarget<'a> {
+    Submit(&'a str),    // New post in a community
+    Edit(i32)           // Existing post
+}
+
+pub fn post_form_page(instance: &String, session: Option<&Session>, account: Account, target: PostFormTarget, form: Option<&PostForm>, error: Option<&str>) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, session, account, Some(match target {
+            PostFormTarget::Submit(community) => html! {
+                a.l href={"/" (instance) "/c/" (community)} {"/c/" (community)}
+                a href={"/" (instance) "/c/" (community) "/submit"} {"/submit"}
+            },
+            PostFormTarget::Edit(id) => html! {
+                a href={"/" (instance) "/post/" (id)} {"/post/" (id)}
+                a href={"/" (instance) "/post/" (id) "/edit"} {"/edit"}
+            }
+        }), None))
+        #w {
+            @if let Some(e) = error {
+                p.fe {(e)}
+            }
+            form.f method="post" action={"/" (instance) @match target {
+                PostFormTarget::Submit(community) => {"/c/" (community) "/submit"},
+                PostFormTarget::Edit(id) => {"/post/" (id) "/edit"}
+            }} {
+                input name="name" placeholder="Title" required value=(form.map_or("", |f| f.name.as_str()));
+                input type="url" name="url" placeholder="URL" value=(form.map_or("", |f| f.url.as_str()));
+                textarea name="body" rows="10" placeholder="Body, markdown" {(form.map_or("", |f| f.body.as_str()))}
+                label {
+                    input type="checkbox" name="nsfw" checked?[form.is_some_and(|f| f.nsfw.is_some())];
+                    " NSFW"
+                }
+                input type="submit" value=(match target {
+                    PostFormTarget::Submit(_) => "Submit",
+                    PostFormTarget::Edit(_) => "Save"
+                });
+            }
+        }
+    }
+}
+
+// Profile and preferences of the logged in user, prefilled with the current values
+pub fn settings_page(instance: &String, session: &Session, account: Account, form: &SettingsForm, error: Option<&str>) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, Some(session), account, Some(html!{
+            a href={"/" (instance) "/settings"} {"/settings"}
+        }), None))
+        #w {
+            @if let Some(e) = error {
+                p.fe {(e)}
+            }
+            @if form.preview.is_some() {
+                h3 {"Preview"}
+                div {(mdstr_to_html(&form.bio))}
+                hr;
+            }
+            form.f method="post" action={"/" (instance) "/settings"} {
+                input name="name" placeholder="Display name" value=(form.name);
+                textarea name="bio" rows="6" placeholder="Bio, markdown" {(form.bio)}
+                input type="url" name="avatar" placeholder="Avatar URL" value=(form.avatar);
+                input name="matrix" placeholder="Matrix user, @user:example.com" value=(form.matrix);
+                label {"Default sort " (preference_markup("s", &form.s, &[("Hot", "Hot"), ("Active", "Active"), ("New", "New"),
+                    ("TopDay", "Day"), ("TopWeek", "Week"), ("TopMonth", "Month"), ("TopYear", "Year"), ("TopAll", "All")]))}
+                label {"Default listing " (preference_markup("t", &form.t, &[("All", "All"), ("Local", "Local"), ("Subscribed", "Subscribed")]))}
+                label {
+                    input type="checkbox" name="nsfw" checked?[form.nsfw.is_some()];
+                    " Show NSFW content"
+                }
+                label {
+                    input type="checkbox" name="avatars" checked?[form.avatars.is_some()];
+                    " Show avatars"
+                }
+                label {
+                    input type="checkbox" name="notify" checked?[form.notify.is_some()];
+                    " Send notifications to email"
+                }
+                input type="submit" name="preview" value="Preview";
+                input type="submit" value="Save";
+            }
+        }
+    }
+}
+
+// Instances have more sorts and listing types than the pagebar offers, the current one is kept so saving doesn't reset it
+fn preference_markup(name: &str, current: &str, choices: &[(&str, &str)]) -> Markup {
+    html! {
+        select name=(name) {
+            @for (value, label) in choices {
+                option selected?[*value == current] value=(value) {(label)}
+            }
+            @if !choices.iter().any(|(value, _)| *value == current) {
+                option selected value=(current) {(current)}
+            }
+        }
+    }
+}
+
+// Compose a comment, or a reply when parent is set, form holds what was submitted for a preview or after an error
+#[allow(clippy::too_many_arguments)]
+pub fn reply_page(instance: &String, session: Option<&Session>, account: Account, post: &PostView, parent: Option<&CommentView>,
+    form: Option<&CommentForm>, error: Option<&str>, now: &NaiveDateTime) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, session, account, None, None))
+        #w {
+            (post_markup(instance, session, post, false, now))
+            hr;
+            @if let Some(comment) = parent {
+                (comment_markup(instance, session, comment, Some(post.creator_id), None, false, now, None))
+                hr;
+            }
+
+            @if let Some(e) = error {
+                p.fe {(e)}
+            }
+            @if let Some(f) = form.filter(|f| f.preview.is_some()) {
+                h3 {"Preview"}
+                div {(mdstr_to_html(&f.content))}
+                hr;
+            }
+            (comment_form_markup(instance, post.id, parent.map(|c| c.id), form.map_or("", |f| f.content.as_str())))
+        }
+    }
+}
+
+// Asks again for the reason of a moderator action, when the browser didn't enforce it
+#[allow(clippy::too_many_arguments)]
+pub fn mod_page(instance: &String, session: &Session, account: Account, target: &str, id: i32, community_id: i32, creator_id: i32,
+    action: &str, error: &str) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, Some(session), account, None, None))
+        #w {
+            p.fe {(error)}
+            form.f method="post" action={"/" (instance) "/" (target) "/" (id) "/mod"} {
+                input type="hidden" name="c" value=(community_id);
+                input type="hidden" name="u" value=(creator_id);
+                input type="hidden" name="a" value=(action);
+                input name="reason" placeholder="Reason" required;
+                input type="submit" value="Apply";
+            }
+        }
+    }
+}
+
+// Reason form for a post, or one of its comments, reason holds what was submitted after an error
+#[allow(clippy::too_many_arguments)]
+pub fn report_page(instance: &String, session: &Session, account: Account, post: &PostView, comment: Option<&CommentView>,
+    reason: &str, error: Option<&str>, now: &NaiveDateTime) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, Some(session), account, None, None))
+        #w {
+            @match comment {
+                Some(c) => (comment_markup(instance, Some(session), c, Some(post.creator_id), None, false, now, None)),
+                None => (post_markup(instance, Some(session), post, false, now))
+            }
+            hr;
+            @if let Some(e) = error {
+                p.fe {(e)}
+            }
+            form.f method="post" action={
+                "/" (instance) "/post/" (post.id)
+                @if let Some(c) = comment {"/comment/" (c.id)}
+                "/report"
+            } {
+                input name="reason" placeholder="Reason" value=(reason) required;
+                input type="submit" value="Report";
+            }
+        }
+    }
+}
+
+// Open reports in the communities the user moderates
+pub fn reports_page(instance: &String, session: &Session, account: Account, reports: ReportList, now: &NaiveDateTime, paging_params: &PagingParams) -> Markup {
+    html! {
+        (headers_markup())
+        (navbar_markup(instance, Some(session), account, Some(html!{
+            a href={"/" (instance) "/reports"} {"/reports"}
+        }), None))
+        #w {
+            .pb { (pager_markup(Some(paging_params))) }
+            h3 {"Posts"}
+            (report_table_markup(instance, "post", &reports.posts, now))
+            h3 {"Comments"}
+            (report_table_markup(instance, "comment", &reports.comments, now))
+            .pb { (pager_markup(Some(paging_params))) }
+        }
+    }
+}
+
 fn headers_markup() -> Markup {
     html! {
         (DOCTYPE)
@@ -305,15 +818,45 @@ fn headers_markup() -> Markup {
         meta name="theme-color" content="#222";
         meta name="description" content="Lemmy";
         title { "Lemmy" }
-        link rel="stylesheet" href=(STYLESHEET);
+        link rel="stylesheet" href=(config::get().assets.stylesheet);
     }
 }
 
-fn navbar_markup(instance: &String, embed: Option<Markup>, search_params: Option<&SearchParams>) -> Markup {
+// What the navbar shows about the logged in user's account, looked up for each page
+#[derive(Clone, Copy, Default)]
+pub struct Account {
+    pub unread: Option<i64>     // Unread notifications
+}
+
+fn navbar_markup(instance: &String, session: Option<&Session>, account: Account, embed: Option<Markup>, search_params: Option<&SearchParams>) -> Markup {
     let paging_params = search_params.map(|s| s.to_paging_params());
     html! {
         #n {
-            a href={"/" (instance) "/communities"} {"Communities"}
+            div {
+                a href={"/" (instance) "/communities"} {"Communities"}
+                " • "
+                @if let Some(s) = session {
+                    a.u href={"/" (instance) "/u/" (s.username)} {(s.username)}
+                    " • "
+                    a href={"/" (instance) "/u/" (s.username) "/saved"} {"Saved"}
+                    " • "
+                    a href={"/" (instance) "/inbox"} {"Inbox"}
+                    @if let Some(unread) = account.unread.filter(|n| *n > 0) {
+                        " " span.b {(unread)}
+                    }
+                    @if s.role.reviews_reports() {
+                        " • "
+                        a href={"/" (instance) "/reports"} {"Reports"}
+                    }
+                    form method="post" action={"/" (instance) "/logout"} {
+                        input type="submit" value="Logout";
+                    }
+                } @else {
+                    a href={"/" (instance) "/login"} {"Login"}
+                    " • "
+                    a href={"/" (instance) "/signup"} {"Sign up"}
+                }
+            }
         
             div {
                 a href={"/" (instance)} {(instance)}
@@ -336,17 +879,118 @@ fn navbar_markup(instance: &String, embed: Option<Markup>, search_params: Option
     }
 }
 
-fn community_markup(instance: &String, community: &CommunityView) -> Markup {
+fn community_markup(instance: &String, session: Option<&Session>, community: &CommunityView) -> Markup {
     html! {
         tr {
             td {a.l href= {"/" (instance) "/c/" (community.name)} {
                 (community.name)
             }}
             td {(community.title)}
-            td {(community.category_name)}
+            td {@if let Some(ref category) = community.category_name {(category)}}
             td.e {(community.number_of_subscribers)}
             td.e {(community.number_of_posts)}
             td.e {(community.number_of_comments)}
+            @if let (Some(_), Some(s)) = (session, community.subscribed) {
+                td {(subscribe_markup(instance, &community.name, s))}
+            }
+        }
+    }
+}
+
+fn report_table_markup(instance: &String, target: &str, reports: &[ReportView], now: &NaiveDateTime) -> Markup {
+    html! {
+        @if reports.is_empty() {
+            p {"Nothing here"}
+        } @else {
+            .o {
+                table {
+                    tr {
+                        th {(if target == "post" {"Post"} else {"Comment"})}
+                        th {"Community"}
+                        th {"Creator"}
+                        th {"Reporter"}
+                        th {"Reason"}
+                        th {"Reported"}
+                        th {}
+                    }
+                    @for report in reports {
+                        (report_markup(instance, target, report, now))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn report_markup(instance: &String, target: &str, report: &ReportView, now: &NaiveDateTime) -> Markup {
+    html! {
+        tr {
+            td {a href={
+                "/" (instance) "/post/" (report.post_id)
+                @if let Some(id) = report.comment_id {"/comment/" (id)}
+            } {
+                (report.content)
+            }}
+            td {a.l href={"/" (instance) "/c/" (report.community_name)} {(report.community_name)}}
+            td {a.u href={"/" (instance) "/u/" (report.reported_name)} {(report.reported_name)}}
+            td {a.u href={"/" (instance) "/u/" (report.creator_name)} {(report.creator_name)}}
+            td {(report.reason)}
+            td {(simple_duration(now, report.published))}
+            td {
+                form.i method="post" action={"/" (instance) "/reports/resolve"} {
+                    input type="hidden" name="t" value=(target);
+                    input type="hidden" name="id" value=(report.id);
+                    input type="submit" value="Resolve";
+                }
+            }
+        }
+    }
+}
+
+fn modlog_entry_markup(instance: &String, entry: &ModlogEntry, now: &NaiveDateTime) -> Markup {
+    html! {
+        tr {
+            td {(simple_duration(now, entry.when))}
+            td {
+                @if let Some(ref name) = entry.moderator_name {
+                    a.u href={"/" (instance) "/u/" (name)} {(name)}
+                }
+            }
+            td {(entry.action.label())}
+            td {
+                @match entry.target {
+                    ModlogTarget::Post { id, ref name } => {
+                        a href={"/" (instance) "/post/" (id)} {(name)}
+                    },
+                    ModlogTarget::Comment { id, post_id, ref content } => {
+                        a href={"/" (instance) "/post/" (post_id) "/comment/" (id)} {(content)}
+                    },
+                    ModlogTarget::User { ref name } => {
+                        a.u href={"/" (instance) "/u/" (name)} {(name)}
+                    }
+                }
+            }
+            td {
+                @if let Some(ref name) = entry.community_name {
+                    a.l href={"/" (instance) "/c/" (name)} {(name)}
+                }
+            }
+            td {@if let Some(ref reason) = entry.reason {(reason)}}
+        }
+    }
+}
+
+// A pending follow can be withdrawn like a subscription
+fn subscribe_markup(instance: &String, community_name: &str, subscription: Subscription) -> Markup {
+    let (follow, label) = match subscription {
+        Subscription::Subscribed => (false, "Unsubscribe"),
+        Subscription::Pending => (false, "Cancel pending subscription"),
+        Subscription::NotSubscribed => (true, "Subscribe")
+    };
+    html! {
+        form.i method="post" action={"/" (instance) "/c/" (community_name) "/subscribe"} {
+            input type="hidden" name="f" value=(follow);
+            input type="submit" value=(label);
         }
     }
 }
@@ -365,6 +1009,11 @@ fn user_markup(instance: &String, user: &UserView) -> Markup {
     }
 }
 
+// Moderator tools are only offered to the community's moderators and the instance's admins, the instance checks again on use
+fn is_moderator(session: Option<&Session>, moderators: &[CommunityModeratorView]) -> bool {
+    session.is_some_and(|s| s.role == Role::Admin || moderators.iter().any(|m| m.user_name == s.username))
+}
+
 fn moderator_markup(instance: &String, moderator: &CommunityModeratorView) -> Markup {
     html! {
         tr {
@@ -375,24 +1024,24 @@ fn moderator_markup(instance: &String, moderator: &CommunityModeratorView) -> Ma
     }
 }
 
-fn post_markup(instance: &String, post: &PostView, now: &NaiveDateTime) -> Markup {
+fn post_markup(instance: &String, session: Option<&Session>, post: &PostView, moderator: bool, now: &NaiveDateTime) -> Markup {
     html!{
-        .r {
+        .r id={"p" (post.id)} {
             p.s {(post.score)}
             @match &post.url {
-                Some(url) => {
-                    a href=(url) {
+                Some(url) if is_safe_url(url) => {
+                    a href=(url) rel=(OUTBOUND_REL) {
                         img.p src={
                             @if ends_with_any(url.clone(), MEDIA_EXT) {
-                                (MEDIA_IMG)
+                                (config::get().assets.media_img)
                             } @else {
-                                (LINK_IMG)
+                                (config::get().assets.link_img)
                             }
                         };
                     }
-                }, None => {
+                }, _ => {
                     a href={"/" (instance) "/post/" (post.id)} {
-                        img.p src=(TEXT_IMG);
+                        img.p src=(config::get().assets.text_img);
                     }
                 }
             }
@@ -400,6 +1049,15 @@ fn post_markup(instance: &String, post: &PostView, now: &NaiveDateTime) -> Marku
                 a.s[post.stickied] href={"/" (instance) "/post/" (post.id)} {
                     @if post.stickied {"📌 "} (post.name)
                 }
+                @if post.deleted {
+                    span.b {"deleted"}
+                }
+                @if post.removed {
+                    span.b {"removed"}
+                }
+                @if post.locked {
+                    span.b {"locked"}
+                }
                 .m{
                     "by "
                     a.u href={"/" (instance) "/u/" (post.creator_name) " " } {
@@ -410,11 +1068,31 @@ fn post_markup(instance: &String, post: &PostView, now: &NaiveDateTime) -> Marku
                         (post.community_name)
                     }
                     div {
-                        "˄ " (post.upvotes) " ˅ " (post.downvotes)
+                        @if session.is_some() {
+                            (vote_markup(instance, "post", post.id, post.my_vote, 1)) " " (post.upvotes)
+                            (vote_markup(instance, "post", post.id, post.my_vote, -1)) " " (post.downvotes)
+                        } @else {
+                            "˄ " (post.upvotes) " ˅ " (post.downvotes)
+                        }
                         a href={"/" (instance) "/post/" (post.id )} {
                             " • ✉ " (post.number_of_comments)
                         }
                         " • " (simple_duration(now, post.published))
+                        @if let (Some(_), Some(saved)) = (session, post.saved) {
+                            " • " (save_markup(instance, "post", post.id, saved))
+                        }
+                        @if session.is_some() {
+                            " • " a href={"/" (instance) "/post/" (post.id) "/report"} {"report"}
+                        }
+                        @if moderator {
+                            " • " (mod_markup(instance, "post", post.id, post.community_id, post.creator_id, &[
+                                if post.removed {("restore", "Restore")} else {("remove", "Remove")},
+                                if post.banned_from_community {("unban", "Unban creator")} else {("ban", "Ban creator")}
+                            ], &[
+                                if post.locked {("unlock", "Unlock")} else {("lock", "Lock")},
+                                if post.stickied {("unsticky", "Unsticky")} else {("sticky", "Sticky")}
+                            ]))
+                        }
                     }
                 }
             }
@@ -422,9 +1100,9 @@ fn post_markup(instance: &String, post: &PostView, now: &NaiveDateTime) -> Marku
     }
 }
 
-fn comment_header_markup(instance: &String, comment: &CommentView, post_creator_id: Option<i32>, highlight_id: Option<i32>, now: &NaiveDateTime) -> Markup {
-    return html! {
-        p.ch.h[Some(comment.id) == highlight_id] {
+fn comment_header_markup(instance: &String, session: Option<&Session>, comment: &CommentView, post_creator_id: Option<i32>, highlight_id: Option<i32>, moderator: bool, now: &NaiveDateTime) -> Markup {
+    html! {
+        p.ch.h[Some(comment.id) == highlight_id] id={"c" (comment.id)} {
             a.u href={"/" (instance) "/u/" (comment.creator_name)} {
                 (comment.creator_name)
             }
@@ -434,19 +1112,105 @@ fn comment_header_markup(instance: &String, comment: &CommentView, post_creator_
                 }
             }
 
-            " ϟ" (comment.score) 
+            @if session.is_some() {
+                " "
+                (vote_markup(instance, "comment", comment.id, comment.my_vote, 1))
+                "ϟ" (comment.score)
+                (vote_markup(instance, "comment", comment.id, comment.my_vote, -1))
+            } @else {
+                " ϟ" (comment.score)
+            }
             a href={"/" (instance) "/post/" (comment.post_id) "/comment/" (comment.id)} {
                 " ⚓ "
             }
+            @if session.is_some() {
+                a href={"/" (instance) "/post/" (comment.post_id) "/comment/" (comment.id) "/reply"} {"reply"}
+                " "
+                @if let Some(saved) = comment.saved {
+                    (save_markup(instance, "comment", comment.id, saved))
+                    " "
+                }
+                a href={"/" (instance) "/post/" (comment.post_id) "/comment/" (comment.id) "/report"} {"report"}
+                " "
+            }
             
             (simple_duration(now, comment.published))
+            @if comment.removed {
+                span.b {"removed"}
+            }
+        }
+        @if moderator {
+            (mod_markup(instance, "comment", comment.id, comment.community_id, comment.creator_id, &[
+                if comment.removed {("restore", "Restore")} else {("remove", "Remove")},
+                if comment.banned_from_community {("unban", "Unban creator")} else {("ban", "Ban creator")}
+            ], &[]))
         }
     }
 }
 
-fn comment_markup(instance: &String, comment: &CommentView, post_creator_id: Option<i32>, highlight_id: Option<i32>, now: &NaiveDateTime, children: Option<Markup>) -> Markup {
+// Moderator actions on a post or comment, the reason of removals and bans ends up in the instance's modlog
+// Locking and stickying take no reason, they get buttons of their own
+fn mod_markup(instance: &String, target: &str, id: i32, community_id: i32, creator_id: i32, actions: &[(&str, &str)], toggles: &[(&str, &str)]) -> Markup {
     html! {
-        (comment_header_markup(instance, comment, post_creator_id, highlight_id, now))
+        details.md {
+            summary {"mod"}
+            form method="post" action={"/" (instance) "/" (target) "/" (id) "/mod"} {
+                input type="hidden" name="c" value=(community_id);
+                input type="hidden" name="u" value=(creator_id);
+                select name="a" {
+                    @for (action, label) in actions {
+                        option value=(action) {(label)}
+                    }
+                }
+                input name="reason" placeholder="Reason" required;
+                input type="submit" value="Apply";
+            }
+            @for (action, label) in toggles {
+                form method="post" action={"/" (instance) "/" (target) "/" (id) "/mod"} {
+                    input type="hidden" name="c" value=(community_id);
+                    input type="hidden" name="u" value=(creator_id);
+                    input type="hidden" name="a" value=(action);
+                    input type="submit" value=(label);
+                }
+            }
+        }
+    }
+}
+
+// Actions on a post shown below its body, depending on who is logged in
+fn post_actions_markup(instance: &String, session: Option<&Session>, post: &PostView) -> Markup {
+    html! {
+        @if session.is_some_and(|s| s.username == post.creator_name) {
+            p.m {
+                a href={"/" (instance) "/post/" (post.id) "/edit"} {"edit"}
+                " "
+                form.i method="post" action={"/" (instance) "/post/" (post.id) "/delete"} {
+                    input type="hidden" name="d" value=(!post.deleted);
+                    input type="submit" value=(if post.deleted {"restore"} else {"delete"});
+                }
+            }
+        }
+    }
+}
+
+fn comment_form_markup(instance: &String, post_id: i32, parent_id: Option<i32>, content: &str) -> Markup {
+    html! {
+        form.f method="post" action={
+            "/" (instance) "/post/" (post_id) "/comment"
+            @if let Some(id) = parent_id {"/" (id) "/reply"}
+        } {
+            textarea name="content" rows="6" placeholder="Markdown" required {(content)}
+            input type="submit" name="preview" value="Preview";
+            input type="submit" value=(if parent_id.is_some() {"Reply"} else {"Comment"});
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn comment_markup(instance: &String, session: Option<&Session>, comment: &CommentView, post_creator_id: Option<i32>, highlight_id: Option<i32>,
+    moderator: bool, now: &NaiveDateTime, children: Option<Markup>) -> Markup {
+    html! {
+        (comment_header_markup(instance, session, comment, post_creator_id, highlight_id, moderator, now))
         
         @if children.is_some() {
             input.c type="checkbox";
@@ -462,22 +1226,151 @@ fn comment_markup(instance: &String, comment: &CommentView, post_creator_id: Opt
 }
 
 // zstewart#2487@discord.rust-community-server
-fn comment_tree_markup(instance: &String, comments: &[CommentView],
-    post_creator_id: i32, comment_parent_id: Option<i32>, depth: i32, highlight_id: Option<i32>, now: &NaiveDateTime) -> Markup {
+#[allow(clippy::too_many_arguments)]
+fn comment_tree_markup(instance: &String, session: Option<&Session>, comments: &[CommentView],
+    post_creator_id: i32, comment_parent_id: Option<i32>, depth: i32, highlight_id: Option<i32>, moderator: bool, now: &NaiveDateTime) -> Markup {
 
+    let trees = comment_tree(comments, comment_parent_id, depth, &mut |comment, depth, children: Vec<Markup>| html! {
+        .{"b" (
+            if depth == 0 {"r".to_string()} else {((depth - 1)%6).to_string()}
+            )} {
+            (comment_markup(instance, session, comment, Some(post_creator_id), highlight_id, moderator, now,
+                Some(html! { @for child in children {(child)} })))
+        }
+    });
     html! {
-        @for comment in comments.iter().filter(|c| c.parent_id == comment_parent_id) {
-            .{"b" (
-                if depth == 0 {"r".to_string()} else {((depth - 1)%6).to_string()}
-                )} {
-                (comment_markup(instance, comment, Some(post_creator_id), highlight_id, now,
-                    Some(comment_tree_markup(instance, comments, post_creator_id, Some(comment.id), depth+1, highlight_id, now))))
+        @for tree in trees {(tree)}
+    }
+}
+
+// Renders the replies to parent_id in order, each one given its own rendered replies
+// Shared by every output format so threads nest the same way everywhere
+pub fn comment_tree<'a, T>(comments: &'a [CommentView], parent_id: Option<i32>, depth: i32,
+    render: &mut dyn FnMut(&'a CommentView, i32, Vec<T>) -> T) -> Vec<T> {
+    comments.iter().filter(|c| c.parent_id == parent_id).map(|comment| {
+        let children = comment_tree(comments, Some(comment.id), depth + 1, render);
+        render(comment, depth, children)
+    }).collect()
+}
+
+// A comment's page shows it with its parent and direct replies, the tree starts above the parent
+pub fn comment_context(comment: &CommentView, mut comments: Vec<CommentView>) -> (Vec<CommentView>, Option<i32>) {
+    comments.retain(|c| Some(c.id) == comment.parent_id ||
+        c.id == comment.id ||
+        c.parent_id == Some(comment.id));
+    let root_id = comments.iter().find(|c| Some(c.id) == comment.parent_id).and_then(|p| p.parent_id);
+    (comments, root_id)
+}
+
+// Query parameters that carry over when the text formats link to another page of a listing
+pub fn paging_pairs(paging_params: &PagingParams) -> Vec<(&'static str, String)> {
+    let mut pairs = Vec::new();
+    if let Some(ref s) = paging_params.s {
+        pairs.push(("s", s.clone()));
+    }
+    if let Some(l) = paging_params.l {
+        pairs.push(("l", l.to_string()));
+    }
+    if let Some(ref t) = paging_params.t {
+        pairs.push(("t", t.clone()));
+    }
+    pairs
+}
+
+pub fn query_string(pairs: &[(&str, String)]) -> String {
+    form_urlencoded::Serializer::new(String::new()).extend_pairs(pairs).finish()
+}
+
+// Previous (from page 2 on) and next page URLs, keeping the other parameters of the page
+pub fn pager_urls(path: &str, pairs: &[(&str, String)], page: Option<i32>) -> (Option<String>, String) {
+    let page = page.unwrap_or(1);
+    let mut pairs: Vec<(&str, String)> = pairs.iter().filter(|(k, _)| *k != "p").cloned().collect();
+    let prev = if page > 1 {
+        pairs.push(("p", (page - 1).to_string()));
+        let url = format!("{}?{}", path, query_string(&pairs));
+        pairs.pop();
+        Some(url)
+    } else {
+        None
+    };
+    pairs.push(("p", (page + 1).to_string()));
+    (prev, format!("{}?{}", path, query_string(&pairs)))
+}
+
+// Where a markdown link goes in the text formats, None when the destination is unsafe
+pub fn markdown_link(instance: &str, linktype: LinkType, url: &str) -> Option<String> {
+    let url = match linktype {
+        LinkType::Email => format!("mailto:{}", url),
+        _ => url.to_string()
+    };
+    Some(url).filter(|u| is_safe_url(u)).map(|u| resolve_link(instance, &u))
+}
+
+// Lemmy links to its own /c/ and /u/ pages, served here under the instance,
+// other relative links like /pictrs/ images are files of the instance
+fn resolve_link(instance: &str, url: &str) -> String {
+    if Url::parse(url).is_ok() {
+        return url.to_string();
+    }
+    if url.starts_with("/c/") || url.starts_with("/u/") {
+        return format!("/{}{}", instance, url);
+    }
+    Url::parse(&format!("{}://{}/", config::get().upstream_scheme, instance))
+        .and_then(|base| base.join(url))
+        .map(String::from)
+        .unwrap_or_else(|_| url.to_string())
+}
+
+// Voting again with the same score removes the vote
+fn vote_markup(instance: &String, target: &str, id: i32, my_vote: Option<i32>, score: i32) -> Markup {
+    let voted = my_vote == Some(score);
+    html! {
+        form.v.va[voted] method="post" action={"/" (instance) "/" (target) "/" (id) "/vote"} {
+            input type="hidden" name="v" value=(if voted {0} else {score});
+            input type="submit" value=(if score > 0 {"˄"} else {"˅"}) title=(if voted {"Remove vote"} else if score > 0 {"Upvote"} else {"Downvote"});
+        }
+    }
+}
+
+fn private_message_markup(instance: &String, message: &PrivateMessageView, highlight: bool, now: &NaiveDateTime) -> Markup {
+    html! {
+        p.ch.h[highlight] id={"m" (message.id)} {
+            a.u href={"/" (instance) "/u/" (message.creator_name)} {(message.creator_name)}
+            " → "
+            a.u href={"/" (instance) "/u/" (message.recipient_name)} {(message.recipient_name)}
+            @if message.deleted {
+                span.b {"deleted"}
             }
+            " " (simple_duration(now, message.published))
         }
+        div {(mdstr_to_html(&message.content))}
     }
 }
 
-fn pagebar_markup(paging_params: Option<&PagingParams>) -> Markup {
+// Marks one notification as read, or the whole inbox without an id
+fn read_markup(instance: &String, tab: InboxTab, id: Option<i32>) -> Markup {
+    html! {
+        form.i method="post" action={"/" (instance) "/inbox/read"} {
+            input type="hidden" name="t" value=(tab.path());
+            @if let Some(id) = id {
+                input type="hidden" name="id" value=(id);
+            }
+            input type="submit" value=(if id.is_some() {"mark as read"} else {"mark all as read"});
+        }
+    }
+}
+
+fn save_markup(instance: &String, target: &str, id: i32, saved: bool) -> Markup {
+    html! {
+        form.i method="post" action={"/" (instance) "/" (target) "/" (id) "/save"} {
+            input type="hidden" name="s" value=(!saved);
+            input type="submit" value=(if saved {"unsave"} else {"save"});
+        }
+    }
+}
+
+// The listing type only applies to the front page, elsewhere it's carried along untouched
+fn pagebar_markup(paging_params: Option<&PagingParams>, listing: bool) -> Markup {
     html! {
         .pb {
             form {
@@ -486,33 +1379,48 @@ fn pagebar_markup(paging_params: Option<&PagingParams>) -> Markup {
                 //     input type="hidden" name="p" value=(page);
                 // }
                 (limit_size_markup(paging_params))
+                @if listing {
+                    (listing_type_markup(paging_params))
+                } @else {
+                    (default_listing_markup(paging_params))
+                }
                 input type="submit" value="Apply";
             }
 
-            div {
-                @if let Some(PagingParams {p: Some(page), ..}) = paging_params {
-                    @if page > &1 {
-                        form {
-                            (default_sort_markup(paging_params))
-                            input type="hidden" name="p" value=((page-1));
-                            (default_limit_markup(paging_params))
-                            input type="submit" value="Prev";
-                        }
-                        " " (page) " "
-                    }
-                    form {
-                        (default_sort_markup(paging_params))
-                        input type="hidden" name="p" value=((page+1));
-                        (default_limit_markup(paging_params))
-                        input type="submit" value="Next";
-                    }
-                } @else {
+            (pager_markup(paging_params))
+        }
+    }
+}
+
+// Prev and Next buttons, keeping the other parameters of the page
+fn pager_markup(paging_params: Option<&PagingParams>) -> Markup {
+    html! {
+        div {
+            @if let Some(PagingParams {p: Some(page), ..}) = paging_params {
+                @if page > &1 {
                     form {
                         (default_sort_markup(paging_params))
-                        input type="hidden" name="p" value=(2);
+                        input type="hidden" name="p" value=((page-1));
                         (default_limit_markup(paging_params))
-                        input type="submit" value="Next";
+                        (default_listing_markup(paging_params))
+                        input type="submit" value="Prev";
                     }
+                    " " (page) " "
+                }
+                form {
+                    (default_sort_markup(paging_params))
+                    input type="hidden" name="p" value=((page+1));
+                    (default_limit_markup(paging_params))
+                    (default_listing_markup(paging_params))
+                    input type="submit" value="Next";
+                }
+            } @else {
+                form {
+                    (default_sort_markup(paging_params))
+                    input type="hidden" name="p" value=(2);
+                    (default_limit_markup(paging_params))
+                    (default_listing_markup(paging_params))
+                    input type="submit" value="Next";
                 }
             }
         }
@@ -640,18 +1548,26 @@ fn limit_size_markup(paging_params: Option<&PagingParams>) -> Markup {
     }
 }
 
-fn default_sort_markup(paging_params: Option<&PagingParams>) -> Markup {
+fn listing_type_markup(paging_params: Option<&PagingParams>) -> Markup {
     html! {
-        @if let Some(PagingParams {s: Some(sort), ..}) = paging_params {
-            input type="hidden" name="s" value=((sort));
+        select name="t" {
+            @if let Some(PagingParams {t: Some(type_), ..}) = paging_params {
+                option selected?[type_==&"All".to_string()] value="All" {"All"}
+                option selected?[type_==&"Local".to_string()] value="Local" {"Local"}
+                option selected?[type_==&"Subscribed".to_string()] value="Subscribed" {"Subscribed"}
+            } @else {
+                option value="All" {"All"}
+                option value="Local" {"Local"}
+                option value="Subscribed" {"Subscribed"}
+            }
         }
     }
 }
 
-fn default_page_markup(paging_params: Option<&PagingParams>) -> Markup {
+fn default_sort_markup(paging_params: Option<&PagingParams>) -> Markup {
     html! {
-        @if let Some(PagingParams {p: Some(page), ..}) = paging_params {
-            input type="hidden" name="p" value=((page));
+        @if let Some(PagingParams {s: Some(sort), ..}) = paging_params {
+            input type="hidden" name="s" value=((sort));
         }
     }
 }
@@ -664,6 +1580,14 @@ fn default_limit_markup(paging_params: Option<&PagingParams>) -> Markup {
     }
 }
 
+fn default_listing_markup(paging_params: Option<&PagingParams>) -> Markup {
+    html! {
+        @if let Some(PagingParams {t: Some(type_), ..}) = paging_params {
+            input type="hidden" name="t" value=((type_));
+        }
+    }
+}
+
 fn default_query_markup(search_params: Option<&SearchParams>) -> Markup {
     html! {
         @if let Some(SearchParams {q: Some(query), ..}) = search_params {
@@ -689,10 +1613,10 @@ fn default_community_markup(search_params: Option<&SearchParams>) -> Markup {
 }
 
 fn ends_with_any(s: String, suffixes: &'static [&'static str]) -> bool {
-    return suffixes.iter().any(|&suffix| s.to_lowercase().ends_with(suffix));
+    suffixes.iter().any(|&suffix| s.to_lowercase().ends_with(suffix))
 }
 
-fn simple_duration(now: &NaiveDateTime, record: NaiveDateTime) -> String {
+pub fn simple_duration(now: &NaiveDateTime, record: NaiveDateTime) -> String {
     let seconds = now.signed_duration_since(record).num_seconds();
     if seconds < 60 {
         format!("{}s", seconds)
@@ -714,9 +1638,14 @@ fn simple_duration(now: &NaiveDateTime, record: NaiveDateTime) -> String {
     }
 }
 
+// API timestamps are UTC
+pub fn rfc3339(time: NaiveDateTime) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
 // Custom markdown to HTML
-fn mdstr_to_html(text: &str) -> Markup {
-    let parser = ImageSwapper::new(Parser::new(text));
+pub fn mdstr_to_html(text: &str) -> Markup {
+    let parser = HtmlSanitizer::new(ImageSwapper::new(Parser::new(text)));
     let mut html_output = String::new();
     pchtml::push_html(&mut html_output, parser);
     PreEscaped(html_output)
@@ -728,7 +1657,7 @@ struct ImageSwapper<'a, I> {
 impl<'a, I> ImageSwapper<'a, I> {
     fn new(iter: I) -> Self {
         ImageSwapper {
-            iter: iter,
+            iter,
             image_title: None,
         }
     }
@@ -762,4 +1691,181 @@ impl<'a, I> Iterator for ImageSwapper<'a, I>
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// Remote content is untrusted, raw HTML is shown as text and links are limited to safe schemes
+struct HtmlSanitizer<I> {
+    iter: I,
+    links: Vec<bool>, // Whether each open link was emitted, images swapped to links can nest
+}
+impl<I> HtmlSanitizer<I> {
+    fn new(iter: I) -> Self {
+        HtmlSanitizer {
+            iter,
+            links: Vec::new(),
+        }
+    }
+}
+impl<'a, I> Iterator for HtmlSanitizer<I>
+    where I: ::std::iter::Iterator<Item = Event<'a>>
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            return Some(match self.iter.next()? {
+                Event::Html(html) => Event::Text(html),
+                Event::Start(Tag::Link(linktype, url, title)) => match link_tag(linktype, &url, &title) {
+                    Some(tag) => {
+                        self.links.push(true);
+                        Event::Html(tag.into())
+                    }
+                    None => {
+                        self.links.push(false);
+                        continue;
+                    }
+                },
+                Event::End(Tag::Link(..)) => match self.links.pop() {
+                    Some(true) => Event::Html("</a>".into()),
+                    _ => continue
+                },
+                event => event
+            });
+        }
+    }
+}
+
+// Opening anchor for a markdown link, None when the destination is unsafe
+fn link_tag(linktype: LinkType, url: &str, title: &str) -> Option<String> {
+    let href = match linktype {
+        LinkType::Email => format!("mailto:{}", url),
+        _ => url.to_string()
+    };
+    if !is_safe_url(&href) {
+        return None;
+    }
+
+    let mut tag = String::from("<a href=\"");
+    escape_href(&mut tag, &href).ok()?;
+    tag.push('"');
+    if !title.is_empty() {
+        tag.push_str(" title=\"");
+        escape_html(&mut tag, title).ok()?;
+        tag.push('"');
+    }
+    if href.contains("//") {
+        tag.push_str(" rel=\"");
+        tag.push_str(OUTBOUND_REL);
+        tag.push('"');
+    }
+    tag.push('>');
+    Some(tag)
+}
+
+// Relative URLs and whitelisted schemes only, browsers ignore whitespace and control characters in schemes
+pub fn is_safe_url(url: &str) -> bool {
+    let cleaned: String = url.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    match cleaned.find([':', '/', '?', '#']) {
+        Some(i) if cleaned[i..].starts_with(':') => SAFE_SCHEMES.contains(&&cleaned[..i]),
+        _ => true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Values of every href attribute in the rendered HTML
+    fn hrefs(html: &str) -> Vec<String> {
+        html.split("href=\"").skip(1)
+            .map(|s| s[..s.find('"').unwrap()].to_string())
+            .collect()
+    }
+
+    fn render(md: &str) -> String {
+        mdstr_to_html(md).into_string()
+    }
+
+    const XSS_CORPUS: &[&str] = &[
+        "<script>alert(1)</script>",
+        "<img src=x onerror=alert(1)>",
+        "<svg/onload=alert(1)>",
+        "<iframe src=\"https://evil.example\"></iframe>",
+        "<a href=\"javascript:alert(1)\">click</a>",
+        "text <b onmouseover=alert(1)>inline</b> html",
+        "<details open ontoggle=alert(1)>",
+        "[click](javascript:alert(1))",
+        "[click](JaVaScRiPt:alert(1))",
+        "[click](&#x6A;avascript:alert(1))",
+        "[click](java&#x09;script:alert(1))",
+        "[click](<java script:alert(1)>)",
+        "[click](vbscript:msgbox(1))",
+        "[click](data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==)",
+        "<javascript:alert(1)>",
+        "![img](javascript:alert(1))",
+        "[![img](https://example.com/a.png)](javascript:alert(1))",
+        "[click][ref]\n\n[ref]: javascript:alert(1)",
+        "[click](https://example.com \"title\\\" onmouseover=\\\"alert(1)\")",
+        "[click](https://example.com/\"onmouseover=\"alert(1))",
+    ];
+
+    #[test]
+    fn xss_corpus_is_neutralized() {
+        for md in XSS_CORPUS {
+            let html = render(md);
+            let lower = html.to_lowercase();
+            for tag in &["<script", "<img", "<svg", "<iframe", "<b ", "<details"] {
+                assert!(!lower.contains(tag), "{} rendered {}", md, html);
+            }
+            assert!(!lower.contains("onmouseover=\""), "{} rendered {}", md, html);
+            for href in hrefs(&html) {
+                assert!(is_safe_url(&href), "{} rendered unsafe href {}", md, href);
+            }
+        }
+    }
+
+    #[test]
+    fn raw_html_is_shown_as_text() {
+        assert_eq!(render("<script>alert(1)</script>"), "&lt;script&gt;alert(1)&lt;/script&gt;");
+        assert_eq!(render("a <b>b</b>"), "<p>a &lt;b&gt;b&lt;/b&gt;</p>\n");
+    }
+
+    #[test]
+    fn unsafe_links_keep_their_text() {
+        assert_eq!(render("[click](javascript:alert(1))"), "<p>click</p>\n");
+        assert_eq!(render("[![img](https://example.com/a.png)](javascript:alert(1))"),
+            "<p><a href=\"https://example.com/a.png\" rel=\"nofollow noopener ugc\">https://example.com/a.pngimg</a></p>\n");
+    }
+
+    #[test]
+    fn outbound_links_are_marked() {
+        assert_eq!(render("[lemmy](https://join-lemmy.org \"Join\")"),
+            "<p><a href=\"https://join-lemmy.org\" title=\"Join\" rel=\"nofollow noopener ugc\">lemmy</a></p>\n");
+        assert_eq!(render("<admin@example.com>"),
+            "<p><a href=\"mailto:admin@example.com\">admin@example.com</a></p>\n");
+        assert_eq!(render("[post](/post/1)"), "<p><a href=\"/post/1\">post</a></p>\n");
+    }
+
+    #[test]
+    fn safe_url_schemes() {
+        assert!(is_safe_url("https://example.com/a:b"));
+        assert!(is_safe_url("/c/rust"));
+        assert!(is_safe_url("page?next=javascript:alert(1)"));
+        assert!(!is_safe_url(" javascript:alert(1)"));
+        assert!(!is_safe_url("java\tscript:alert(1)"));
+        assert!(!is_safe_url("DATA:text/html,x"));
+        assert!(!is_safe_url("vbscript:msgbox(1)"));
+    }
+
+    #[test]
+    fn admins_moderate_every_community() {
+        let session = |role| Session { username: "me".to_string(), jwt: "tok".to_string(), role };
+        let moderators = [CommunityModeratorView { community_name: "rust".to_string(), user_id: 3, user_name: "mod".to_string() }];
+        assert!(is_moderator(Some(&session(Role::Admin)), &moderators));
+        assert!(!is_moderator(Some(&session(Role::Moderator)), &moderators));
+        assert!(!is_moderator(None, &moderators));
+    }
+}
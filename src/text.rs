@@ -0,0 +1,530 @@
+/*
+Plain text pages
+
+An alternative to the HTML templates for terminal browsers and curl,
+selected with ?fmt=txt or an Accept header preferring text/plain over text/html.
+Text is wrapped to 72 columns, links are numbered in brackets and listed with their URL at the end of the page.
+*/
+
+use chrono::naive::NaiveDateTime;
+use actix_web::{HttpRequest, http::header};
+use pulldown_cmark::{Parser, Event, Tag};
+use crate::routes::base_url;
+use crate::lemmy_api::{PostView, PostDetail, CommentView, CommunityView, CommunityList, CommunityDetail, UserView, UserDetail,
+    PagingParams, SearchParams, SearchResponse, Modlog, ModlogTarget};
+use crate::templates::{comment_tree, comment_context, is_safe_url, markdown_link, pager_urls, paging_pairs, simple_duration};
+
+const WIDTH: usize = 72;
+const INDENT: &str = "| ";
+
+// Where the links of a text page point
+pub struct TextRequest {
+    base: String,   // Scheme and host, links are absolute so they can be copied from a terminal
+    fmt: bool       // Asked for with ?fmt=txt, which links to other pages have to keep
+}
+
+// Some when the request asks for text, the Accept header is enough for terminal browsers
+pub fn wants_text(req: &HttpRequest) -> Option<TextRequest> {
+    let fmt = req.query_string().split('&').any(|p| p == "fmt=txt");
+    let accept = req.headers().get(header::ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .is_some_and(|a| quality(a, "text/plain") > quality(a, "text/html"));
+    if fmt || accept {
+        Some(TextRequest { base: base_url(req), fmt })
+    } else {
+        None
+    }
+}
+
+// Quality the Accept header gives a type, the most specific matching range wins
+fn quality(accept: &str, mime: &str) -> f32 {
+    let wildcard = format!("{}/*", mime.split('/').next().unwrap_or_default());
+    let mut best = (0, 0.0);
+    for range in accept.to_lowercase().split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let specificity = match parts.next() {
+            Some(name) if name == mime => 3,
+            Some(name) if name == wildcard => 2,
+            Some("*/*") => 1,
+            _ => continue
+        };
+        let q = parts.find_map(|p| p.strip_prefix("q=")).and_then(|q| q.parse().ok()).unwrap_or(1.0);
+        if specificity > best.0 {
+            best = (specificity, q);
+        }
+    }
+    best.1
+}
+
+pub fn post_list_page(req: &TextRequest, instance: &str, community: Option<&String>, posts: &[PostView], now: &NaiveDateTime,
+    paging_params: &PagingParams) -> String {
+    let mut text = Text::new(req);
+    let path = match community {
+        Some(c) => {
+            text.heading(&format!("/c/{} on {}", c, instance));
+            let info = text.link(&format!("/{}/c/{}/info", instance, c));
+            text.wrap(&format!("More info {}", info), "");
+            text.blank();
+            format!("/{}/c/{}", instance, c)
+        },
+        None => {
+            text.heading(instance);
+            format!("/{}", instance)
+        }
+    };
+    for post in posts {
+        text.post(instance, post, now);
+    }
+    text.pager(&path, &paging_pairs(paging_params), paging_params.p);
+    text.finish()
+}
+
+pub fn post_page(req: &TextRequest, instance: &str, post_detail: PostDetail, now: &NaiveDateTime) -> String {
+    let mut text = Text::new(req);
+    let post = &post_detail.post;
+    text.post(instance, post, now);
+    if let Some(ref body) = post.body {
+        text.markdown(instance, body, "");
+    }
+    text.rule();
+    text.comment_tree(instance, &post_detail.comments, Some(post.creator_id), None, None, now);
+    text.finish()
+}
+
+pub fn comment_page(req: &TextRequest, instance: &str, comment: CommentView, post_detail: PostDetail, now: &NaiveDateTime) -> String {
+    let mut text = Text::new(req);
+    let post = &post_detail.post;
+    text.post(instance, post, now);
+    if let Some(ref body) = post.body {
+        text.markdown(instance, body, "");
+    }
+    text.rule();
+    let (comments, root_id) = comment_context(&comment, post_detail.comments);
+    text.comment_tree(instance, &comments, Some(post.creator_id), root_id, Some(comment.id), now);
+    text.finish()
+}
+
+pub fn user_page(req: &TextRequest, instance: &str, user: UserDetail, saved: bool, now: &NaiveDateTime, paging_params: &PagingParams) -> String {
+    let mut text = Text::new(req);
+    let path = if saved {
+        text.heading(&format!("Saved by /u/{}", user.user.name));
+        format!("/{}/u/{}/saved", instance, user.user.name)
+    } else {
+        text.heading(&format!("/u/{}", user.user.name));
+        text.wrap(&format!("{} post points, {} comment points, joined {} ago",
+            user.user.post_score, user.user.comment_score, simple_duration(now, user.user.published)), "");
+        text.blank();
+        if let Some(ref bio) = user.user.bio {
+            text.markdown(instance, bio, "");
+        }
+        format!("/{}/u/{}", instance, user.user.name)
+    };
+    for post in &user.posts {
+        text.post(instance, post, now);
+    }
+    for comment in &user.comments {
+        text.comment(instance, comment, None, None, "", now);
+    }
+    text.pager(&path, &paging_pairs(paging_params), paging_params.p);
+    text.finish()
+}
+
+pub fn communities_page(req: &TextRequest, instance: &str, community_list: CommunityList, paging_params: &PagingParams) -> String {
+    let mut text = Text::new(req);
+    text.heading(&format!("Communities on {}", instance));
+    for community in &community_list.communities {
+        text.community(instance, community);
+    }
+    text.pager(&format!("/{}/communities", instance), &paging_pairs(paging_params), paging_params.p);
+    text.finish()
+}
+
+pub fn community_info_page(req: &TextRequest, instance: &str, community_detail: CommunityDetail) -> String {
+    let mut text = Text::new(req);
+    let community = &community_detail.community;
+    text.heading(&community.title);
+    let posts = text.link(&format!("/{}/c/{}", instance, community.name));
+    text.wrap(&format!("/c/{} {}", community.name, posts), "");
+    text.wrap(&format!("{} subscribers, {} posts, {} comments", community.number_of_subscribers,
+        community.number_of_posts, community.number_of_comments), "");
+    text.blank();
+    if let Some(ref description) = community.description {
+        text.markdown(instance, description, "");
+    }
+    if !community_detail.moderators.is_empty() {
+        text.heading("Moderators");
+        for moderator in &community_detail.moderators {
+            let link = text.link(&format!("/{}/u/{}", instance, moderator.user_name));
+            text.wrap(&format!("{} {}", moderator.user_name, link), "");
+        }
+        text.blank();
+    }
+    text.finish()
+}
+
+pub fn search_page(req: &TextRequest, instance: &str, search_res: Option<SearchResponse>, search_params: &SearchParams, now: &NaiveDateTime) -> String {
+    let mut text = Text::new(req);
+    let results = match search_res {
+        Some(results) => results,
+        None => {
+            text.wrap("Empty search, add ?q= to the URL", "");
+            return text.finish();
+        }
+    };
+    text.heading(&format!("Search for {}", search_params.q.as_deref().unwrap_or_default()));
+    for community in &results.communities {
+        text.community(instance, community);
+    }
+    for user in &results.users {
+        text.user(instance, user);
+    }
+    for post in &results.posts {
+        text.post(instance, post, now);
+    }
+    for comment in &results.comments {
+        text.comment(instance, comment, None, None, "", now);
+    }
+    let mut pairs = paging_pairs(&search_params.to_paging_params());
+    for (key, value) in &[("q", &search_params.q), ("t", &search_params.t), ("c", &search_params.c)] {
+        if let Some(value) = value {
+            pairs.push((key, value.clone()));
+        }
+    }
+    text.pager(&format!("/{}/search", instance), &pairs, search_params.p);
+    text.finish()
+}
+
+pub fn modlog_page(req: &TextRequest, instance: &str, modlog: Modlog, community: Option<&String>, now: &NaiveDateTime,
+    paging_params: &PagingParams) -> String {
+    let mut text = Text::new(req);
+    let path = match community {
+        Some(c) => {
+            text.heading(&format!("Modlog of /c/{}", c));
+            format!("/{}/c/{}/modlog", instance, c)
+        },
+        None => {
+            text.heading(&format!("Modlog of {}", instance));
+            format!("/{}/modlog", instance)
+        }
+    };
+    for entry in &modlog.entries {
+        let (target, path) = match entry.target {
+            ModlogTarget::Post { id, ref name } => (name, format!("/{}/post/{}", instance, id)),
+            ModlogTarget::Comment { id, post_id, ref content } => (content, format!("/{}/post/{}/comment/{}", instance, post_id, id)),
+            ModlogTarget::User { ref name } => (name, format!("/{}/u/{}", instance, name))
+        };
+        let link = text.link(&path);
+        let mut line = format!("{} ago: ", simple_duration(now, entry.when));
+        if let Some(ref name) = entry.moderator_name {
+            line.push_str(&format!("{} ", name));
+        }
+        line.push_str(&format!("{}: {} {}", entry.action.label().to_lowercase(), target, link));
+        if let Some(ref name) = entry.community_name {
+            line.push_str(&format!(" in /c/{}", name));
+        }
+        if let Some(ref reason) = entry.reason {
+            line.push_str(&format!(", reason: {}", reason));
+        }
+        text.wrap(&line, "");
+        text.blank();
+    }
+    text.pager(&path, &paging_pairs(paging_params), paging_params.p);
+    text.finish()
+}
+
+struct Text<'a> {
+    req: &'a TextRequest,
+    out: String,
+    links: Vec<String>
+}
+
+impl<'a> Text<'a> {
+    fn new(req: &'a TextRequest) -> Self {
+        Text {
+            req,
+            out: String::new(),
+            links: Vec::new()
+        }
+    }
+
+    // Reference to a link, a URL linked twice keeps its number
+    fn link(&mut self, url: &str) -> String {
+        let url = match (url.starts_with('/'), self.req.fmt) {
+            (true, true) => format!("{}{}{}fmt=txt", self.req.base, url, if url.contains('?') {'&'} else {'?'}),
+            (true, false) => format!("{}{}", self.req.base, url),
+            _ => url.to_string()
+        };
+        let url = printable(&url);
+        let number = match self.links.iter().position(|l| *l == url) {
+            Some(i) => i + 1,
+            None => {
+                self.links.push(url);
+                self.links.len()
+            }
+        };
+        format!("[{}]", number)
+    }
+
+    fn finish(mut self) -> String {
+        if !self.links.is_empty() {
+            self.rule();
+            for (i, url) in self.links.iter().enumerate() {
+                self.out.push_str(&format!("[{}] {}\n", i + 1, url));
+            }
+        }
+        self.out
+    }
+
+    fn blank(&mut self) {
+        self.out.push('\n');
+    }
+
+    // Blank line between blocks, keeping the bar of nested comments
+    fn separator(&mut self, indent: &str) {
+        self.out.push_str(indent.trim_end());
+        self.out.push('\n');
+    }
+
+    fn rule(&mut self) {
+        self.out.push_str(&"-".repeat(WIDTH));
+        self.out.push_str("\n\n");
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.wrap(text, "");
+        self.out.push_str(&"=".repeat(text.chars().count().min(WIDTH)));
+        self.out.push_str("\n\n");
+    }
+
+    fn wrap(&mut self, text: &str, indent: &str) {
+        self.wrap_hanging(text, indent, indent);
+    }
+
+    // Words too long for a line, like URLs, get a line of their own rather than being cut
+    fn wrap_hanging(&mut self, text: &str, first: &str, rest: &str) {
+        let text = printable(text);
+        let mut line = first.to_string();
+        let mut empty = true;
+        for word in text.split_whitespace() {
+            let len = line.chars().count();
+            if !empty && len + 1 + word.chars().count() > WIDTH {
+                self.out.push_str(line.trim_end());
+                self.out.push('\n');
+                line = rest.to_string();
+                empty = true;
+            }
+            if !empty {
+                line.push(' ');
+            }
+            line.push_str(word);
+            empty = false;
+        }
+        if !empty {
+            self.out.push_str(line.trim_end());
+            self.out.push('\n');
+        }
+    }
+
+    fn post(&mut self, instance: &str, post: &PostView, now: &NaiveDateTime) {
+        let link = self.link(&format!("/{}/post/{}", instance, post.id));
+        let pin = if post.stickied { "[pinned] " } else { "" };
+        self.wrap(&format!("{}{} {}", pin, post.name, link), "");
+        let creator = self.link(&format!("/{}/u/{}", instance, post.creator_name));
+        let community = self.link(&format!("/{}/c/{}", instance, post.community_name));
+        let mut meta = format!("{} points, {} comments, by {} {} to /c/{} {}, {} ago", post.score, post.number_of_comments,
+            post.creator_name, creator, post.community_name, community, simple_duration(now, post.published));
+        for (set, badge) in &[(post.deleted, "deleted"), (post.removed, "removed"), (post.locked, "locked"), (post.nsfw, "nsfw")] {
+            if *set {
+                meta.push_str(&format!(", {}", badge));
+            }
+        }
+        self.wrap(&meta, "  ");
+        if let Some(url) = post.url.as_ref().filter(|u| is_safe_url(u)) {
+            let link = self.link(url);
+            self.wrap(&format!("link {}", link), "  ");
+        }
+        self.blank();
+    }
+
+    // Same highlighting as the HTML comment header, the creator of the post is marked too
+    fn comment(&mut self, instance: &str, comment: &CommentView, post_creator_id: Option<i32>, highlight_id: Option<i32>,
+        indent: &str, now: &NaiveDateTime) {
+        let link = self.link(&format!("/{}/post/{}/comment/{}", instance, comment.post_id, comment.id));
+        let mut header = String::new();
+        if Some(comment.id) == highlight_id {
+            header.push_str(">> ");
+        }
+        header.push_str(&comment.creator_name);
+        if post_creator_id == Some(comment.creator_id) {
+            header.push_str(" (creator)");
+        }
+        header.push_str(&format!(", {} points, {} ago", comment.score, simple_duration(now, comment.published)));
+        if comment.removed {
+            header.push_str(", removed");
+        }
+        header.push_str(&format!(" {}", link));
+        self.wrap(&header, indent);
+        self.markdown(instance, &comment.content, indent);
+    }
+
+    // The tree is flattened first so links are numbered in reading order
+    fn comment_tree(&mut self, instance: &str, comments: &[CommentView], post_creator_id: Option<i32>, parent_id: Option<i32>,
+        highlight_id: Option<i32>, now: &NaiveDateTime) {
+        let trees = comment_tree(comments, parent_id, 0, &mut |comment, depth, children: Vec<Vec<(&CommentView, i32)>>| {
+            let mut thread = vec![(comment, depth)];
+            thread.extend(children.into_iter().flatten());
+            thread
+        });
+        for (comment, depth) in trees.into_iter().flatten() {
+            self.comment(instance, comment, post_creator_id, highlight_id, &INDENT.repeat(depth as usize), now);
+        }
+    }
+
+    fn community(&mut self, instance: &str, community: &CommunityView) {
+        let link = self.link(&format!("/{}/c/{}", instance, community.name));
+        self.wrap(&format!("{} {} /c/{}, {} subscribers, {} posts", community.title, link, community.name,
+            community.number_of_subscribers, community.number_of_posts), "");
+        self.blank();
+    }
+
+    fn user(&mut self, instance: &str, user: &UserView) {
+        let link = self.link(&format!("/{}/u/{}", instance, user.name));
+        self.wrap(&format!("{} {}, {} post points, {} comment points", user.name, link, user.post_score, user.comment_score), "");
+        self.blank();
+    }
+
+    // Prev and Next links, keeping the other parameters of the page
+    fn pager(&mut self, path: &str, pairs: &[(&str, String)], page: Option<i32>) {
+        let (prev, next) = pager_urls(path, pairs, page);
+        let mut line = format!("Page {}", page.unwrap_or(1));
+        if let Some(prev) = prev {
+            let link = self.link(&prev);
+            line.push_str(&format!(", previous {}", link));
+        }
+        let link = self.link(&next);
+        line.push_str(&format!(", next {}", link));
+        self.wrap(&line, "");
+        self.blank();
+    }
+
+    fn markdown(&mut self, instance: &str, text: &str, indent: &str) {
+        let mut line = String::new();
+        let mut quote = String::new();
+        let mut lists: Vec<Option<u64>> = Vec::new();    // Next number of each ordered list
+        let mut item: Option<String> = None;            // Marker of the item about to be written
+        let mut code = false;
+        let mut links: Vec<Option<String>> = Vec::new();
+        for event in Parser::new(text) {
+            match event {
+                Event::Start(Tag::Paragraph) | Event::Start(Tag::Heading(_)) | Event::Start(Tag::List(_)) => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    if let Event::Start(Tag::List(start)) = event {
+                        lists.push(start);
+                    }
+                },
+                Event::End(Tag::Paragraph) | Event::End(Tag::Heading(_)) => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    if lists.is_empty() {
+                        self.separator(indent);
+                    }
+                },
+                Event::End(Tag::List(_)) => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    lists.pop();
+                    if lists.is_empty() {
+                        self.separator(indent);
+                    }
+                },
+                Event::Start(Tag::Item) => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    item = Some(match lists.last_mut() {
+                        Some(Some(n)) => {
+                            *n += 1;
+                            format!("{}. ", *n - 1)
+                        },
+                        _ => "* ".to_string()
+                    });
+                },
+                Event::End(Tag::Item) => self.markdown_line(&mut line, indent, &quote, &lists, &mut item),
+                Event::Start(Tag::BlockQuote) => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    quote.push_str("> ");
+                },
+                Event::End(Tag::BlockQuote) => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    quote.truncate(quote.len() - 2);
+                },
+                Event::Start(Tag::CodeBlock(_)) => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    code = true;
+                },
+                Event::End(Tag::CodeBlock(_)) => {
+                    code = false;
+                    self.separator(indent);
+                },
+                Event::Text(text) if code => {
+                    for l in text.lines() {
+                        self.out.push_str(&format!("{}{}    {}\n", indent, quote, printable(l)).replace('\t', "    "));
+                    }
+                },
+                Event::Start(Tag::Link(linktype, url, _)) | Event::Start(Tag::Image(linktype, url, _)) => {
+                    links.push(markdown_link(instance, linktype, &url));
+                },
+                Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                    if let Some(Some(url)) = links.pop() {
+                        let link = self.link(&url);
+                        line.push_str(&format!(" {}", link));
+                    }
+                },
+                Event::Code(text) => line.push_str(&format!("`{}`", text)),
+                Event::Text(text) | Event::Html(text) => line.push_str(&text),
+                Event::SoftBreak => line.push(' '),
+                Event::HardBreak => self.markdown_line(&mut line, indent, &quote, &lists, &mut item),
+                Event::Rule => {
+                    self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+                    self.wrap("----------", indent);
+                    self.separator(indent);
+                },
+                _ => ()
+            }
+        }
+        self.markdown_line(&mut line, indent, &quote, &lists, &mut item);
+    }
+
+    // Writes the pending text of a markdown block, list items hang under their marker
+    fn markdown_line(&mut self, line: &mut String, indent: &str, quote: &str, lists: &[Option<u64>], item: &mut Option<String>) {
+        if line.trim().is_empty() {
+            line.clear();
+            return;
+        }
+        let nesting = "  ".repeat(lists.len().saturating_sub(1));
+        let prefix = format!("{}{}{}", indent, quote, nesting);
+        match item.take() {
+            Some(marker) => self.wrap_hanging(line, &format!("{}{}", prefix, marker), &format!("{}{}", prefix, " ".repeat(marker.len()))),
+            None if lists.is_empty() => self.wrap(line, &prefix),
+            None => self.wrap(line, &format!("{}  ", prefix))
+        }
+        line.clear();
+    }
+}
+
+// Remote text reaches terminals as is, escape sequences and carriage returns could rewrite what is shown
+fn printable(text: &str) -> String {
+    text.chars().filter(|&c| !c.is_control() || c == '\t' || c == '\n').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_characters_never_reach_the_terminal() {
+        let req = TextRequest { base: "https://lite.example.com".to_string(), fmt: false };
+        let mut text = Text::new(&req);
+        text.heading("Title\u{1b}[2J");
+        text.markdown("lemmy.ml", "clear\u{1b}[2J\rscreen\u{9b}2J\n\n    code\u{1b}[2J\r", "");
+        let out = text.finish();
+        assert!(!out.chars().any(|c| c.is_control() && c != '\n'), "{:?}", out);
+        assert!(out.contains("Title[2J") && out.contains("clear[2J screen2J") && out.contains("code[2J"));
+    }
+}